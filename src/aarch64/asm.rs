@@ -5,6 +5,8 @@ use core::arch::asm;
 use aarch64_cpu::{asm::barrier, registers::*};
 use memory_addr::{PhysAddr, VirtAddr};
 
+use crate::IrqFlags;
+
 /// Allows the current CPU to respond to interrupts.
 ///
 /// In AArch64, it unmasks IRQs by clearing the I bit in the `DAIF` register.
@@ -29,6 +31,21 @@ pub fn irqs_enabled() -> bool {
     !DAIF.matches_all(DAIF::I::Masked)
 }
 
+/// Makes the current CPU to ignore interrupts, and returns the previous value
+/// of the `DAIF` register.
+#[inline]
+pub fn local_irq_save() -> IrqFlags {
+    let flags = DAIF.get();
+    disable_irqs();
+    IrqFlags(flags as usize)
+}
+
+/// Restores the `DAIF` register saved by [`local_irq_save`].
+#[inline]
+pub fn local_irq_restore(flags: IrqFlags) {
+    DAIF.set(flags.0 as u64);
+}
+
 /// Relaxes the current CPU and waits for interrupts.
 ///
 /// It must be called with interrupts enabled, otherwise it will never return.
@@ -196,10 +213,16 @@ pub unsafe fn write_thread_pointer(tpidr_el0: usize) {
 }
 
 /// Enable FP/SIMD instructions by setting the `FPEN` field in `CPACR_EL1`.
+///
+/// With the `sve` feature, it also configures the vector lengths of SVE and
+/// SME if they are supported, and leaves them disabled for user space until
+/// their first use.
 #[inline]
 pub fn enable_fp() {
-    CPACR_EL1.write(CPACR_EL1::FPEN::TrapNothing);
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapNothing);
     barrier::isb(barrier::SY);
+    #[cfg(feature = "sve")]
+    super::sve::init();
 }
 
 /// Returns the frequency of the system counter.
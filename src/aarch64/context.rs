@@ -72,21 +72,172 @@ pub struct FpState {
     pub fpcr: u32,
     /// Floating-point Status Register (FPSR)
     pub fpsr: u32,
+    /// SVE and SME states, whose Z registers hold V0..V31 in their lowest
+    /// 128 bits if saved.
+    #[cfg(feature = "sve")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sve")))]
+    pub sve: super::SveState,
 }
 
 #[cfg(feature = "fp-simd")]
 impl FpState {
     /// Saves the current FP/SIMD states from CPU to this structure.
     pub fn save(&mut self) {
+        #[cfg(feature = "sve")]
+        if let Some(vl) = self.sve.save() {
+            for (i, reg) in self.regs.iter_mut().enumerate() {
+                let z = &self.sve.z[i * vl..i * vl + 16];
+                *reg = u128::from_le_bytes(z.try_into().unwrap());
+            }
+            let (fpcr, fpsr): (u64, u64);
+            unsafe {
+                core::arch::asm!(
+                    ".arch_extension fp",
+                    "mrs {}, fpcr",
+                    "mrs {}, fpsr",
+                    out(reg) fpcr,
+                    out(reg) fpsr,
+                )
+            };
+            self.fpcr = fpcr as u32;
+            self.fpsr = fpsr as u32;
+            return;
+        }
         unsafe { fpstate_save(self) }
     }
 
     /// Restores the FP/SIMD states from this structure to CPU.
     pub fn restore(&self) {
+        #[cfg(feature = "sve")]
+        if self.sve.restore() {
+            let (fpcr, fpsr) = (self.fpcr as u64, self.fpsr as u64);
+            unsafe {
+                core::arch::asm!(
+                    ".arch_extension fp",
+                    "msr fpcr, {}",
+                    "msr fpsr, {}",
+                    in(reg) fpcr,
+                    in(reg) fpsr,
+                )
+            };
+            return;
+        }
         unsafe { fpstate_restore(self) }
     }
 }
 
+/// FP/SIMD states of the current task saved by the outermost kernel-mode
+/// FP/SIMD section on each CPU if SVE or SME is enabled for the task.
+#[cfg(feature = "sve")]
+#[percpu::def_percpu]
+static KERNEL_FPU_SVE_STATE: FpState = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
+
+/// FP/SIMD states saved by a kernel-mode FP/SIMD section.
+///
+/// It has the same layout as the beginning of [`FpState`], without the SVE and
+/// SME states, which take several kilobytes. They can only be enabled in the
+/// outermost section on a CPU, which saves them to a per-CPU buffer instead.
+#[cfg(feature = "fp-simd")]
+#[repr(C, align(16))]
+#[derive(Default)]
+pub(crate) struct KernelFpState {
+    regs: [u128; 32],
+    fpcr: u32,
+    fpsr: u32,
+    /// Whether the states are saved to the per-CPU buffer.
+    #[cfg(feature = "sve")]
+    sve_saved: bool,
+}
+
+#[cfg(feature = "fp-simd")]
+static_assertions::const_assert_eq!(
+    core::mem::offset_of!(KernelFpState, fpsr),
+    core::mem::offset_of!(FpState, fpsr)
+);
+
+#[cfg(feature = "fp-simd")]
+impl KernelFpState {
+    /// Saves the live FP/SIMD states and enables FP/SIMD instructions by
+    /// `CPACR_EL1.FPEN` for a kernel-mode FP/SIMD section.
+    ///
+    /// Returns whether the states are live, i.e., `CPACR_EL1.FPEN` does not
+    /// trap them.
+    pub(crate) fn kernel_fpu_begin(&mut self) -> bool {
+        use aarch64_cpu::{asm::barrier, registers::*};
+        let live = CPACR_EL1.matches_all(CPACR_EL1::FPEN::TrapNothing);
+        if !live {
+            CPACR_EL1.modify(CPACR_EL1::FPEN::TrapNothing);
+            barrier::isb(barrier::SY);
+            return false;
+        }
+        #[cfg(feature = "sve")]
+        if super::sve::enabled() {
+            unsafe { KERNEL_FPU_SVE_STATE.current_ref_mut_raw() }.save();
+            // The streaming mode of SME forbids most FP/SIMD instructions.
+            super::sve::park();
+            self.sve_saved = true;
+            return true;
+        }
+        unsafe { fpstate_save(self as *mut Self as *mut FpState) };
+        true
+    }
+
+    /// Restores the FP/SIMD states saved by [`Self::kernel_fpu_begin`], or
+    /// disables FP/SIMD instructions back if they are not live.
+    pub(crate) fn kernel_fpu_end(&self, live: bool) {
+        use aarch64_cpu::{asm::barrier, registers::*};
+        if !live {
+            CPACR_EL1.modify(CPACR_EL1::FPEN::TrapEl0El1);
+            barrier::isb(barrier::SY);
+            return;
+        }
+        #[cfg(feature = "sve")]
+        if self.sve_saved {
+            unsafe { KERNEL_FPU_SVE_STATE.current_ref_raw() }.restore();
+            return;
+        }
+        unsafe { fpstate_restore(self as *const Self as *const FpState) };
+    }
+}
+
+#[cfg(feature = "fp-lazy")]
+impl FpState {
+    /// Saves the current FP/SIMD states to this structure if they have been
+    /// used since the last switch, and disables FP/SIMD instructions by
+    /// `CPACR_EL1.FPEN` so that the states of `next` are restored on their
+    /// first use.
+    fn lazy_switch_to(&mut self, next: &Self) {
+        use aarch64_cpu::{asm::barrier, registers::*};
+        if CPACR_EL1.matches_all(CPACR_EL1::FPEN::TrapNothing) {
+            self.save();
+            #[cfg(feature = "sve")]
+            super::sve::park();
+            CPACR_EL1.modify(CPACR_EL1::FPEN::TrapEl0El1);
+            barrier::isb(barrier::SY);
+        }
+        crate::lazy_fp::set_current(next);
+    }
+}
+
+/// Enables FP/SIMD instructions and restores the FP/SIMD states of the current
+/// task on the trap caused by their first use since the task was switched to.
+///
+/// Returns `false` if FP/SIMD instructions are not disabled by
+/// `CPACR_EL1.FPEN`, i.e., the trap is not caused by lazy switching.
+#[cfg(all(feature = "fp-lazy", target_os = "none"))]
+pub(super) fn handle_fp_trap() -> bool {
+    use aarch64_cpu::registers::*;
+    if CPACR_EL1.matches_all(CPACR_EL1::FPEN::TrapNothing) {
+        return false;
+    }
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapNothing);
+    aarch64_cpu::asm::barrier::isb(aarch64_cpu::asm::barrier::SY);
+    if let Some(state) = unsafe { crate::lazy_fp::current::<FpState>() } {
+        state.restore();
+    }
+    true
+}
+
 /// Saved hardware states of a task.
 ///
 /// The context usually includes:
@@ -121,6 +272,9 @@ pub struct TaskContext {
     pub ttbr0_el1: memory_addr::PhysAddr,
     #[cfg(feature = "fp-simd")]
     pub fp_state: FpState,
+    /// Hardware breakpoints of the task.
+    #[cfg(feature = "hw-breakpoint")]
+    pub hw_breakpoints: super::debug::HwBreakpoints,
 }
 
 impl TaskContext {
@@ -158,16 +312,20 @@ impl TaskContext {
     /// It first saves the current task's context from CPU to this place, and then
     /// restores the next task's context from `next_ctx` to CPU.
     pub fn switch_to(&mut self, next_ctx: &Self) {
-        #[cfg(feature = "fp-simd")]
+        #[cfg(all(feature = "fp-simd", not(feature = "fp-lazy")))]
         {
             self.fp_state.save();
             next_ctx.fp_state.restore();
         }
+        #[cfg(feature = "fp-lazy")]
+        self.fp_state.lazy_switch_to(&next_ctx.fp_state);
         #[cfg(feature = "uspace")]
         if self.ttbr0_el1 != next_ctx.ttbr0_el1 {
             unsafe { crate::asm::write_user_page_table(next_ctx.ttbr0_el1) };
             crate::asm::flush_tlb(None); // currently flush the entire TLB
         }
+        #[cfg(feature = "hw-breakpoint")]
+        self.hw_breakpoints.switch_to(&next_ctx.hw_breakpoints);
         unsafe { context_switch(self, next_ctx) }
     }
 }
@@ -202,9 +360,11 @@ unsafe extern "C" fn context_switch(_current_task: &mut TaskContext, _next_task:
     )
 }
 
+/// Saves V0..V31, FPCR and FPSR to the beginning of `state`, which may also be
+/// a [`KernelFpState`].
 #[unsafe(naked)]
 #[cfg(feature = "fp-simd")]
-unsafe extern "C" fn fpstate_save(state: &mut FpState) {
+unsafe extern "C" fn fpstate_save(state: *mut FpState) {
     naked_asm!(
         ".arch armv8
         // save fp/neon context
@@ -226,17 +386,19 @@ unsafe extern "C" fn fpstate_save(state: &mut FpState) {
         stp     q26, q27, [x0, 26 * 16]
         stp     q28, q29, [x0, 28 * 16]
         stp     q30, q31, [x0, 30 * 16]
-        str     x9, [x0, 64 *  8]
-        str     x10, [x0, 65 * 8]
+        str     w9, [x0, 32 * 16]
+        str     w10, [x0, 32 * 16 + 4]
 
         isb
         ret"
     )
 }
 
+/// Restores V0..V31, FPCR and FPSR from the beginning of `state`, which may
+/// also be a [`KernelFpState`].
 #[unsafe(naked)]
 #[cfg(feature = "fp-simd")]
-unsafe extern "C" fn fpstate_restore(state: &FpState) {
+unsafe extern "C" fn fpstate_restore(state: *const FpState) {
     naked_asm!(
         ".arch armv8
         // restore fp/neon context
@@ -256,8 +418,8 @@ unsafe extern "C" fn fpstate_restore(state: &FpState) {
         ldp     q26, q27, [x0, 26 * 16]
         ldp     q28, q29, [x0, 28 * 16]
         ldp     q30, q31, [x0, 30 * 16]
-        ldr     x9, [x0, 64 * 8]
-        ldr     x10, [x0, 65 * 8]
+        ldr     w9, [x0, 32 * 16]
+        ldr     w10, [x0, 32 * 16 + 4]
         msr     fpcr, x9
         msr     fpsr, x10
 
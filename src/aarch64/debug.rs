@@ -0,0 +1,254 @@
+//! Hardware debugging support.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "hw-breakpoint")]
+use aarch64_cpu::registers::{Readable, ID_AA64DFR0_EL1};
+use aarch64_cpu::{
+    asm::barrier,
+    registers::{ReadWriteable, Writeable, MDSCR_EL1, OSLAR_EL1},
+};
+
+use super::TrapFrame;
+
+/// `SPSR_EL1.SS`: software step.
+const SPSR_SS: u64 = 1 << 21;
+/// `SPSR_EL1.D`: debug exception mask.
+const SPSR_D: u64 = 1 << 9;
+
+/// Whether single-stepping has ever been turned on. Until then, exception
+/// returns do not touch `MDSCR_EL1`, whose accesses may be trapped by a
+/// hypervisor.
+pub(super) static STEP_USED: AtomicBool = AtomicBool::new(false);
+
+/// Turns single-stepping on or off for the context in the trap frame.
+///
+/// When it is on, a [`SINGLE_STEP`](crate::trap::SINGLE_STEP) trap is taken
+/// after the context executes one instruction on return from the trap. It uses
+/// the software step state machine: `SPSR_EL1.SS` in the trap frame is copied
+/// to `MDSCR_EL1.SS` on exception returns once any context has been stepped. Stepping kernel contexts also
+/// enables kernel debug exceptions (`MDSCR_EL1.KDE`) and unmasks them in the
+/// trap frame.
+///
+/// Returns `false` if single-stepping is not supported on the architecture.
+pub fn set_single_step(tf: &mut TrapFrame, on: bool) -> bool {
+    if on {
+        // Debug exceptions are not generated while the OS lock is locked,
+        // which is the case after a cold reset.
+        OSLAR_EL1.write(OSLAR_EL1::OSLK::Unlocked);
+        if tf.spsr & 0b1111 != 0 {
+            // M[3:0] != EL0t, i.e., a kernel context
+            MDSCR_EL1.modify(MDSCR_EL1::KDE::AllDebugExceptionsEnabled);
+            tf.spsr &= !SPSR_D;
+        }
+        barrier::isb(barrier::SY);
+        STEP_USED.store(true, Ordering::Relaxed);
+        tf.spsr |= SPSR_SS;
+    } else {
+        tf.spsr &= !SPSR_SS;
+    }
+    true
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub use crate::hw_breakpoint::{HwBreakpoint, HwBreakpointKind, HwBreakpoints};
+
+/// Maximum number of breakpoint or watchpoint register pairs.
+#[cfg(feature = "hw-breakpoint")]
+const MAX_PAIRS: usize = 16;
+
+/// Number of hardware breakpoint slots. Slots `0..16` are breakpoint register
+/// pairs (`DBGBVR<n>_EL1` and `DBGBCR<n>_EL1`), and slots `16..32` are
+/// watchpoint register pairs (`DBGWVR<n>_EL1` and `DBGWCR<n>_EL1`). Only
+/// those implemented, as reported by `ID_AA64DFR0_EL1`, can be used.
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) const NUM_HW_SLOTS: usize = MAX_PAIRS * 2;
+
+/// `DBGBCR<n>_EL1` and `DBGWCR<n>_EL1`: enable.
+#[cfg(feature = "hw-breakpoint")]
+const DBGCR_E: u64 = 1 << 0;
+/// `DBGBCR<n>_EL1.PMC` and `DBGWCR<n>_EL1.PAC`: match at EL0 and EL1.
+#[cfg(feature = "hw-breakpoint")]
+const DBGCR_EL0_EL1: u64 = 0b11 << 1;
+/// `DBGBCR<n>_EL1.BAS`: match A64 instructions.
+#[cfg(feature = "hw-breakpoint")]
+const DBGBCR_BAS_A64: u64 = 0b1111 << 5;
+
+#[cfg(feature = "hw-breakpoint")]
+macro_rules! debug_reg_pairs {
+    ($($n:literal)*) => {
+        /// Reads the value and control registers of a breakpoint (`wp` is
+        /// `false`) or watchpoint (`wp` is `true`) register pair.
+        fn read_pair(wp: bool, n: usize) -> (u64, u64) {
+            let (value, ctrl): (u64, u64);
+            match (wp, n) {
+                $(
+                    (false, $n) => unsafe {
+                        core::arch::asm!(
+                            concat!("mrs {}, dbgbvr", $n, "_el1"),
+                            concat!("mrs {}, dbgbcr", $n, "_el1"),
+                            out(reg) value,
+                            out(reg) ctrl,
+                        )
+                    },
+                    (true, $n) => unsafe {
+                        core::arch::asm!(
+                            concat!("mrs {}, dbgwvr", $n, "_el1"),
+                            concat!("mrs {}, dbgwcr", $n, "_el1"),
+                            out(reg) value,
+                            out(reg) ctrl,
+                        )
+                    },
+                )*
+                _ => unreachable!(),
+            }
+            (value, ctrl)
+        }
+
+        /// Writes the value and control registers of a breakpoint (`wp` is
+        /// `false`) or watchpoint (`wp` is `true`) register pair.
+        fn write_pair(wp: bool, n: usize, value: u64, ctrl: u64) {
+            match (wp, n) {
+                $(
+                    (false, $n) => unsafe {
+                        core::arch::asm!(
+                            concat!("msr dbgbvr", $n, "_el1, {}"),
+                            concat!("msr dbgbcr", $n, "_el1, {}"),
+                            in(reg) value,
+                            in(reg) ctrl,
+                        )
+                    },
+                    (true, $n) => unsafe {
+                        core::arch::asm!(
+                            concat!("msr dbgwvr", $n, "_el1, {}"),
+                            concat!("msr dbgwcr", $n, "_el1, {}"),
+                            in(reg) value,
+                            in(reg) ctrl,
+                        )
+                    },
+                )*
+                _ => unreachable!(),
+            }
+        }
+    };
+}
+
+#[cfg(feature = "hw-breakpoint")]
+debug_reg_pairs!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+
+/// Returns the numbers of implemented breakpoint and watchpoint register
+/// pairs.
+#[cfg(feature = "hw-breakpoint")]
+fn num_pairs() -> (usize, usize) {
+    let dfr0 = ID_AA64DFR0_EL1.extract();
+    (
+        dfr0.read(ID_AA64DFR0_EL1::BRPs) as usize + 1,
+        dfr0.read(ID_AA64DFR0_EL1::WRPs) as usize + 1,
+    )
+}
+
+/// Returns the value and control register values of the breakpoint.
+#[cfg(feature = "hw-breakpoint")]
+fn encode(bp: &HwBreakpoint) -> Option<(u64, u64)> {
+    let addr = bp.addr as u64;
+    let lsc = match bp.kind {
+        HwBreakpointKind::Execute => {
+            let ctrl = DBGBCR_BAS_A64 | DBGCR_EL0_EL1 | DBGCR_E;
+            return addr.is_multiple_of(4).then_some((addr, ctrl));
+        }
+        HwBreakpointKind::Read => 0b01,
+        HwBreakpointKind::Write => 0b10,
+        HwBreakpointKind::ReadWrite => 0b11,
+    };
+    if !matches!(bp.len, 1 | 2 | 4 | 8) || !bp.addr.is_multiple_of(bp.len) {
+        return None;
+    }
+    // Byte address select within the aligned doubleword.
+    let bas = ((1 << bp.len) - 1) << (addr & 7);
+    Some((addr & !7, bas << 5 | lsc << 3 | DBGCR_EL0_EL1 | DBGCR_E))
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn hw_slot_supports(slot: usize, bp: &HwBreakpoint) -> bool {
+    let (brps, wrps) = num_pairs();
+    let supported = match bp.kind {
+        HwBreakpointKind::Execute => slot < brps,
+        _ => (MAX_PAIRS..MAX_PAIRS + wrps).contains(&slot),
+    };
+    supported && encode(bp).is_some()
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn install_hw_breakpoints(slots: &[Option<HwBreakpoint>; NUM_HW_SLOTS]) {
+    let (brps, wrps) = num_pairs();
+    let (bps, wps) = slots.split_at(MAX_PAIRS);
+    for (wp, pairs, num) in [(false, bps, brps), (true, wps, wrps)] {
+        for (n, bp) in pairs.iter().take(num).enumerate() {
+            let (value, ctrl) = bp.and_then(|bp| encode(&bp)).unwrap_or((0, 0));
+            write_pair(wp, n, value, ctrl);
+        }
+    }
+    if slots.iter().any(Option::is_some) {
+        OSLAR_EL1.write(OSLAR_EL1::OSLK::Unlocked);
+        MDSCR_EL1.modify(MDSCR_EL1::MDE::SET + MDSCR_EL1::KDE::AllDebugExceptionsEnabled);
+    } else {
+        MDSCR_EL1.modify(MDSCR_EL1::MDE::CLEAR);
+    }
+    barrier::isb(barrier::SY);
+}
+
+/// Reports the hardware breakpoints (`wp` is `false`) or watchpoints (`wp` is
+/// `true`) that `addr` hits.
+///
+/// Breakpoint and watchpoint exceptions are taken before the instruction is
+/// executed, so the registers that hit are disabled, and the instruction is
+/// single-stepped before they are enabled again by [`rearm_hw_breakpoints`].
+#[cfg(all(feature = "hw-breakpoint", target_os = "none"))]
+pub(super) fn handle_hw_breakpoints(tf: &mut TrapFrame, wp: bool, addr: usize) {
+    let (brps, wrps) = num_pairs();
+    let num = if wp { wrps } else { brps };
+    let mut hit = false;
+    for n in 0..num {
+        let (value, ctrl) = read_pair(wp, n);
+        let matched = if wp {
+            // Any byte of the doubleword may be accessed.
+            addr as u64 & !7 == value
+        } else {
+            addr as u64 == value
+        };
+        if ctrl & DBGCR_E == 0 || !matched {
+            continue;
+        }
+        hit = true;
+        write_pair(wp, n, value, ctrl & !DBGCR_E);
+        let slot = if wp { MAX_PAIRS + n } else { n };
+        if !handle_trap!(HW_BREAKPOINT, tf, slot, va!(addr)) {
+            debug!("Hardware breakpoint {} hit @ {:#x}", slot, tf.elr);
+        }
+    }
+    if hit {
+        set_single_step(tf, true);
+    }
+}
+
+/// Enables the registers disabled by [`handle_hw_breakpoints`] after the
+/// instruction is single-stepped.
+///
+/// Returns `false` if no register is disabled, i.e., the single-step trap is
+/// not for stepping over a hardware breakpoint.
+#[cfg(all(feature = "hw-breakpoint", target_os = "none"))]
+pub(super) fn rearm_hw_breakpoints() -> bool {
+    let (brps, wrps) = num_pairs();
+    let mut rearmed = false;
+    for (wp, num) in [(false, brps), (true, wrps)] {
+        for n in 0..num {
+            let (value, ctrl) = read_pair(wp, n);
+            // Unused registers are cleared entirely.
+            if ctrl != 0 && ctrl & DBGCR_E == 0 {
+                write_pair(wp, n, value, ctrl | DBGCR_E);
+                rearmed = true;
+            }
+        }
+    }
+    rearmed
+}
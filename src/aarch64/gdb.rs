@@ -0,0 +1,73 @@
+//! Mapping of aarch64 trap frames to the GDB register layout.
+
+use crate::TrapFrame;
+
+/// Number of registers in the `g` packet: `x0` to `x30`, `sp` and `pc` of 64
+/// bits, followed by the 32-bit `cpsr`.
+pub(crate) const NUM_REGS: usize = 34;
+
+/// Whether the trap is from EL0, in which case `sp` is saved in the trap frame.
+fn is_user(tf: &TrapFrame) -> bool {
+    tf.spsr & 0b1111 == 0 // M[3:0] == EL0t
+}
+
+/// Reads the `n`-th register of GDB, returning the value and its size in
+/// bytes.
+pub(crate) fn read_reg(tf: &TrapFrame, n: usize) -> Option<(u64, usize)> {
+    Some(match n {
+        0..31 => (tf.r[n], 8),
+        31 if is_user(tf) => (tf.usp, 8),
+        // The trap frame is pushed on the kernel stack of the trapped code.
+        31 => (
+            (tf as *const TrapFrame as usize + size_of::<TrapFrame>()) as _,
+            8,
+        ),
+        32 => (tf.elr, 8),
+        33 => (tf.spsr, 4),
+        _ => return None,
+    })
+}
+
+/// Writes the `n`-th register of GDB. Returns `false` if it is not writable.
+pub(crate) fn write_reg(tf: &mut TrapFrame, n: usize, val: u64) -> bool {
+    match n {
+        0..31 => tf.r[n] = val,
+        31 if is_user(tf) => tf.usp = val,
+        32 => tf.elr = val,
+        _ => return false,
+    }
+    true
+}
+
+/// `brk #0`
+const BRK: [u8; 4] = 0xd420_0000u32.to_le_bytes();
+
+/// Returns the breakpoint instruction of the given GDB breakpoint kind.
+pub(crate) fn breakpoint_insn(kind: usize) -> Option<&'static [u8]> {
+    match kind {
+        4 => Some(&BRK),
+        _ => None,
+    }
+}
+
+/// Returns the address of the breakpoint instruction that caused the trap.
+pub(crate) fn breakpoint_addr(tf: &TrapFrame) -> usize {
+    tf.elr as _
+}
+
+/// Sets the program counter in the trap frame.
+pub(crate) fn set_pc(tf: &mut TrapFrame, pc: usize) {
+    tf.elr = pc as _;
+}
+
+/// Makes the modified instructions in `addr..addr + len` visible to
+/// instruction fetch.
+pub(crate) fn flush_icache(addr: usize, len: usize) {
+    // The minimum cache line size of aarch64.
+    const LINE_SIZE: usize = 16;
+    let start = addr & !(LINE_SIZE - 1);
+    for line in (start..addr + len).step_by(LINE_SIZE) {
+        unsafe { core::arch::asm!("dc cvau, {}", in(reg) line) };
+    }
+    unsafe { core::arch::asm!("dsb ish", "ic iallu", "dsb ish", "isb") };
+}
@@ -1,12 +1,23 @@
 mod context;
 
 pub mod asm;
+pub mod debug;
 pub mod init;
 
+#[cfg(feature = "gdbstub")]
+pub(crate) mod gdb;
+
+#[cfg(feature = "sve")]
+mod sve;
 #[cfg(target_os = "none")]
 mod trap;
 
 #[cfg(feature = "uspace")]
 pub mod uspace;
 
+#[cfg(feature = "fp-simd")]
+pub(crate) use self::context::KernelFpState;
 pub use self::context::{FpState, TaskContext, TrapFrame};
+#[cfg(feature = "sve")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sve")))]
+pub use self::sve::{sme_vl, sve_vl, SveState, SVE_MAX_VL};
@@ -0,0 +1,451 @@
+//! SVE and SME states.
+//!
+//! SVE and SME instructions are disabled for user space (EL0) by
+//! `CPACR_EL1.ZEN` and `CPACR_EL1.SMEN` until a task uses them, which traps
+//! and enables them for the task. The Z, P and FFR registers, the streaming
+//! mode and the ZA storage are saved and restored only for the tasks that have
+//! enabled them, and the other tasks only save and restore the V registers as
+//! the lowest 128 bits of the Z registers.
+//!
+//! The vector lengths are deliberately limited to [`SVE_MAX_VL`] bytes (512
+//! bits) by `ZCR_EL1` and `SMCR_EL1`, so that [`SveState`] has a fixed size.
+//! The actual ones are read by `RDVL` and `RDSVL` in [`init`], and returned by
+//! [`sve_vl`] and [`sme_vl`]. Longer vector lengths supported by the hardware
+//! are not used.
+
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use aarch64_cpu::{asm::barrier, registers::*};
+
+/// Maximum vector length in bytes of SVE and the streaming mode of SME.
+pub const SVE_MAX_VL: usize = 64;
+
+/// `LEN` of `ZCR_EL1` and `SMCR_EL1` for the longest architectural vector
+/// length, which is limited to the longest one supported by the hardware.
+const LEN_MAX: u64 = 0xf;
+
+/// `SVCR.SM`: the streaming mode is enabled.
+const SVCR_SM: u64 = 1 << 0;
+/// `SVCR.ZA`: the ZA storage is enabled.
+const SVCR_ZA: u64 = 1 << 1;
+/// `SMCR_EL1.EZT0`: the ZT0 register of SME2 is accessible.
+const SMCR_EZT0: u64 = 1 << 30;
+
+/// Offset and values of `CPACR_EL1.SMEN`, which is not defined in
+/// [`CPACR_EL1`].
+const CPACR_SMEN_SHIFT: u64 = 24;
+const CPACR_SMEN_TRAP_EL0: u64 = 0b01;
+const CPACR_SMEN_TRAP_NOTHING: u64 = 0b11;
+
+/// SVE vector length in bytes, or `0` if SVE is not supported.
+static SVE_VL: AtomicUsize = AtomicUsize::new(0);
+/// SME streaming vector length in bytes, or `0` if SME is not supported.
+static SME_VL: AtomicUsize = AtomicUsize::new(0);
+/// Whether SME2 (and its ZT0 register) is supported.
+static SME2: AtomicBool = AtomicBool::new(false);
+
+/// Returns the SVE vector length in bytes, or `0` if SVE is not supported.
+///
+/// It is at most [`SVE_MAX_VL`], even if the hardware supports a longer one.
+pub fn sve_vl() -> usize {
+    SVE_VL.load(Ordering::Relaxed)
+}
+
+/// Returns the streaming vector length of SME in bytes, or `0` if SME is not
+/// supported.
+///
+/// It is at most [`SVE_MAX_VL`], even if the hardware supports a longer one.
+pub fn sme_vl() -> usize {
+    SME_VL.load(Ordering::Relaxed)
+}
+
+/// Configures the vector lengths and disables SVE and SME for user space on
+/// the current CPU, if they are supported.
+pub(super) fn init() {
+    let vl_len = (SVE_MAX_VL / 16 - 1) as u64;
+    if ID_AA64PFR0_EL1.read(ID_AA64PFR0_EL1::SVE) != 0 {
+        CPACR_EL1.modify(CPACR_EL1::ZEN::TrapEl0);
+        barrier::isb(barrier::SY);
+        let set_len = |len: u64| {
+            let vl: usize;
+            unsafe {
+                asm!(
+                    ".arch_extension sve",
+                    "msr S3_0_C1_C2_0, {len}", // ZCR_EL1
+                    "isb",
+                    "rdvl {vl}, #1",
+                    len = in(reg) len,
+                    vl = out(reg) vl,
+                )
+            };
+            vl
+        };
+        let max_vl = set_len(LEN_MAX);
+        let vl = set_len(vl_len);
+        if max_vl > vl {
+            info!("SVE vector length limited from {max_vl} to {vl} bytes");
+        }
+        SVE_VL.store(vl, Ordering::Relaxed);
+    }
+
+    let sme = (ID_AA64PFR1_EL1.get() >> 24) & 0xf;
+    if sme != 0 {
+        set_smen(false);
+        let ezt0 = if sme >= 2 { SMCR_EZT0 } else { 0 };
+        let set_len = |len: u64| {
+            let vl: usize;
+            unsafe {
+                asm!(
+                    ".arch_extension sme",
+                    "msr S3_0_C1_C2_6, {smcr}", // SMCR_EL1
+                    "isb",
+                    "rdsvl {vl}, #1",
+                    smcr = in(reg) len | ezt0,
+                    vl = out(reg) vl,
+                )
+            };
+            vl
+        };
+        let max_vl = set_len(LEN_MAX);
+        let vl = set_len(vl_len);
+        if max_vl > vl {
+            info!("SME streaming vector length limited from {max_vl} to {vl} bytes");
+        }
+        SME_VL.store(vl, Ordering::Relaxed);
+        SME2.store(sme >= 2, Ordering::Relaxed);
+    }
+}
+
+fn zen_enabled() -> bool {
+    CPACR_EL1.matches_all(CPACR_EL1::ZEN::TrapNothing)
+}
+
+fn set_zen(enabled: bool) {
+    if enabled {
+        CPACR_EL1.modify(CPACR_EL1::ZEN::TrapNothing);
+    } else {
+        CPACR_EL1.modify(CPACR_EL1::ZEN::TrapEl0);
+    }
+}
+
+fn smen_enabled() -> bool {
+    (CPACR_EL1.get() >> CPACR_SMEN_SHIFT) & 0b11 == CPACR_SMEN_TRAP_NOTHING
+}
+
+fn set_smen(enabled: bool) {
+    let smen = if enabled {
+        CPACR_SMEN_TRAP_NOTHING
+    } else {
+        CPACR_SMEN_TRAP_EL0
+    };
+    let cpacr = CPACR_EL1.get() & !(0b11 << CPACR_SMEN_SHIFT);
+    CPACR_EL1.set(cpacr | smen << CPACR_SMEN_SHIFT);
+}
+
+fn read_svcr() -> u64 {
+    let svcr;
+    unsafe { asm!("mrs {}, S3_3_C4_C2_2", out(reg) svcr) };
+    svcr
+}
+
+/// SVE and SME states of a task.
+#[repr(C, align(16))]
+pub struct SveState {
+    /// Z0..Z31, each of the vector length of the saved mode.
+    pub z: [u8; 32 * SVE_MAX_VL],
+    /// P0..P15, each of 1/8 of the vector length of the saved mode.
+    pub p: [u8; 16 * SVE_MAX_VL / 8],
+    /// The first-fault register (FFR), which is not saved in the streaming
+    /// mode.
+    pub ffr: [u8; SVE_MAX_VL / 8],
+    /// The ZA storage, saved only if `SVCR.ZA` is set.
+    pub za: [u8; SVE_MAX_VL * SVE_MAX_VL],
+    /// The ZT0 register of SME2, saved only if `SVCR.ZA` is set.
+    pub zt0: [u8; 64],
+    /// The streaming vector control register (`SVCR`).
+    pub svcr: u64,
+    /// Whether SVE instructions are enabled for the task.
+    pub sve_enabled: bool,
+    /// Whether SME instructions are enabled for the task.
+    pub sme_enabled: bool,
+}
+
+impl SveState {
+    /// Saves the current SVE and SME states to this structure.
+    ///
+    /// Returns the vector length if the Z registers are saved, i.e., SVE is
+    /// enabled or the streaming mode is on.
+    pub(super) fn save(&mut self) -> Option<usize> {
+        let sve_vl = SVE_VL.load(Ordering::Relaxed);
+        let sme_vl = SME_VL.load(Ordering::Relaxed);
+        self.sve_enabled = sve_vl != 0 && zen_enabled();
+        self.sme_enabled = sme_vl != 0 && smen_enabled();
+        self.svcr = if sme_vl != 0 { read_svcr() } else { 0 };
+
+        let streaming = self.svcr & SVCR_SM != 0;
+        if self.svcr & SVCR_ZA != 0 {
+            unsafe { save_za(self) };
+        }
+        if streaming {
+            unsafe { save_zp(self) };
+            Some(sme_vl)
+        } else if self.sve_enabled {
+            unsafe {
+                save_zp(self);
+                save_ffr(self);
+            }
+            Some(sve_vl)
+        } else {
+            None
+        }
+    }
+
+    /// Restores the SVE and SME states from this structure, and enables or
+    /// disables SVE and SME for the task accordingly.
+    ///
+    /// Returns whether the Z registers are restored.
+    pub(super) fn restore(&self) -> bool {
+        let sve_vl = SVE_VL.load(Ordering::Relaxed);
+        let sme_vl = SME_VL.load(Ordering::Relaxed);
+        if sve_vl != 0 {
+            set_zen(self.sve_enabled);
+        }
+        if sme_vl != 0 {
+            set_smen(self.sme_enabled);
+            // Entering and exiting the streaming mode or the ZA storage resets
+            // the registers, so they are set before the registers are loaded.
+            unsafe {
+                asm!(".arch_extension sme", "smstop");
+                if self.svcr & SVCR_SM != 0 {
+                    asm!(".arch_extension sme", "smstart sm");
+                }
+                if self.svcr & SVCR_ZA != 0 {
+                    asm!(".arch_extension sme", "smstart za");
+                }
+            }
+        }
+        barrier::isb(barrier::SY);
+
+        if self.svcr & SVCR_ZA != 0 {
+            unsafe { restore_za(self) };
+        }
+        if self.svcr & SVCR_SM != 0 {
+            unsafe { restore_zp(self) };
+            true
+        } else if self.sve_enabled {
+            unsafe {
+                restore_ffr(self);
+                restore_zp(self);
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for SveState {
+    fn default() -> Self {
+        unsafe { core::mem::MaybeUninit::zeroed().assume_init() }
+    }
+}
+
+impl fmt::Debug for SveState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SveState")
+            .field("svcr", &self.svcr)
+            .field("sve_enabled", &self.sve_enabled)
+            .field("sme_enabled", &self.sme_enabled)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns whether SVE or SME is enabled for the current task, i.e., its SVE
+/// and SME states are live.
+pub(super) fn enabled() -> bool {
+    (SVE_VL.load(Ordering::Relaxed) != 0 && zen_enabled())
+        || (SME_VL.load(Ordering::Relaxed) != 0 && smen_enabled())
+}
+
+/// Disables SVE and SME and exits the streaming mode after the states are
+/// saved, so that the next task cannot access them before its own states are
+/// restored on lazy switching, and the kernel can use FP/SIMD instructions.
+pub(super) fn park() {
+    if SVE_VL.load(Ordering::Relaxed) != 0 {
+        set_zen(false);
+    }
+    if SME_VL.load(Ordering::Relaxed) != 0 {
+        set_smen(false);
+        unsafe { asm!(".arch_extension sme", "smstop") };
+    }
+}
+
+/// Enables SVE for the current task on the trap caused by its first SVE
+/// instruction, and clears the SVE registers, which may hold the states of
+/// other tasks.
+///
+/// Returns `false` if SVE is not supported or already enabled.
+#[cfg(target_os = "none")]
+pub(super) fn handle_sve_trap() -> bool {
+    // SVE traps take precedence over FP traps.
+    #[cfg(feature = "fp-lazy")]
+    if super::context::handle_fp_trap() {
+        return true;
+    }
+    if SVE_VL.load(Ordering::Relaxed) == 0 || zen_enabled() {
+        return false;
+    }
+    set_zen(true);
+    barrier::isb(barrier::SY);
+    unsafe {
+        asm!(
+            ".arch_extension sve",
+            ".arch_extension simd",
+            // Writes to V registers clear the upper bits of Z registers.
+            ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+            "mov v\\n\\().16b, v\\n\\().16b",
+            ".endr",
+            ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15",
+            "pfalse p\\n\\().b",
+            ".endr",
+            "wrffr p0.b",
+        )
+    };
+    true
+}
+
+/// Enables SME for the current task on the trap caused by its first SME
+/// instruction. The streaming mode and the ZA storage are off, and entering
+/// them resets the registers.
+///
+/// Returns `false` if SME is not supported or already enabled.
+#[cfg(target_os = "none")]
+pub(super) fn handle_sme_trap() -> bool {
+    #[cfg(feature = "fp-lazy")]
+    if super::context::handle_fp_trap() {
+        return true;
+    }
+    if SME_VL.load(Ordering::Relaxed) == 0 || smen_enabled() {
+        return false;
+    }
+    set_smen(true);
+    barrier::isb(barrier::SY);
+    true
+}
+
+/// Saves Z0..Z31 and P0..P15 of the current vector length.
+unsafe fn save_zp(state: &mut SveState) {
+    unsafe {
+        asm!(
+            ".arch_extension sve",
+            ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+            "str z\\n, [{z}, #\\n, mul vl]",
+            ".endr",
+            ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15",
+            "str p\\n, [{p}, #\\n, mul vl]",
+            ".endr",
+            z = in(reg) state.z.as_mut_ptr(),
+            p = in(reg) state.p.as_mut_ptr(),
+        )
+    }
+}
+
+/// Restores Z0..Z31 and P0..P15 of the current vector length.
+unsafe fn restore_zp(state: &SveState) {
+    unsafe {
+        asm!(
+            ".arch_extension sve",
+            ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+            "ldr z\\n, [{z}, #\\n, mul vl]",
+            ".endr",
+            ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15",
+            "ldr p\\n, [{p}, #\\n, mul vl]",
+            ".endr",
+            z = in(reg) state.z.as_ptr(),
+            p = in(reg) state.p.as_ptr(),
+        )
+    }
+}
+
+/// Saves FFR, using P0 as the scratch register, which must be saved before.
+unsafe fn save_ffr(state: &mut SveState) {
+    unsafe {
+        asm!(
+            ".arch_extension sve",
+            "rdffr p0.b",
+            "str p0, [{ffr}]",
+            "ldr p0, [{p}]",
+            ffr = in(reg) state.ffr.as_mut_ptr(),
+            p = in(reg) state.p.as_ptr(),
+        )
+    }
+}
+
+/// Restores FFR, using P0 as the scratch register, which must be restored
+/// after.
+unsafe fn restore_ffr(state: &SveState) {
+    unsafe {
+        asm!(
+            ".arch_extension sve",
+            "ldr p0, [{ffr}]",
+            "wrffr p0.b",
+            ffr = in(reg) state.ffr.as_ptr(),
+        )
+    }
+}
+
+/// Saves the ZA storage, and ZT0 if SME2 is supported.
+unsafe fn save_za(state: &mut SveState) {
+    unsafe {
+        asm!(
+            ".arch_extension sme",
+            "rdsvl {svl}, #1",
+            "mov w12, #0",
+            "1:",
+            "str za[w12, 0], [{za}]",
+            "add {za}, {za}, {svl}",
+            "add w12, w12, #1",
+            "cmp x12, {svl}",
+            "b.lo 1b",
+            za = inout(reg) state.za.as_mut_ptr() => _,
+            svl = out(reg) _,
+            out("x12") _,
+        );
+        if SME2.load(Ordering::Relaxed) {
+            asm!(
+                ".arch_extension sme2",
+                "str zt0, [{}]",
+                in(reg) state.zt0.as_mut_ptr(),
+            );
+        }
+    }
+}
+
+/// Restores the ZA storage, and ZT0 if SME2 is supported.
+unsafe fn restore_za(state: &SveState) {
+    unsafe {
+        asm!(
+            ".arch_extension sme",
+            "rdsvl {svl}, #1",
+            "mov w12, #0",
+            "1:",
+            "ldr za[w12, 0], [{za}]",
+            "add {za}, {za}, {svl}",
+            "add w12, w12, #1",
+            "cmp x12, {svl}",
+            "b.lo 1b",
+            za = inout(reg) state.za.as_ptr() => _,
+            svl = out(reg) _,
+            out("x12") _,
+        );
+        if SME2.load(Ordering::Relaxed) {
+            asm!(
+                ".arch_extension sme2",
+                "ldr zt0, [{}]",
+                in(reg) state.zt0.as_ptr(),
+            );
+        }
+    }
+}
@@ -1,10 +1,17 @@
 use aarch64_cpu::registers::{ESR_EL1, FAR_EL1};
+use memory_addr::VirtAddr;
 use tock_registers::interfaces::Readable;
 
 use super::TrapFrame;
-use crate::trap::PageFaultFlags;
+use crate::extable::search_exception_table;
+#[cfg(feature = "uspace")]
+use crate::trap::SyscallAction;
+use crate::trap::{ExceptionInfo, ExceptionKind, PageFaultFlags};
 
-core::arch::global_asm!(include_str!("trap.S"));
+core::arch::global_asm!(
+    include_str!("trap.S"),
+    step_used = sym super::debug::STEP_USED,
+);
 
 #[repr(u8)]
 #[derive(Debug)]
@@ -29,17 +36,67 @@ enum TrapSource {
 #[unsafe(no_mangle)]
 fn invalid_exception(tf: &TrapFrame, kind: TrapKind, source: TrapSource) {
     panic!(
-        "Invalid exception {:?} from {:?}:\n{:#x?}",
-        kind, source, tf
+        "Invalid exception {:?} from {:?}:\n{:#x?}\n{}",
+        kind,
+        source,
+        tf,
+        crate::backtrace::trap_backtrace(tf),
     );
 }
 
 #[unsafe(no_mangle)]
-fn handle_irq_exception(_tf: &TrapFrame) {
-    handle_trap!(IRQ, 0);
+fn handle_irq_exception(tf: &TrapFrame) {
+    trace_trap!(TRAP_ENTER, crate::trap::TrapType::Irq, tf);
+    #[cfg(feature = "gicv3")]
+    if let Some(intid) = crate::gicv3::ack() {
+        let handled = handle_trap!(IRQ, intid as usize);
+        count_trap!(IRQ, intid as usize, handled);
+        crate::gicv3::eoi(intid);
+    } else {
+        count_trap!(unhandled_irqs);
+    }
+    #[cfg(not(feature = "gicv3"))]
+    {
+        let handled = handle_trap!(IRQ, 0);
+        count_trap!(IRQ, 0, handled);
+    }
+    trace_trap!(TRAP_EXIT, crate::trap::TrapType::Irq, tf);
+    irq_return!(tf);
+}
+
+fn handle_exception(
+    tf: &mut TrapFrame,
+    kind: ExceptionKind,
+    vaddr: Option<VirtAddr>,
+    is_user: bool,
+) -> bool {
+    count_trap!(EXCEPTION, kind);
+    if !is_user {
+        if let Some(fixup) = search_exception_table(tf.elr as _) {
+            tf.elr = fixup as _;
+            return true;
+        }
+    }
+    let info = ExceptionInfo {
+        kind,
+        pc: tf.elr as _,
+        cause: ESR_EL1.get() as _,
+        vaddr,
+        is_user,
+    };
+    handle_trap!(EXCEPTION, tf, &info)
+}
+
+/// Classifies an abort by its fault status code (IFSC or DFSC bits).
+fn abort_kind(iss: u64) -> ExceptionKind {
+    match iss & 0b111111 {
+        0b100001 => ExceptionKind::Misaligned,
+        fsc if matches!(fsc & 0b111100, 0b0100 | 0b1100) => ExceptionKind::PageFault,
+        _ => ExceptionKind::AccessFault,
+    }
 }
 
-fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
+fn handle_instruction_abort(tf: &mut TrapFrame, iss: u64, is_user: bool) {
     let mut access_flags = PageFaultFlags::EXECUTE;
     if is_user {
         access_flags |= PageFaultFlags::USER;
@@ -47,22 +104,25 @@ fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
     let vaddr = va!(FAR_EL1.get() as usize);
 
     // Only handle Translation fault and Permission fault
-    if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
-        || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
+    let kind = abort_kind(iss);
+    if kind == ExceptionKind::PageFault {
+        count_trap!(page_faults);
+    }
+    if !(kind == ExceptionKind::PageFault && handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user))
+        && !handle_exception(tf, kind, Some(vaddr), is_user)
     {
         panic!(
-            "Unhandled {} Instruction Abort @ {:#x}, fault_vaddr={:#x}, ESR={:#x} ({:?}):\n{:#x?}",
+            "Unhandled {} Instruction Abort @ {:#x}, fault_vaddr={:#x}, ESR={:#x} ({:?}):\n{:#x?}\n{}",
             if is_user { "EL0" } else { "EL1" },
             tf.elr,
             vaddr,
             ESR_EL1.get(),
             access_flags,
-            tf,
-        );
+            tf, crate::backtrace::trap_backtrace(tf));
     }
 }
 
-fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
+fn handle_data_abort(tf: &mut TrapFrame, iss: u64, is_user: bool) {
     let wnr = (iss & (1 << 6)) != 0; // WnR: Write not Read
     let cm = (iss & (1 << 8)) != 0; // CM: Cache maintenance
     let mut access_flags = if wnr & !cm {
@@ -76,17 +136,22 @@ fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
     let vaddr = va!(FAR_EL1.get() as usize);
 
     // Only handle Translation fault and Permission fault
-    if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
-        || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
+    let kind = abort_kind(iss);
+    if kind == ExceptionKind::PageFault {
+        count_trap!(page_faults);
+    }
+    if !(kind == ExceptionKind::PageFault && handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user))
+        && !handle_exception(tf, kind, Some(vaddr), is_user)
     {
         panic!(
-            "Unhandled {} Data Abort @ {:#x}, fault_vaddr={:#x}, ESR={:#x} ({:?}):\n{:#x?}",
+            "Unhandled {} Data Abort @ {:#x}, fault_vaddr={:#x}, ESR={:#x} ({:?}):\n{:#x?}\n{}",
             if is_user { "EL0" } else { "EL1" },
             tf.elr,
             vaddr,
             ESR_EL1.get(),
             access_flags,
             tf,
+            crate::backtrace::trap_backtrace(tf),
         );
     }
 }
@@ -95,27 +160,106 @@ fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
 fn handle_sync_exception(tf: &mut TrapFrame) {
     let esr = ESR_EL1.extract();
     let iss = esr.read(ESR_EL1::ISS);
+    #[cfg(feature = "trap-trace")]
+    let ty = ec_to_type(esr.read_as_enum(ESR_EL1::EC), iss);
+    trace_trap!(TRAP_ENTER, ty, tf);
     match esr.read_as_enum(ESR_EL1::EC) {
         #[cfg(feature = "uspace")]
         Some(ESR_EL1::EC::Value::SVC64) => {
-            tf.r[0] = crate::trap::handle_syscall(tf, tf.r[8] as usize) as u64;
+            let syscall_num = tf.r[8];
+            match crate::trap::handle_syscall(tf, syscall_num as usize) {
+                SyscallAction::Return(ret) => tf.r[0] = ret as u64,
+                SyscallAction::Restart => {
+                    tf.r[8] = syscall_num;
+                    tf.elr -= 4;
+                }
+                SyscallAction::NoReturnValue => {}
+            }
         }
         Some(ESR_EL1::EC::Value::InstrAbortLowerEL) => handle_instruction_abort(tf, iss, true),
         Some(ESR_EL1::EC::Value::InstrAbortCurrentEL) => handle_instruction_abort(tf, iss, false),
         Some(ESR_EL1::EC::Value::DataAbortLowerEL) => handle_data_abort(tf, iss, true),
         Some(ESR_EL1::EC::Value::DataAbortCurrentEL) => handle_data_abort(tf, iss, false),
+        #[cfg(feature = "fp-lazy")]
+        Some(ESR_EL1::EC::Value::TrappedFP) if super::context::handle_fp_trap() => {}
+        #[cfg(feature = "sve")]
+        Some(ESR_EL1::EC::Value::TrappedSve) if super::sve::handle_sve_trap() => {}
+        #[cfg(feature = "sve")]
+        Some(ESR_EL1::EC::Value::TrappedSME) if super::sve::handle_sme_trap() => {}
         Some(ESR_EL1::EC::Value::Brk64) => {
-            debug!("BRK #{:#x} @ {:#x} ", iss, tf.elr);
-            tf.elr += 4;
+            count_trap!(breakpoints);
+            if !handle_trap!(BREAKPOINT, tf) {
+                debug!("BRK #{:#x} @ {:#x} ", iss, tf.elr);
+                tf.elr += 4;
+            }
+        }
+        Some(ESR_EL1::EC::Value::SoftwareStepLowerEL)
+        | Some(ESR_EL1::EC::Value::SoftwareStepCurrentEL) => {
+            super::debug::set_single_step(tf, false);
+            // Steps over hardware breakpoints are not reported.
+            #[cfg(feature = "hw-breakpoint")]
+            let stepped_over = super::debug::rearm_hw_breakpoints();
+            #[cfg(not(feature = "hw-breakpoint"))]
+            let stepped_over = false;
+            if !stepped_over && !handle_trap!(SINGLE_STEP, tf) {
+                debug!("Software step @ {:#x}", tf.elr);
+            }
+        }
+        #[cfg(feature = "hw-breakpoint")]
+        Some(ESR_EL1::EC::Value::BreakpointLowerEL)
+        | Some(ESR_EL1::EC::Value::BreakpointCurrentEL) => {
+            super::debug::handle_hw_breakpoints(tf, false, tf.elr as _)
         }
-        _ => {
-            panic!(
-                "Unhandled synchronous exception @ {:#x}: ESR={:#x} (EC {:#08b}, ISS {:#x})",
-                tf.elr,
-                esr.get(),
-                esr.read(ESR_EL1::EC),
-                esr.read(ESR_EL1::ISS),
-            );
+        #[cfg(feature = "hw-breakpoint")]
+        Some(ESR_EL1::EC::Value::WatchpointLowerEL)
+        | Some(ESR_EL1::EC::Value::WatchpointCurrentEL) => {
+            super::debug::handle_hw_breakpoints(tf, true, FAR_EL1.get() as _)
+        }
+        ec => {
+            let kind = match ec {
+                Some(ESR_EL1::EC::Value::Unknown) => ExceptionKind::IllegalInstruction,
+                Some(ESR_EL1::EC::Value::PCAlignmentFault)
+                | Some(ESR_EL1::EC::Value::SPAlignmentFault) => ExceptionKind::Misaligned,
+                Some(ESR_EL1::EC::Value::TrappedFP64) => ExceptionKind::FloatingPoint,
+                _ => ExceptionKind::Other,
+            };
+            let is_user = tf.spsr & 0b1111 == 0; // M[3:0] == EL0t
+            if !handle_exception(tf, kind, None, is_user) {
+                panic!(
+                    "Unhandled synchronous exception @ {:#x}: ESR={:#x} (EC {:#08b}, ISS {:#x})\n{}",
+                    tf.elr,
+                    esr.get(),
+                    esr.read(ESR_EL1::EC),
+                    esr.read(ESR_EL1::ISS),
+                    crate::backtrace::trap_backtrace(tf),
+                );
+            }
+        }
+    }
+    trace_trap!(TRAP_EXIT, ty, tf);
+}
+
+#[cfg(feature = "trap-trace")]
+fn ec_to_type(ec: Option<ESR_EL1::EC::Value>, iss: u64) -> crate::trap::TrapType {
+    use crate::trap::TrapType;
+    match ec {
+        #[cfg(feature = "uspace")]
+        Some(ESR_EL1::EC::Value::SVC64) => TrapType::Syscall,
+        Some(ESR_EL1::EC::Value::InstrAbortLowerEL)
+        | Some(ESR_EL1::EC::Value::InstrAbortCurrentEL)
+        | Some(ESR_EL1::EC::Value::DataAbortLowerEL)
+        | Some(ESR_EL1::EC::Value::DataAbortCurrentEL)
+            if abort_kind(iss) == ExceptionKind::PageFault =>
+        {
+            TrapType::PageFault
         }
+        Some(ESR_EL1::EC::Value::Brk64)
+        | Some(ESR_EL1::EC::Value::SoftwareStepLowerEL)
+        | Some(ESR_EL1::EC::Value::SoftwareStepCurrentEL)
+        | Some(ESR_EL1::EC::Value::BreakpointLowerEL)
+        | Some(ESR_EL1::EC::Value::BreakpointCurrentEL)
+        | Some(ESR_EL1::EC::Value::WatchpointLowerEL)
+        | Some(ESR_EL1::EC::Value::WatchpointCurrentEL) => TrapType::Breakpoint,
+        _ => TrapType::Exception,
     }
 }
@@ -1,9 +1,17 @@
 //! Structures and functions for user space.
 
+use core::arch::naked_asm;
+
 use memory_addr::VirtAddr;
 
 use crate::TrapFrame;
 
+pub use crate::uaccess::{
+    clear_user, copy_from_user, copy_to_user, is_user_range, strncpy_from_user,
+};
+
+pub mod signal;
+
 /// Context to enter user space.
 pub struct UspaceContext(TrapFrame);
 
@@ -86,6 +94,10 @@ impl UspaceContext {
                 msr     sp_el0, x9
                 msr     elr_el1, x10
                 msr     spsr_el1, x11
+                mrs     x12, mdscr_el1
+                ubfx    x13, x11, #21, #1
+                bfi     x12, x13, #0, #1
+                msr     mdscr_el1, x12
 
                 ldp     x28, x29, [x0, 28 * 8]
                 ldp     x26, x27, [x0, 26 * 8]
@@ -110,3 +122,104 @@ impl UspaceContext {
         }
     }
 }
+
+/// Clears `PSTATE.PAN` to allow privileged access to user memory if PAN is
+/// implemented. Whether PAN is implemented is kept in `x9`, and the original
+/// `PSTATE.PAN` is kept in `x10`.
+macro_rules! user_access_begin {
+    () => {
+        "
+        mrs     x9, ID_AA64MMFR1_EL1
+        ubfx    x9, x9, #20, #4         // ID_AA64MMFR1_EL1.PAN
+        cbz     x9, 9f
+        mrs     x10, S3_0_C4_C2_3       // PAN
+        msr     S3_0_C4_C2_3, xzr
+    9:"
+    };
+}
+
+/// Restores `PSTATE.PAN` if PAN is implemented.
+macro_rules! user_access_end {
+    () => {
+        "
+        cbz     x9, 9f
+        msr     S3_0_C4_C2_3, x10
+    9:"
+    };
+}
+
+/// Copies `len` bytes from `src` to `dst`, either of which may be in user
+/// space. Returns the number of bytes not copied.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_copy_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> usize {
+    naked_asm!(
+        user_access_begin!(),
+        "
+        cbz     x2, 4f
+    2:  ldrb    w11, [x1], #1
+    3:  strb    w11, [x0], #1
+        subs    x2, x2, #1
+        b.ne    2b
+    4:",
+        user_access_end!(),
+        "
+        mov     x0, x2
+        ret",
+        crate::asm_extable!("2b", "4b"),
+        crate::asm_extable!("3b", "4b"),
+    )
+}
+
+/// Fills `len` bytes of user space at `dst` with zeros. Returns the number of
+/// bytes not cleared.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_clear_user(_dst: *mut u8, _len: usize) -> usize {
+    naked_asm!(
+        user_access_begin!(),
+        "
+        cbz     x1, 4f
+    2:  strb    wzr, [x0], #1
+        subs    x1, x1, #1
+        b.ne    2b
+    4:",
+        user_access_end!(),
+        "
+        mov     x0, x1
+        ret",
+        crate::asm_extable!("2b", "4b"),
+    )
+}
+
+/// Copies a NUL-terminated string of at most `len` bytes from user space.
+/// Returns the length of the string, `len` if it is not terminated within
+/// `len` bytes, or `-1` on fault.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_strncpy_from_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> isize {
+    naked_asm!(
+        user_access_begin!(),
+        "
+        mov     x3, #0
+    2:  cmp     x3, x2
+        b.eq    4f
+    3:  ldrb    w11, [x1, x3]
+        strb    w11, [x0, x3]
+        cbz     w11, 4f
+        add     x3, x3, #1
+        b       2b
+    5:  mov     x3, #-1
+    4:",
+        user_access_end!(),
+        "
+        mov     x0, x3
+        ret",
+        crate::asm_extable!("3b", "5b"),
+    )
+}
@@ -0,0 +1,201 @@
+//! Signal frames compatible with the aarch64 Linux ABI.
+
+use super::UspaceContext;
+use crate::signal::{read_from_user, write_to_user};
+use crate::TrapFrame;
+
+pub use crate::signal::{SignalDelivery, SignalInfo, SignalStack};
+
+/// Magic number of the FP/SIMD context record.
+#[cfg(feature = "fp-simd")]
+const FPSIMD_MAGIC: u32 = 0x4650_8001;
+
+/// `PSTATE` bits that can be changed by the signal handler, i.e., the
+/// condition flags.
+const PSTATE_NZCV: u64 = 0xf000_0000;
+
+/// `struct fpsimd_context` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FpsimdContext {
+    magic: u32,
+    size: u32,
+    fpsr: u32,
+    fpcr: u32,
+    vregs: [u128; 32],
+}
+
+/// The `__reserved` area of `struct sigcontext`, which holds a list of
+/// context records terminated by a null record.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct SigContextReserved {
+    fpsimd: FpsimdContext,
+    rest: [u8; 4096 - core::mem::size_of::<FpsimdContext>()],
+}
+
+/// `struct sigcontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SigContext {
+    fault_address: u64,
+    regs: [u64; 31],
+    sp: u64,
+    pc: u64,
+    pstate: u64,
+    _pad: u64,
+    reserved: SigContextReserved,
+}
+
+/// `struct ucontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UContext {
+    flags: u64,
+    link: u64,
+    stack: SignalStack,
+    sigmask: u64,
+    _unused: [u8; 1024 / 8 - 8],
+    _pad: u64,
+    mcontext: SigContext,
+}
+
+/// `struct rt_sigframe` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SignalFrame {
+    info: SignalInfo,
+    uc: UContext,
+}
+
+/// `struct frame_record` in Linux, which links the signal frame into the
+/// frame-pointer chain.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FrameRecord {
+    fp: u64,
+    lr: u64,
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<SigContext>(), 4384);
+static_assertions::const_assert_eq!(core::mem::size_of::<SignalFrame>(), 4688);
+
+/// Pushes a signal frame onto the user stack, and redirects the trap frame to
+/// the signal handler.
+///
+/// The FP/SIMD context record is saved in the frame if the `fp-simd` feature
+/// is enabled. The address of the trampoline is set to the link register
+/// (`x30`) of the handler.
+///
+/// Returns `false` if the user stack is not writable, in which case the trap
+/// frame is not modified.
+///
+/// # Safety
+///
+/// The caller must ensure that the user stack to push the frame onto is in
+/// user space.
+pub unsafe fn setup_sigframe(tf: &mut TrapFrame, delivery: &SignalDelivery) -> bool {
+    let sp = delivery.stack_top(tf.usp as _);
+    let record_addr = (sp - core::mem::size_of::<FrameRecord>()) & !15;
+    let frame_addr = (record_addr - core::mem::size_of::<SignalFrame>()) & !15;
+
+    let mut frame: SignalFrame = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
+    frame.info = delivery.info;
+    frame.uc.stack = delivery.altstack;
+    frame.uc.sigmask = delivery.sigmask;
+    let sc = &mut frame.uc.mcontext;
+    sc.regs = tf.r;
+    sc.sp = tf.usp;
+    sc.pc = tf.elr;
+    sc.pstate = tf.spsr;
+    #[cfg(feature = "fp-simd")]
+    {
+        let mut state = crate::FpState::default();
+        state.save();
+        sc.reserved.fpsimd = FpsimdContext {
+            magic: FPSIMD_MAGIC,
+            size: core::mem::size_of::<FpsimdContext>() as _,
+            fpsr: state.fpsr,
+            fpcr: state.fpcr,
+            vregs: state.regs,
+        };
+    }
+
+    let record = FrameRecord {
+        fp: tf.r[29],
+        lr: tf.elr,
+    };
+    if !unsafe { write_to_user(frame_addr, &frame) && write_to_user(record_addr, &record) } {
+        return false;
+    }
+
+    tf.r[0] = delivery.info.signo as _;
+    tf.r[1] = (frame_addr + core::mem::offset_of!(SignalFrame, info)) as _;
+    tf.r[2] = (frame_addr + core::mem::offset_of!(SignalFrame, uc)) as _;
+    tf.r[29] = record_addr as _;
+    tf.r[30] = delivery.restorer as _;
+    tf.usp = frame_addr as _;
+    tf.elr = delivery.handler as _;
+    true
+}
+
+/// Restores the trap frame from the signal frame on the user stack, which is
+/// pushed by [`setup_sigframe`]. It should be called on `rt_sigreturn`.
+///
+/// Returns the signal mask saved in the frame, or [`None`] if the frame is not
+/// readable, in which case the trap frame is not modified.
+///
+/// # Safety
+///
+/// The caller must ensure that the user stack pointer in the trap frame points
+/// to user space.
+pub unsafe fn restore_from_sigframe(tf: &mut TrapFrame) -> Option<u64> {
+    let frame: SignalFrame = unsafe { read_from_user(tf.usp as _)? };
+    let sc = &frame.uc.mcontext;
+
+    #[cfg(feature = "fp-simd")]
+    if sc.reserved.fpsimd.magic == FPSIMD_MAGIC {
+        let fpsimd = &sc.reserved.fpsimd;
+        let state = crate::FpState {
+            regs: fpsimd.vregs,
+            fpcr: fpsimd.fpcr,
+            fpsr: fpsimd.fpsr,
+            // The SVE and SME states are not in the signal frame, so they are
+            // discarded and disabled until the next use.
+            #[cfg(feature = "sve")]
+            sve: Default::default(),
+        };
+        state.restore();
+    }
+
+    tf.r = sc.regs;
+    tf.usp = sc.sp;
+    tf.elr = sc.pc;
+    tf.spsr = (tf.spsr & !PSTATE_NZCV) | (sc.pstate & PSTATE_NZCV);
+    Some(frame.uc.sigmask)
+}
+
+impl UspaceContext {
+    /// Pushes a signal frame onto the user stack, and redirects the context to
+    /// the signal handler.
+    ///
+    /// See [`setup_sigframe`] for details.
+    ///
+    /// # Safety
+    ///
+    /// See [`setup_sigframe`].
+    pub unsafe fn setup_sigframe(&mut self, delivery: &SignalDelivery) -> bool {
+        unsafe { setup_sigframe(&mut self.0, delivery) }
+    }
+
+    /// Restores the context from the signal frame on the user stack.
+    ///
+    /// See [`restore_from_sigframe`] for details.
+    ///
+    /// # Safety
+    ///
+    /// See [`restore_from_sigframe`].
+    pub unsafe fn restore_from_sigframe(&mut self) -> Option<u64> {
+        unsafe { restore_from_sigframe(&mut self.0) }
+    }
+}
@@ -5,6 +5,8 @@ use memory_addr::{PhysAddr, VirtAddr};
 
 use aarch32_cpu::register::*;
 
+use crate::IrqFlags;
+
 pub use aarch32_cpu::asm::{dmb, dsb, isb, sev, wfe, wfi};
 
 /// Allows the current CPU to respond to interrupts.
@@ -28,6 +30,25 @@ pub fn irqs_enabled() -> bool {
     !cpsr.i() // I bit is 1 when disabled, 0 when enabled
 }
 
+/// Makes the current CPU to ignore interrupts, and returns the previous state
+/// of `CPSR.I`.
+#[inline]
+pub fn local_irq_save() -> IrqFlags {
+    let flags = IrqFlags(Cpsr::read().i() as usize);
+    disable_irqs();
+    flags
+}
+
+/// Restores `CPSR.I` saved by [`local_irq_save`].
+#[inline]
+pub fn local_irq_restore(flags: IrqFlags) {
+    if flags.0 == 0 {
+        enable_irqs();
+    } else {
+        disable_irqs();
+    }
+}
+
 /// Relaxes the current CPU and waits for interrupts.
 ///
 /// It must be called with interrupts enabled, otherwise it will never return.
@@ -86,8 +86,38 @@ impl FpState {
     pub fn restore(&self) {
         unsafe { fpstate_restore(self) }
     }
+
+    /// Saves the live FP/SIMD states to this structure and enables VFP by
+    /// `FPEXC.EN` for a kernel-mode FP/SIMD section.
+    ///
+    /// Returns whether the states are live, i.e., `FPEXC.EN` is set.
+    pub(crate) fn kernel_fpu_begin(&mut self) -> bool {
+        let fpexc: u32;
+        unsafe { core::arch::asm!("vmrs {}, fpexc", out(reg) fpexc) };
+        let live = fpexc & FPEXC_EN != 0;
+        if live {
+            self.save();
+        } else {
+            crate::asm::enable_fp();
+        }
+        live
+    }
+
+    /// Restores the FP/SIMD states saved by [`Self::kernel_fpu_begin`], or
+    /// disables VFP back if they are not live.
+    pub(crate) fn kernel_fpu_end(&self, live: bool) {
+        if live {
+            self.restore();
+        } else {
+            unsafe { core::arch::asm!("vmsr fpexc, {}", in(reg) 0u32) };
+        }
+    }
 }
 
+/// `FPEXC.EN`: VFP is enabled.
+#[cfg(feature = "fp-simd")]
+const FPEXC_EN: u32 = 1 << 30;
+
 /// Saved hardware states of a task.
 ///
 /// The context usually includes:
@@ -116,6 +146,9 @@ pub struct TaskContext {
     pub tp: u32,
     #[cfg(feature = "fp-simd")]
     pub fp_state: FpState,
+    /// Hardware breakpoints of the task.
+    #[cfg(feature = "hw-breakpoint")]
+    pub hw_breakpoints: super::debug::HwBreakpoints,
 }
 
 impl TaskContext {
@@ -158,6 +191,8 @@ impl TaskContext {
             self.fp_state.save();
             next_ctx.fp_state.restore();
         }
+        #[cfg(feature = "hw-breakpoint")]
+        self.hw_breakpoints.switch_to(&next_ctx.hw_breakpoints);
         unsafe { context_switch(self, next_ctx) }
     }
 }
@@ -0,0 +1,27 @@
+//! Hardware debugging support.
+
+use super::TrapFrame;
+
+/// Turns single-stepping on or off for the context in the trap frame.
+///
+/// ARMv7-A has no single-step facility, so single-stepping is not supported
+/// and this function always returns `false`.
+pub fn set_single_step(_tf: &mut TrapFrame, _on: bool) -> bool {
+    false
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub use crate::hw_breakpoint::{HwBreakpoint, HwBreakpointKind, HwBreakpoints};
+
+/// Number of hardware breakpoint slots. Hardware breakpoints are not supported
+/// on ARMv7-A yet.
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) const NUM_HW_SLOTS: usize = 0;
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn hw_slot_supports(_slot: usize, _bp: &HwBreakpoint) -> bool {
+    false
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn install_hw_breakpoints(_slots: &[Option<HwBreakpoint>; NUM_HW_SLOTS]) {}
@@ -0,0 +1,75 @@
+//! Mapping of ARMv7-A trap frames to the GDB register layout.
+
+use crate::TrapFrame;
+
+/// Number of registers in the `g` packet: `r0` to `r15` of 32 bits, the
+/// legacy FPA registers `f0` to `f7` of 96 bits and `fps`, followed by `cpsr`.
+pub(crate) const NUM_REGS: usize = 26;
+
+/// Reads the `n`-th register of GDB, returning the value and its size in
+/// bytes.
+pub(crate) fn read_reg(tf: &TrapFrame, n: usize) -> Option<(u64, usize)> {
+    Some(match n {
+        0..13 => (tf.r[n] as _, 4),
+        13 => (tf.sp as _, 4),
+        14 => (tf.lr as _, 4),
+        15 => (tf.pc as _, 4),
+        16..24 => (0, 12),
+        24 => (0, 4),
+        25 => (tf.cpsr as _, 4),
+        _ => return None,
+    })
+}
+
+/// Writes the `n`-th register of GDB. Returns `false` if it is not writable.
+pub(crate) fn write_reg(tf: &mut TrapFrame, n: usize, val: u64) -> bool {
+    let val = val as u32;
+    match n {
+        0..13 => tf.r[n] = val,
+        13 => tf.sp = val,
+        14 => tf.lr = val,
+        15 => tf.pc = val,
+        _ => return false,
+    }
+    true
+}
+
+/// `bkpt #0`
+const BKPT: [u8; 4] = 0xe120_0070u32.to_le_bytes();
+/// `bkpt #0` in Thumb state.
+const BKPT_THUMB: [u8; 2] = 0xbe00u16.to_le_bytes();
+
+/// Returns the breakpoint instruction of the given GDB breakpoint kind.
+pub(crate) fn breakpoint_insn(kind: usize) -> Option<&'static [u8]> {
+    match kind {
+        2 => Some(&BKPT_THUMB),
+        4 => Some(&BKPT),
+        _ => None,
+    }
+}
+
+/// Returns the address of the breakpoint instruction that caused the trap.
+pub(crate) fn breakpoint_addr(tf: &TrapFrame) -> usize {
+    tf.pc as _
+}
+
+/// Sets the program counter in the trap frame.
+pub(crate) fn set_pc(tf: &mut TrapFrame, pc: usize) {
+    tf.pc = pc as _;
+}
+
+/// Makes the modified instructions in `addr..addr + len` visible to
+/// instruction fetch.
+pub(crate) fn flush_icache(addr: usize, len: usize) {
+    // The minimum cache line size of ARMv7-A.
+    const LINE_SIZE: usize = 32;
+    let start = addr & !(LINE_SIZE - 1);
+    for line in (start..addr + len).step_by(LINE_SIZE) {
+        // DCCMVAU: clean data cache line by VA to PoU
+        unsafe { core::arch::asm!("mcr p15, 0, {}, c7, c11, 1", in(reg) line) };
+    }
+    unsafe {
+        // ICIALLU: invalidate all instruction caches to PoU
+        core::arch::asm!("dsb", "mcr p15, 0, {}, c7, c5, 0", "dsb", "isb", in(reg) 0);
+    }
+}
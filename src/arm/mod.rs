@@ -3,18 +3,16 @@
 mod context;
 
 pub mod asm;
+pub mod debug;
 pub mod init;
 
+#[cfg(feature = "gdbstub")]
+pub(crate) mod gdb;
+
 #[cfg(target_os = "none")]
 mod trap;
 
 #[cfg(feature = "uspace")]
-pub mod uspace {
-    // TODO: This module is currently empty, but it will contain user-space related code in the future.
-    use crate::TrapFrame;
-
-    /// Context to enter user space.
-    pub struct UspaceContext(TrapFrame);
-}
+pub mod uspace;
 
 pub use self::context::{FpState, TaskContext, TrapFrame};
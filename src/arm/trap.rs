@@ -6,8 +6,13 @@ use aarch32_cpu::register::{
     ifsr::FsrStatus,
 };
 
+use memory_addr::VirtAddr;
+
 use super::TrapFrame;
-use crate::trap::PageFaultFlags;
+use crate::extable::search_exception_table;
+#[cfg(feature = "uspace")]
+use crate::trap::SyscallAction;
+use crate::trap::{ExceptionInfo, ExceptionKind, PageFaultFlags};
 
 core::arch::global_asm!(include_str!("trap.S"));
 
@@ -34,9 +39,44 @@ pub enum TrapKind {
     Fiq = 7,
 }
 
+fn is_user(tf: &TrapFrame) -> bool {
+    Cpsr::new_with_raw_value(tf.cpsr).mode() == Ok(ProcessorMode::Usr)
+}
+
+fn handle_exception(
+    tf: &mut TrapFrame,
+    kind: ExceptionKind,
+    cause: usize,
+    vaddr: Option<VirtAddr>,
+) -> bool {
+    count_trap!(EXCEPTION, kind);
+    let is_user = is_user(tf);
+    if !is_user {
+        if let Some(fixup) = search_exception_table(tf.pc as _) {
+            tf.pc = fixup as _;
+            return true;
+        }
+    }
+    let info = ExceptionInfo {
+        kind,
+        pc: tf.pc as _,
+        cause,
+        vaddr,
+        is_user,
+    };
+    handle_trap!(EXCEPTION, tf, &info)
+}
+
 /// Handler for invalid/unhandled exceptions.
 #[unsafe(no_mangle)]
-fn invalid_exception(tf: &TrapFrame, kind: u32) {
+fn invalid_exception(tf: &mut TrapFrame, kind: u32) {
+    if kind == TrapKind::Undefined as u32 {
+        trace_trap!(TRAP_ENTER, crate::trap::TrapType::Exception, tf);
+        if handle_exception(tf, ExceptionKind::IllegalInstruction, kind as _, None) {
+            trace_trap!(TRAP_EXIT, crate::trap::TrapType::Exception, tf);
+            return;
+        }
+    }
     let kind = match kind {
         0 => TrapKind::Reset,
         1 => TrapKind::Undefined,
@@ -48,14 +88,34 @@ fn invalid_exception(tf: &TrapFrame, kind: u32) {
         7 => TrapKind::Fiq,
         _ => TrapKind::Reserved,
     };
-    panic!("Invalid exception {:?}:\n{:#x?}", kind, tf);
+    panic!(
+        "Invalid exception {:?}:\n{:#x?}\n{}",
+        kind,
+        tf,
+        crate::backtrace::trap_backtrace(tf),
+    );
 }
 
 /// Handler for IRQ exceptions.
 #[unsafe(no_mangle)]
-fn handle_irq_exception(_tf: &TrapFrame) {
+fn handle_irq_exception(tf: &TrapFrame) {
     trace!("IRQ received");
-    handle_trap!(IRQ, 0);
+    trace_trap!(TRAP_ENTER, crate::trap::TrapType::Irq, tf);
+    #[cfg(feature = "gicv3")]
+    if let Some(intid) = crate::gicv3::ack() {
+        let handled = handle_trap!(IRQ, intid as usize);
+        count_trap!(IRQ, intid as usize, handled);
+        crate::gicv3::eoi(intid);
+    } else {
+        count_trap!(unhandled_irqs);
+    }
+    #[cfg(not(feature = "gicv3"))]
+    {
+        let handled = handle_trap!(IRQ, 0);
+        count_trap!(IRQ, 0, handled);
+    }
+    trace_trap!(TRAP_EXIT, crate::trap::TrapType::Irq, tf);
+    irq_return!(tf);
 }
 
 /// Handler for SVC (software interrupt) exceptions.
@@ -69,26 +129,53 @@ fn handle_sync_exception(tf: &mut TrapFrame) {
     // Handle syscall through the trap handler
     #[cfg(feature = "uspace")]
     {
-        tf.r[0] = crate::trap::handle_syscall(tf, svc_num as usize) as u32;
+        trace_trap!(TRAP_ENTER, crate::trap::TrapType::Syscall, tf);
+        match crate::trap::handle_syscall(tf, svc_num as usize) {
+            SyscallAction::Return(ret) => tf.r[0] = ret as u32,
+            SyscallAction::Restart => {
+                let is_thumb = (tf.cpsr & (1 << 5)) != 0;
+                tf.r[7] = svc_num;
+                tf.pc -= if is_thumb { 2 } else { 4 };
+            }
+            SyscallAction::NoReturnValue => {}
+        }
+        trace_trap!(TRAP_EXIT, crate::trap::TrapType::Syscall, tf);
     }
     #[cfg(not(feature = "uspace"))]
     {
         panic!(
-            "SVC #{} at {:#x} but uspace feature not enabled:\n{:#x?}",
-            svc_num, tf.pc, tf
+            "SVC #{} at {:#x} but uspace feature not enabled:\n{:#x?}\n{}",
+            svc_num,
+            tf.pc,
+            tf,
+            crate::backtrace::trap_backtrace(tf),
         );
     }
 }
 
-fn handle_page_fault(tf: &TrapFrame, vaddr: usize, base_flags: PageFaultFlags) {
-    let is_user = Cpsr::new_with_raw_value(tf.cpsr).mode() == Ok(ProcessorMode::Usr);
+fn handle_page_fault(tf: &mut TrapFrame, vaddr: usize, base_flags: PageFaultFlags, fsr: u32) {
+    count_trap!(page_faults);
+    let is_user = is_user(tf);
 
     let mut access_flags = base_flags;
     if is_user {
         access_flags |= PageFaultFlags::USER;
     }
 
-    handle_trap!(PAGE_FAULT, vaddr.into(), access_flags, is_user);
+    if !handle_trap!(PAGE_FAULT, vaddr.into(), access_flags, is_user)
+        && !handle_exception(tf, ExceptionKind::PageFault, fsr as _, Some(vaddr.into()))
+    {
+        panic!(
+            "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x}, FSR={:#x} ({:?}):\n{:#x?}\n{}",
+            if is_user { "USR" } else { "SVC" },
+            tf.pc,
+            vaddr,
+            fsr,
+            access_flags,
+            tf,
+            crate::backtrace::trap_backtrace(tf),
+        );
+    }
 }
 
 /// Handler for prefetch abort exceptions.
@@ -99,28 +186,46 @@ fn handle_prefetch_abort_exception(tf: &mut TrapFrame) {
     let fsr_status = match fsr.status() {
         Ok(status) => status,
         Err(raw) => panic!(
-            "Unknown IFSR status {:#x} in Prefetch Abort at {:#x}:\n{:#x?}",
-            raw, tf.pc, tf
+            "Unknown IFSR status {:#x} in Prefetch Abort at {:#x}:\n{:#x?}\n{}",
+            raw,
+            tf.pc,
+            tf,
+            crate::backtrace::trap_backtrace(tf)
         ),
     };
 
+    #[cfg(feature = "trap-trace")]
+    let ty = match fsr_status {
+        FsrStatus::TranslationFaultFirstLevel | FsrStatus::TranslationFaultSecondLevel => {
+            crate::trap::TrapType::PageFault
+        }
+        FsrStatus::DebugEvent => crate::trap::TrapType::Breakpoint,
+        _ => crate::trap::TrapType::Exception,
+    };
+    trace_trap!(TRAP_ENTER, ty, tf);
     match fsr_status {
         FsrStatus::TranslationFaultFirstLevel | FsrStatus::TranslationFaultSecondLevel => {
-            handle_page_fault(tf, far.0 as usize, PageFaultFlags::EXECUTE);
+            handle_page_fault(tf, far.0 as usize, PageFaultFlags::EXECUTE, fsr.raw_value());
         }
         FsrStatus::DebugEvent => {
-            // Treat BKPT as a handled breakpoint and continue at next instruction.
-            let is_thumb = (tf.cpsr & (1 << 5)) != 0;
-            let instr_len = if is_thumb { 2 } else { 4 };
-            tf.pc = tf.pc.wrapping_add(instr_len);
+            count_trap!(breakpoints);
+            if !handle_trap!(BREAKPOINT, tf) {
+                // Skip BKPT and continue at next instruction.
+                let is_thumb = (tf.cpsr & (1 << 5)) != 0;
+                let instr_len = if is_thumb { 2 } else { 4 };
+                tf.pc = tf.pc.wrapping_add(instr_len);
+            }
         }
         _ => {
-            panic!(
-                "Unhandled IFSR status {:?} in Prefetch Abort at {:#x} (IFAR={:#x}):\n{:#x?}",
-                fsr_status, tf.pc, far.0, tf
-            );
+            let vaddr = Some(va!(far.0 as usize));
+            if !handle_exception(tf, ExceptionKind::AccessFault, fsr.raw_value() as _, vaddr) {
+                panic!(
+                    "Unhandled IFSR status {:?} in Prefetch Abort at {:#x} (IFAR={:#x}):\n{:#x?}\n{}",
+                    fsr_status, tf.pc, far.0, tf, crate::backtrace::trap_backtrace(tf));
+            }
         }
     }
+    trace_trap!(TRAP_EXIT, ty, tf);
 }
 
 /// Handler for data abort exceptions.
@@ -137,23 +242,49 @@ fn handle_data_abort_exception(tf: &mut TrapFrame) {
     let fsr_status = match fsr.status() {
         Ok(status) => status,
         Err(raw) => panic!(
-            "Unknown DFSR status {:#x} in Data Abort at {:#x}:\n{:#x?}",
-            raw, tf.pc, tf
+            "Unknown DFSR status {:#x} in Data Abort at {:#x}:\n{:#x?}\n{}",
+            raw,
+            tf.pc,
+            tf,
+            crate::backtrace::trap_backtrace(tf)
         ),
     };
 
+    #[cfg(feature = "trap-trace")]
+    let ty = match fsr_status {
+        DfsrStatus::CommonFsr(FsrStatus::TranslationFaultFirstLevel)
+        | DfsrStatus::CommonFsr(FsrStatus::TranslationFaultSecondLevel)
+        | DfsrStatus::CommonFsr(FsrStatus::PermissionFaultFirstLevel)
+        | DfsrStatus::CommonFsr(FsrStatus::PermissionFaultSecondLevel) => {
+            crate::trap::TrapType::PageFault
+        }
+        _ => crate::trap::TrapType::Exception,
+    };
+    trace_trap!(TRAP_ENTER, ty, tf);
     match fsr_status {
         DfsrStatus::CommonFsr(FsrStatus::TranslationFaultFirstLevel)
         | DfsrStatus::CommonFsr(FsrStatus::TranslationFaultSecondLevel)
         | DfsrStatus::CommonFsr(FsrStatus::PermissionFaultFirstLevel)
         | DfsrStatus::CommonFsr(FsrStatus::PermissionFaultSecondLevel) => {
-            handle_page_fault(tf, far.0 as usize, base_flags);
+            handle_page_fault(tf, far.0 as usize, base_flags, fsr.raw_value());
         }
         _ => {
-            panic!(
-                "Unhandled DFSR status {:?} in Data Abort at {:#x}, FAR={:#x}:\n{:#x?}",
-                fsr_status, tf.pc, far.0, tf
-            );
+            let kind = match fsr_status {
+                DfsrStatus::AlignmentFault => ExceptionKind::Misaligned,
+                _ => ExceptionKind::AccessFault,
+            };
+            let vaddr = Some(va!(far.0 as usize));
+            if !handle_exception(tf, kind, fsr.raw_value() as _, vaddr) {
+                panic!(
+                    "Unhandled DFSR status {:?} in Data Abort at {:#x}, FAR={:#x}:\n{:#x?}\n{}",
+                    fsr_status,
+                    tf.pc,
+                    far.0,
+                    tf,
+                    crate::backtrace::trap_backtrace(tf),
+                );
+            }
         }
     }
+    trace_trap!(TRAP_EXIT, ty, tf);
 }
@@ -0,0 +1,81 @@
+//! Structures and functions for user space.
+
+use core::arch::naked_asm;
+
+use crate::TrapFrame;
+
+pub use crate::uaccess::{
+    clear_user, copy_from_user, copy_to_user, is_user_range, strncpy_from_user,
+};
+
+// TODO: Add the methods to enter user space.
+/// Context to enter user space.
+pub struct UspaceContext(TrapFrame);
+
+/// Copies `len` bytes from `src` to `dst`, either of which may be in user
+/// space. Returns the number of bytes not copied.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_copy_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> usize {
+    naked_asm!(
+        "
+        cmp     r2, #0
+        beq     4f
+    2:  ldrb    r3, [r1], #1
+    3:  strb    r3, [r0], #1
+        subs    r2, r2, #1
+        bne     2b
+    4:  mov     r0, r2
+        bx      lr",
+        crate::asm_extable!("2b", "4b"),
+        crate::asm_extable!("3b", "4b"),
+    )
+}
+
+/// Fills `len` bytes of user space at `dst` with zeros. Returns the number of
+/// bytes not cleared.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_clear_user(_dst: *mut u8, _len: usize) -> usize {
+    naked_asm!(
+        "
+        mov     r2, #0
+        cmp     r1, #0
+        beq     4f
+    2:  strb    r2, [r0], #1
+        subs    r1, r1, #1
+        bne     2b
+    4:  mov     r0, r1
+        bx      lr",
+        crate::asm_extable!("2b", "4b"),
+    )
+}
+
+/// Copies a NUL-terminated string of at most `len` bytes from user space.
+/// Returns the length of the string, `len` if it is not terminated within
+/// `len` bytes, or `-1` on fault.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_strncpy_from_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> isize {
+    naked_asm!(
+        "
+        mov     r3, #0
+    2:  cmp     r3, r2
+        beq     4f
+    3:  ldrb    r12, [r1, r3]
+        strb    r12, [r0, r3]
+        cmp     r12, #0
+        beq     4f
+        add     r3, r3, #1
+        b       2b
+    5:  mvn     r3, #0
+    4:  mov     r0, r3
+        bx      lr",
+        crate::asm_extable!("3b", "5b"),
+    )
+}
@@ -0,0 +1,240 @@
+//! Frame-pointer based stack backtraces.
+//!
+//! The backtrace is obtained by walking the chain of frame records, so the
+//! kernel must be built with frame pointers enabled (i.e.,
+//! `-C force-frame-pointers=yes`). The frame pointer register is `rbp` on
+//! x86_64, `x29` on AArch64, `s0` on RISC-V, `fp` on LoongArch64 and `r11` on
+//! ARMv7-A.
+//!
+//! The walk never leaves the given stack range, so a corrupted frame chain
+//! cannot make it read arbitrary memory.
+//!
+//! ```ignore
+//! use axcpu::backtrace::Backtrace;
+//!
+//! let bt = Backtrace::current(stack_bottom..stack_top);
+//! info!("{}", bt.display(Some(symbolize)));
+//!
+//! fn symbolize(addr: usize) -> Option<(&'static str, usize)> {
+//!     // look up the symbol containing `addr` and the offset into it
+//! }
+//! ```
+
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::TrapFrame;
+
+/// Maximum number of frames to walk.
+const MAX_DEPTH: usize = 64;
+
+const WORD_SIZE: usize = core::mem::size_of::<usize>();
+
+cfg_if::cfg_if! {
+    if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64", target_arch = "loongarch64"))] {
+        // The frame pointer points to the top of the frame, below which the
+        // return address and the previous frame pointer are saved.
+        const RA_OFFSET: isize = -(WORD_SIZE as isize);
+        const FP_OFFSET: isize = -2 * WORD_SIZE as isize;
+    } else {
+        // The frame pointer points to a frame record of the previous frame
+        // pointer, followed by the return address.
+        const RA_OFFSET: isize = WORD_SIZE as isize;
+        const FP_OFFSET: isize = 0;
+    }
+}
+
+/// Returns the program counter and the frame pointer in the trap frame.
+fn trap_frame_regs(tf: &TrapFrame) -> (usize, usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            (tf.rip as _, tf.rbp as _)
+        } else if #[cfg(target_arch = "aarch64")] {
+            (tf.elr as _, tf.r[29] as _)
+        } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+            (tf.sepc, tf.regs.s0)
+        } else if #[cfg(target_arch = "loongarch64")] {
+            (tf.era, tf.regs.fp)
+        } else if #[cfg(target_arch = "arm")] {
+            (tf.pc as _, tf.r[11] as _)
+        }
+    }
+}
+
+/// Reads the frame pointer of the current function.
+#[inline(always)]
+fn current_fp() -> usize {
+    let fp: usize;
+    unsafe {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "x86_64")] {
+                core::arch::asm!("mov {}, rbp", out(reg) fp);
+            } else if #[cfg(target_arch = "aarch64")] {
+                core::arch::asm!("mov {}, x29", out(reg) fp);
+            } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+                core::arch::asm!("mv {}, s0", out(reg) fp);
+            } else if #[cfg(target_arch = "loongarch64")] {
+                core::arch::asm!("move {}, $fp", out(reg) fp);
+            } else if #[cfg(target_arch = "arm")] {
+                core::arch::asm!("mov {}, r11", out(reg) fp);
+            }
+        }
+    }
+    fp
+}
+
+/// Signature of symbolizers, which receive a code address and return the name
+/// of the symbol containing it and the offset into the symbol.
+pub type Symbolizer = fn(usize) -> Option<(&'static str, usize)>;
+
+/// A frame in the backtrace.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    /// The program counter of the frame.
+    ///
+    /// It is the return address for all frames but the one of a trap frame,
+    /// i.e., it points to the instruction after the call.
+    pub pc: usize,
+    /// The frame pointer of the frame.
+    pub fp: usize,
+}
+
+/// An iterator over the frames of a stack, from the innermost to the
+/// outermost.
+#[derive(Debug, Clone)]
+pub struct Backtrace {
+    first_pc: Option<usize>,
+    fp: usize,
+    stack: Range<usize>,
+    depth: usize,
+}
+
+impl Backtrace {
+    /// Creates a backtrace of the code interrupted by the trap, starting from
+    /// the program counter in the trap frame.
+    ///
+    /// Frames outside `stack` are not walked.
+    pub fn from_trap_frame(tf: &TrapFrame, stack: Range<usize>) -> Self {
+        let (pc, fp) = trap_frame_regs(tf);
+        Self {
+            first_pc: Some(pc),
+            fp,
+            stack,
+            depth: 0,
+        }
+    }
+
+    /// Creates a backtrace of the caller, starting from its return address.
+    ///
+    /// Frames outside `stack` are not walked.
+    #[inline(always)]
+    pub fn current(stack: Range<usize>) -> Self {
+        Self {
+            first_pc: None,
+            fp: current_fp(),
+            stack,
+            depth: 0,
+        }
+    }
+
+    /// Returns an object that implements [`fmt::Display`] for printing the
+    /// backtrace, with addresses symbolized by `symbolizer` if given.
+    pub fn display(self, symbolizer: Option<Symbolizer>) -> BacktraceDisplay {
+        BacktraceDisplay {
+            backtrace: self,
+            symbolizer,
+        }
+    }
+
+    /// Whether the frame record at `fp` lies in the stack.
+    fn is_valid_fp(&self, fp: usize) -> bool {
+        let lo = fp.checked_add_signed(FP_OFFSET.min(RA_OFFSET));
+        let hi = fp.checked_add_signed(FP_OFFSET.max(RA_OFFSET) + WORD_SIZE as isize);
+        match (lo, hi) {
+            (Some(lo), Some(hi)) => {
+                fp.is_multiple_of(WORD_SIZE) && lo >= self.stack.start && hi <= self.stack.end
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Iterator for Backtrace {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if let Some(pc) = self.first_pc.take() {
+            return Some(Frame { pc, fp: self.fp });
+        }
+        if self.depth >= MAX_DEPTH || !self.is_valid_fp(self.fp) {
+            return None;
+        }
+        let fp = self.fp;
+        let (ra, prev_fp) = unsafe {
+            (
+                (fp.wrapping_add_signed(RA_OFFSET) as *const usize).read_volatile(),
+                (fp.wrapping_add_signed(FP_OFFSET) as *const usize).read_volatile(),
+            )
+        };
+        if ra == 0 {
+            return None;
+        }
+        // The stack grows downwards, so outer frames must be at higher
+        // addresses. Stop at a loop in the chain.
+        self.fp = if prev_fp > fp { prev_fp } else { 0 };
+        self.depth += 1;
+        Some(Frame { pc: ra, fp })
+    }
+}
+
+/// Helper struct for printing a [`Backtrace`] with `format!` and `{}`.
+pub struct BacktraceDisplay {
+    backtrace: Backtrace,
+    symbolizer: Option<Symbolizer>,
+}
+
+impl fmt::Display for BacktraceDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Backtrace:")?;
+        for (i, frame) in self.backtrace.clone().enumerate() {
+            write!(f, "\n  #{:<2} {:#018x}", i, frame.pc)?;
+            if let Some((name, offset)) = self.symbolizer.and_then(|s| s(frame.pc)) {
+                write!(f, " {}+{:#x}", name, offset)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+static STACK_BOUNDS_FN: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+static SYMBOLIZER: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Sets the function that returns the bounds of the current kernel stack,
+/// which is used to print backtraces in the panics of unhandled traps.
+///
+/// If it is not set, such backtraces only contain the trapped frame.
+pub fn set_stack_bounds_fn(f: fn() -> Range<usize>) {
+    STACK_BOUNDS_FN.store(f as *mut (), Ordering::Release);
+}
+
+/// Sets the symbolizer used to print backtraces in the panics of unhandled
+/// traps.
+pub fn set_symbolizer(f: Symbolizer) {
+    SYMBOLIZER.store(f as *mut (), Ordering::Release);
+}
+
+/// Returns the backtrace of the trap frame to be printed in the panics of
+/// unhandled traps.
+#[allow(dead_code)]
+pub(crate) fn trap_backtrace(tf: &TrapFrame) -> BacktraceDisplay {
+    let stack = match STACK_BOUNDS_FN.load(Ordering::Acquire) {
+        f if f.is_null() => 0..0,
+        f => unsafe { core::mem::transmute::<*mut (), fn() -> Range<usize>>(f)() },
+    };
+    let symbolizer = match SYMBOLIZER.load(Ordering::Acquire) {
+        f if f.is_null() => None,
+        f => Some(unsafe { core::mem::transmute::<*mut (), Symbolizer>(f) }),
+    };
+    Backtrace::from_trap_frame(tf, stack).display(symbolizer)
+}
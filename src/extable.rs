@@ -0,0 +1,107 @@
+//! Kernel exception fixup table.
+//!
+//! Kernel instructions that may fault, e.g., those accessing user memory, can
+//! be annotated with a *fixup* address by
+//! [`asm_extable!`](crate::asm_extable). When such an instruction faults in
+//! kernel mode and the fault is not resolved by any
+//! [`PAGE_FAULT`](crate::trap::PAGE_FAULT) handler, the trap handler resumes
+//! execution at the fixup address instead of panicking.
+//!
+//! ```ignore
+//! /// Reads a byte from `ptr`, or returns `None` on fault.
+//! unsafe fn read_byte(ptr: *const u8) -> Option<u8> {
+//!     let val: u8;
+//!     let ok: usize;
+//!     core::arch::asm!(
+//!         "2: ldrb {val:w}, [{ptr}]",
+//!         "   mov {ok}, #1",
+//!         "3:",
+//!         axcpu::asm_extable!("2b", "3b"),
+//!         ptr = in(reg) ptr,
+//!         val = out(reg) val,
+//!         ok = inout(reg) 0usize => ok,
+//!     );
+//!     (ok != 0).then_some(val)
+//! }
+//! ```
+
+/// An entry of the exception table.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ExceptionTableEntry {
+    /// Address of the instruction that may fault.
+    pub insn: usize,
+    /// Address to resume execution at when the instruction faults.
+    pub fixup: usize,
+}
+
+unsafe extern "C" {
+    static __start_axcpu_extable: ExceptionTableEntry;
+    static __stop_axcpu_extable: ExceptionTableEntry;
+}
+
+// Make sure the section always exists, so that the linker defines the start
+// and stop symbols even if there is no entry.
+#[used]
+#[unsafe(link_section = "axcpu_extable")]
+static EXTABLE_PLACEHOLDER: [ExceptionTableEntry; 0] = [];
+
+/// Emits an entry of the exception table in inline or global assembly.
+///
+/// Both arguments are string literals of assembly symbols or local labels,
+/// for example `asm_extable!("2b", "3f")`.
+#[cfg(target_pointer_width = "64")]
+#[macro_export]
+macro_rules! asm_extable {
+    ($insn:literal, $fixup:literal) => {
+        concat!(
+            "\n.pushsection axcpu_extable, \"a\"\n",
+            ".balign 8\n",
+            ".quad ",
+            $insn,
+            ", ",
+            $fixup,
+            "\n",
+            ".popsection\n",
+        )
+    };
+}
+
+/// Emits an entry of the exception table in inline or global assembly.
+///
+/// Both arguments are string literals of assembly symbols or local labels,
+/// for example `asm_extable!("2b", "3f")`.
+#[cfg(target_pointer_width = "32")]
+#[macro_export]
+macro_rules! asm_extable {
+    ($insn:literal, $fixup:literal) => {
+        concat!(
+            "\n.pushsection axcpu_extable, \"a\"\n",
+            ".balign 4\n",
+            ".long ",
+            $insn,
+            ", ",
+            $fixup,
+            "\n",
+            ".popsection\n",
+        )
+    };
+}
+
+/// Returns all entries of the exception table.
+pub fn exception_table() -> &'static [ExceptionTableEntry] {
+    unsafe {
+        let start = &raw const __start_axcpu_extable;
+        let stop = &raw const __stop_axcpu_extable;
+        core::slice::from_raw_parts(start, stop.offset_from(start) as usize)
+    }
+}
+
+/// Searches the exception table for the fixup address of the instruction at
+/// `pc`.
+pub fn search_exception_table(pc: usize) -> Option<usize> {
+    exception_table()
+        .iter()
+        .find(|entry| entry.insn == pc)
+        .map(|entry| entry.fixup)
+}
@@ -0,0 +1,669 @@
+//! A stub of the GDB remote serial protocol (RSP).
+//!
+//! The stub talks to GDB over a byte channel provided by the kernel, e.g., a
+//! UART, and is entered from trap handlers. While it is entered, the trapped
+//! code is stopped and GDB can read and write its registers (mapped from the
+//! [`TrapFrame`]) and memory, insert and remove software breakpoints, and
+//! resume or single-step the execution.
+//!
+//! Memory is accessed with the fault-safe primitives used for user memory, so
+//! an invalid address requested by GDB results in an error reply instead of a
+//! kernel panic.
+//!
+//! ```ignore
+//! use axcpu::gdbstub::{Connection, GdbStub};
+//! use axcpu::trap::{register_trap_handler, BreakpointHandler, SingleStepHandler, TrapHandler};
+//! use axcpu::trap::{BREAKPOINT, SINGLE_STEP};
+//!
+//! static STUB: SpinNoIrq<GdbStub<Uart>> = SpinNoIrq::new(GdbStub::new(Uart));
+//!
+//! #[register_trap_handler(BREAKPOINT)]
+//! static GDB_BREAKPOINT: TrapHandler<BreakpointHandler> =
+//!     TrapHandler::new(100, |tf| STUB.lock().handle_breakpoint(tf));
+//!
+//! #[register_trap_handler(SINGLE_STEP)]
+//! static GDB_SINGLE_STEP: TrapHandler<SingleStepHandler> =
+//!     TrapHandler::new(100, |tf| STUB.lock().handle_single_step(tf));
+//! ```
+
+use crate::uaccess::{copy_from_user, copy_to_user};
+use crate::{gdb as arch, TrapFrame};
+
+/// A byte channel to GDB.
+pub trait Connection {
+    /// Reads a byte, blocking until one is available.
+    fn read_byte(&mut self) -> u8;
+
+    /// Writes a byte.
+    fn write_byte(&mut self, byte: u8);
+
+    /// Flushes the written bytes.
+    fn flush(&mut self) {}
+}
+
+/// Maximum size of a packet, excluding the framing characters and checksum.
+const MAX_PACKET_SIZE: usize = 0x1000;
+
+/// Maximum number of software breakpoints.
+const MAX_BREAKPOINTS: usize = 32;
+
+/// Signal number reported to GDB for traps.
+const SIGTRAP: u8 = 5;
+
+/// Error numbers in error replies, following the errno values of GDB's File-I/O
+/// extension.
+///
+/// `EPERM` is replied to memory accesses whose range wraps around the end of
+/// the address space.
+const EPERM: u8 = 1;
+const EFAULT: u8 = 14;
+const EINVAL: u8 = 22;
+const ENOSPC: u8 = 28;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// A software breakpoint inserted by GDB.
+#[derive(Debug, Clone, Copy)]
+struct SwBreakpoint {
+    addr: usize,
+    len: usize,
+    /// The original instruction bytes.
+    saved: [u8; 4],
+}
+
+/// The reply to a packet, which is built in place.
+struct Reply {
+    buf: [u8; MAX_PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn push(&mut self, byte: u8) {
+        if self.len < self.buf.len() {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        s.bytes().for_each(|b| self.push(b));
+    }
+
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.push(HEX_DIGITS[(b >> 4) as usize]);
+            self.push(HEX_DIGITS[(b & 0xf) as usize]);
+        }
+    }
+
+    fn ok(&mut self) {
+        self.push_str("OK");
+    }
+
+    fn error(&mut self, errno: u8) {
+        self.push(b'E');
+        self.push_hex(&[errno]);
+    }
+}
+
+/// What to do after a packet is handled.
+enum Action {
+    /// Sends the reply and waits for the next packet.
+    Reply,
+    /// Sends the reply (if not empty) and resumes the execution.
+    Resume,
+}
+
+/// A GDB remote serial protocol stub.
+pub struct GdbStub<C> {
+    conn: C,
+    breakpoints: [Option<SwBreakpoint>; MAX_BREAKPOINTS],
+    packet: [u8; MAX_PACKET_SIZE],
+    reply: Reply,
+    signal: u8,
+    stepping: bool,
+}
+
+impl<C: Connection> GdbStub<C> {
+    /// Creates a new stub that talks to GDB over `conn`.
+    pub const fn new(conn: C) -> Self {
+        Self {
+            conn,
+            breakpoints: [None; MAX_BREAKPOINTS],
+            packet: [0; MAX_PACKET_SIZE],
+            reply: Reply {
+                buf: [0; MAX_PACKET_SIZE],
+                len: 0,
+            },
+            signal: SIGTRAP,
+            stepping: false,
+        }
+    }
+
+    /// Handles a breakpoint trap.
+    ///
+    /// If the breakpoint is inserted by GDB, it enters the stub and returns
+    /// `true` after GDB resumes the execution. Otherwise, it returns `false`
+    /// so that the breakpoint can be passed to other handlers.
+    pub fn handle_breakpoint(&mut self, tf: &mut TrapFrame) -> bool {
+        let addr = arch::breakpoint_addr(tf);
+        if !self.breakpoints.iter().flatten().any(|bp| bp.addr == addr) {
+            return false;
+        }
+        arch::set_pc(tf, addr);
+        self.enter(tf, SIGTRAP);
+        true
+    }
+
+    /// Handles a single-step trap.
+    ///
+    /// If the step is requested by GDB, it enters the stub and returns `true`
+    /// after GDB resumes the execution. Otherwise, it returns `false` so that
+    /// the trap can be passed to other handlers.
+    pub fn handle_single_step(&mut self, tf: &mut TrapFrame) -> bool {
+        if !self.stepping {
+            return false;
+        }
+        self.stepping = false;
+        self.enter(tf, SIGTRAP);
+        true
+    }
+
+    /// Stops the trapped code, reports `signal` to GDB, and serves the
+    /// requests of GDB until it resumes the execution.
+    ///
+    /// It can be called from any trap handler, e.g., to wait for GDB to attach
+    /// or to report a fault.
+    pub fn enter(&mut self, tf: &mut TrapFrame, signal: u8) {
+        self.signal = signal;
+        self.reply.len = 0;
+        self.reply.push(b'S');
+        self.reply.push_hex(&[signal]);
+        self.send_reply();
+        loop {
+            let len = self.recv_packet();
+            self.reply.len = 0;
+            let action = self.handle_packet(tf, len);
+            if let Action::Resume = action {
+                if self.reply.len != 0 {
+                    self.send_reply();
+                }
+                break;
+            }
+            self.send_reply();
+        }
+    }
+
+    /// Receives a packet into `self.packet`, and returns its length.
+    fn recv_packet(&mut self) -> usize {
+        loop {
+            // Skip anything before the start of a packet, e.g., acks and
+            // interrupt requests.
+            while self.conn.read_byte() != b'$' {}
+
+            let mut len = 0;
+            let mut checksum = 0u8;
+            let mut overflow = false;
+            loop {
+                match self.conn.read_byte() {
+                    b'#' => break,
+                    byte => {
+                        checksum = checksum.wrapping_add(byte);
+                        if len < self.packet.len() {
+                            self.packet[len] = byte;
+                            len += 1;
+                        } else {
+                            overflow = true;
+                        }
+                    }
+                }
+            }
+            let hi = hex_value(self.conn.read_byte());
+            let lo = hex_value(self.conn.read_byte());
+            if !overflow && hi.zip(lo).map(|(hi, lo)| hi << 4 | lo) == Some(checksum) {
+                self.conn.write_byte(b'+');
+                self.conn.flush();
+                return len;
+            }
+            self.conn.write_byte(b'-');
+            self.conn.flush();
+        }
+    }
+
+    /// Sends `self.reply` as a packet, and retransmits it until GDB
+    /// acknowledges it.
+    fn send_reply(&mut self) {
+        let data = &self.reply.buf[..self.reply.len];
+        let checksum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        loop {
+            self.conn.write_byte(b'$');
+            data.iter().for_each(|&b| self.conn.write_byte(b));
+            self.conn.write_byte(b'#');
+            self.conn.write_byte(HEX_DIGITS[(checksum >> 4) as usize]);
+            self.conn.write_byte(HEX_DIGITS[(checksum & 0xf) as usize]);
+            self.conn.flush();
+            loop {
+                match self.conn.read_byte() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Handles the packet in `self.packet[..len]`, building the reply in
+    /// `self.reply`.
+    fn handle_packet(&mut self, tf: &mut TrapFrame, len: usize) -> Action {
+        let packet = &self.packet[..len];
+        let reply = &mut self.reply;
+        let Some((&cmd, args)) = packet.split_first() else {
+            return Action::Reply;
+        };
+        match cmd {
+            b'?' => {
+                reply.push(b'S');
+                reply.push_hex(&[self.signal]);
+            }
+            b'g' => {
+                for n in 0..arch::NUM_REGS {
+                    let (val, size) = arch::read_reg(tf, n).unwrap();
+                    push_reg(reply, val, size);
+                }
+            }
+            b'G' => {
+                let mut args = args;
+                for n in 0..arch::NUM_REGS {
+                    let (_, size) = arch::read_reg(tf, n).unwrap();
+                    let Some(val) = args.get(..size * 2).and_then(parse_reg) else {
+                        break;
+                    };
+                    arch::write_reg(tf, n, val);
+                    args = &args[size * 2..];
+                }
+                reply.ok();
+            }
+            b'p' => match parse_hex(args).and_then(|n| arch::read_reg(tf, n)) {
+                Some((val, size)) => push_reg(reply, val, size),
+                None => reply.error(EINVAL),
+            },
+            b'P' => {
+                let written = split_at_byte(args, b'=')
+                    .and_then(|(n, val)| Some(arch::write_reg(tf, parse_hex(n)?, parse_reg(val)?)));
+                match written {
+                    Some(true) => reply.ok(),
+                    _ => reply.error(EINVAL),
+                }
+            }
+            b'm' => match parse_addr_len(args) {
+                Some((addr, len)) => {
+                    // The reply holds at most `MAX_PACKET_SIZE / 2` bytes in
+                    // hex digits.
+                    let len = len.min(MAX_PACKET_SIZE / 2);
+                    if range_wraps(addr, len) {
+                        reply.error(EPERM);
+                    } else {
+                        read_memory(reply, addr, len);
+                    }
+                }
+                None => reply.error(EINVAL),
+            },
+            b'M' => {
+                let parsed = split_at_byte(args, b':')
+                    .and_then(|(range, data)| Some((parse_addr_len(range)?, data)));
+                match parsed {
+                    // The data is in the packet, so `len` is clamped to the
+                    // packet size before it is used.
+                    Some(((addr, len), data))
+                        if len <= MAX_PACKET_SIZE / 2 && len.checked_mul(2) == Some(data.len()) =>
+                    {
+                        if range_wraps(addr, len) {
+                            reply.error(EPERM);
+                        } else if write_memory(addr, data) {
+                            arch::flush_icache(addr, len);
+                            reply.ok();
+                        } else {
+                            reply.error(EFAULT);
+                        }
+                    }
+                    _ => reply.error(EINVAL),
+                }
+            }
+            b'Z' | b'z' => {
+                // Only software breakpoints (type 0) are supported. Reply
+                // nothing for the others.
+                if let Some(args) = args.strip_prefix(b"0,") {
+                    match parse_addr_len(args) {
+                        Some((addr, kind)) if cmd == b'Z' => {
+                            self.insert_breakpoint(addr, kind);
+                        }
+                        Some((addr, _)) => self.remove_breakpoint(addr),
+                        None => self.reply.error(EINVAL),
+                    }
+                }
+            }
+            b'c' => {
+                if let Some(addr) = parse_hex(args) {
+                    arch::set_pc(tf, addr);
+                }
+                return Action::Resume;
+            }
+            b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    arch::set_pc(tf, addr);
+                }
+                if !crate::debug::set_single_step(tf, true) {
+                    reply.error(EINVAL);
+                    return Action::Reply;
+                }
+                self.stepping = true;
+                return Action::Resume;
+            }
+            b'D' => {
+                self.remove_all_breakpoints();
+                self.reply.ok();
+                return Action::Resume;
+            }
+            b'k' => {
+                self.remove_all_breakpoints();
+                return Action::Resume;
+            }
+            b'H' => reply.ok(),
+            b'q' if args.starts_with(b"Supported") => {
+                reply.push_str("PacketSize=");
+                push_hex_usize(reply, MAX_PACKET_SIZE);
+            }
+            b'q' if args == b"Attached" => reply.push(b'1'),
+            // Unsupported packets are replied with an empty packet.
+            _ => {}
+        }
+        Action::Reply
+    }
+
+    fn insert_breakpoint(&mut self, addr: usize, kind: usize) {
+        let reply = &mut self.reply;
+        if self.breakpoints.iter().flatten().any(|bp| bp.addr == addr) {
+            return reply.ok();
+        }
+        let Some(insn) = arch::breakpoint_insn(kind) else {
+            return reply.error(EINVAL);
+        };
+        let Some(slot) = self.breakpoints.iter_mut().find(|bp| bp.is_none()) else {
+            return reply.error(ENOSPC);
+        };
+        let mut bp = SwBreakpoint {
+            addr,
+            len: insn.len(),
+            saved: [0; 4],
+        };
+        unsafe {
+            if copy_from_user(&mut bp.saved[..bp.len], addr as _) != 0
+                || copy_to_user(addr as _, insn) != 0
+            {
+                return reply.error(EFAULT);
+            }
+        }
+        arch::flush_icache(addr, bp.len);
+        *slot = Some(bp);
+        reply.ok();
+    }
+
+    fn remove_breakpoint(&mut self, addr: usize) {
+        let slot = self
+            .breakpoints
+            .iter_mut()
+            .find(|bp| bp.is_some_and(|bp| bp.addr == addr));
+        match slot {
+            Some(slot) => {
+                restore_breakpoint(&slot.take().unwrap());
+                self.reply.ok();
+            }
+            None => self.reply.error(EINVAL),
+        }
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(bp) = slot.take() {
+                restore_breakpoint(&bp);
+            }
+        }
+    }
+}
+
+/// Writes back the original instruction of a breakpoint.
+fn restore_breakpoint(bp: &SwBreakpoint) {
+    unsafe { copy_to_user(bp.addr as _, &bp.saved[..bp.len]) };
+    arch::flush_icache(bp.addr, bp.len);
+}
+
+/// Whether the memory range of `len` bytes at `addr` wraps around the end of
+/// the address space.
+fn range_wraps(addr: usize, len: usize) -> bool {
+    len != 0 && addr.checked_add(len - 1).is_none()
+}
+
+/// Reads `len` bytes of memory at `addr` into the reply as hex digits.
+///
+/// Fewer bytes are returned if a fault occurs in the middle.
+fn read_memory(reply: &mut Reply, addr: usize, len: usize) {
+    let len = len.min(MAX_PACKET_SIZE / 2);
+    let mut chunk = [0u8; 64];
+    let mut done = 0;
+    while done < len {
+        let n = (len - done).min(chunk.len());
+        let Some(src) = addr.checked_add(done) else {
+            break;
+        };
+        let left = unsafe { copy_from_user(&mut chunk[..n], src as _) };
+        reply.push_hex(&chunk[..n - left]);
+        done += n - left;
+        if left != 0 {
+            break;
+        }
+    }
+    if done == 0 && len != 0 {
+        reply.error(EFAULT);
+    }
+}
+
+/// Writes the bytes in hex digits `data` to memory at `addr`. Returns `false`
+/// if the data is malformed or a fault occurs.
+fn write_memory(addr: usize, data: &[u8]) -> bool {
+    let mut chunk = [0u8; 64];
+    for (i, hex) in data.chunks(chunk.len() * 2).enumerate() {
+        let n = hex.len() / 2;
+        for (byte, digits) in chunk.iter_mut().zip(hex.chunks(2)) {
+            match parse_hex(digits) {
+                Some(b) => *byte = b as u8,
+                None => return false,
+            }
+        }
+        let Some(dst) = addr.checked_add(i * chunk.len()) else {
+            return false;
+        };
+        if unsafe { copy_to_user(dst as _, &chunk[..n]) } != 0 {
+            return false;
+        }
+    }
+    true
+}
+
+/// Appends a register value to the reply in target (little-endian) byte order.
+fn push_reg(reply: &mut Reply, val: u64, size: usize) {
+    let bytes = val.to_le_bytes();
+    for i in 0..size {
+        reply.push_hex(&[bytes.get(i).copied().unwrap_or(0)]);
+    }
+}
+
+fn push_hex_usize(reply: &mut Reply, val: usize) {
+    let digits = (usize::BITS - val.leading_zeros()).div_ceil(4).max(1);
+    for i in (0..digits).rev() {
+        reply.push(HEX_DIGITS[(val >> (i * 4)) & 0xf]);
+    }
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Parses a big-endian hex number.
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > size_of::<usize>() * 2 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0, |val, &b| Some(val << 4 | hex_value(b)? as usize))
+}
+
+/// Parses a register value in target (little-endian) byte order. Bytes beyond
+/// 64 bits are ignored.
+fn parse_reg(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || !s.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    for (i, digits) in s.chunks(2).enumerate() {
+        let byte = parse_hex(digits)? as u8;
+        if let Some(b) = bytes.get_mut(i) {
+            *b = byte;
+        }
+    }
+    Some(u64::from_le_bytes(bytes))
+}
+
+/// Parses `addr,len` in hex.
+fn parse_addr_len(s: &[u8]) -> Option<(usize, usize)> {
+    let (addr, len) = split_at_byte(s, b',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+fn split_at_byte(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let pos = s.iter().position(|&b| b == sep)?;
+    Some((&s[..pos], &s[pos + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// A connection that reads from a given input and records the output.
+    #[derive(Default)]
+    struct MockConn {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Connection for MockConn {
+        fn read_byte(&mut self) -> u8 {
+            self.input.pop_front().expect("no more input")
+        }
+
+        fn write_byte(&mut self, byte: u8) {
+            self.output.push(byte);
+        }
+    }
+
+    fn stub(input: &[u8]) -> GdbStub<MockConn> {
+        GdbStub::new(MockConn {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        })
+    }
+
+    /// Handles `packet` and returns the reply.
+    fn handle(packet: &[u8]) -> Vec<u8> {
+        let mut stub = stub(b"");
+        stub.packet[..packet.len()].copy_from_slice(packet);
+        stub.handle_packet(&mut TrapFrame::default(), packet.len());
+        stub.reply.buf[..stub.reply.len].to_vec()
+    }
+
+    #[test]
+    fn recv_packet() {
+        let mut stub = stub(b"+$?#3f");
+        let len = stub.recv_packet();
+        assert_eq!(&stub.packet[..len], b"?");
+        assert_eq!(stub.conn.output, b"+");
+    }
+
+    #[test]
+    fn recv_packet_bad_checksum() {
+        // The first packet is rejected and retransmitted.
+        let mut stub = stub(b"$?#3e$?#xx$?#3f");
+        let len = stub.recv_packet();
+        assert_eq!(&stub.packet[..len], b"?");
+        assert_eq!(stub.conn.output, b"--+");
+    }
+
+    #[test]
+    fn recv_packet_too_long() {
+        let mut input = vec![b'$'];
+        input.extend(core::iter::repeat_n(b'0', MAX_PACKET_SIZE + 1));
+        let checksum = (b'0' as usize * (MAX_PACKET_SIZE + 1)) as u8;
+        input.push(b'#');
+        input.extend([
+            HEX_DIGITS[checksum as usize >> 4],
+            HEX_DIGITS[checksum as usize & 0xf],
+        ]);
+        input.extend(b"$?#3f");
+        let mut stub = stub(&input);
+        let len = stub.recv_packet();
+        assert_eq!(&stub.packet[..len], b"?");
+        assert_eq!(stub.conn.output, b"-+");
+    }
+
+    #[test]
+    fn send_reply() {
+        // The reply is retransmitted until it is acknowledged.
+        let mut stub = stub(b"-+");
+        stub.reply.ok();
+        stub.send_reply();
+        assert_eq!(stub.conn.output, b"$OK#9a$OK#9a");
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_hex(b"1f"), Some(0x1f));
+        assert_eq!(parse_hex(b"ffffffffffffffff"), Some(usize::MAX));
+        assert_eq!(parse_hex(b"10000000000000000"), None);
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"1g"), None);
+
+        assert_eq!(parse_reg(b"0102"), Some(0x0201));
+        assert_eq!(parse_reg(b"010"), None);
+        assert_eq!(parse_reg(b""), None);
+        assert_eq!(parse_reg(b"0x"), None);
+
+        assert_eq!(parse_addr_len(b"1000,4"), Some((0x1000, 4)));
+        assert_eq!(parse_addr_len(b"1000"), None);
+        assert_eq!(parse_addr_len(b"1000,"), None);
+    }
+
+    #[test]
+    fn memory_range() {
+        assert!(!range_wraps(0, 0));
+        assert!(!range_wraps(usize::MAX, 0));
+        assert!(!range_wraps(usize::MAX, 1));
+        assert!(!range_wraps(usize::MAX - 0xf, 0x10));
+        assert!(range_wraps(usize::MAX, 2));
+        assert!(range_wraps(usize::MAX - 0xf, 0x11));
+    }
+
+    #[test]
+    fn memory_packets_with_bad_ranges() {
+        // Ranges that wrap around the end of the address space.
+        assert_eq!(handle(b"mffffffffffffffff,2"), b"E01");
+        assert_eq!(handle(b"Mffffffffffffffff,2:abcd"), b"E01");
+        // Oversized lengths are clamped before the range is checked.
+        assert_eq!(handle(b"mfffffffffffffc00,ffffffffffffffff"), b"E01");
+        assert_eq!(handle(b"M1000,801:abcd"), b"E16");
+        assert_eq!(handle(b"M1000,ffffffffffffffff:abcd"), b"E16");
+        // Malformed packets.
+        assert_eq!(handle(b"M1000,2:abc"), b"E16");
+        assert_eq!(handle(b"m1000"), b"E16");
+    }
+}
@@ -0,0 +1,224 @@
+//! GICv3/v4 CPU interface.
+//!
+//! The CPU interface is accessed through the system registers (`ICC_*`), and
+//! only Group 1 interrupts are used. The distributor and the redistributors
+//! are memory-mapped and should be initialized by the platform code.
+//!
+//! With the `gicv3` feature, the IRQ path acknowledges the interrupt by
+//! [`ack`], passes its INTID to the [`IRQ`](crate::trap::IRQ) handlers, and
+//! then signals the end of the interrupt by [`eoi`], which also deactivates
+//! it. Spurious interrupts are not passed to the handlers.
+
+/// The first INTID of Software Generated Interrupts (SGIs).
+pub const SGI_START: u32 = 0;
+/// The first INTID of Private Peripheral Interrupts (PPIs).
+pub const PPI_START: u32 = 16;
+/// The first INTID of Shared Peripheral Interrupts (SPIs).
+pub const SPI_START: u32 = 32;
+/// Special INTIDs, which are not real interrupts, e.g., 1023 for spurious
+/// interrupts. INTIDs after them are real ones, e.g., extended PPIs and SPIs.
+const SPECIAL_INTIDS: core::ops::RangeInclusive<u32> = 1020..=1023;
+/// The first INTID of Locality-specific Peripheral Interrupts (LPIs).
+pub const LPI_START: u32 = 8192;
+
+/// `ICC_SRE.SRE`: the system register interface is enabled.
+const ICC_SRE_SRE: u64 = 1 << 0;
+/// `ICC_SRE_EL2.Enable`: EL1 accesses to `ICC_SRE_EL1` are permitted.
+#[cfg(all(target_arch = "aarch64", feature = "arm-el2"))]
+const ICC_SRE_ENABLE: u64 = 1 << 3;
+/// `ICC_CTLR.EOImode`: `ICC_EOIR1` only drops the priority, and `ICC_DIR`
+/// deactivates the interrupt.
+const ICC_CTLR_EOIMODE: u64 = 1 << 1;
+/// `ICC_SGI1R.IRM`: the SGI is routed to all PEs except the current one.
+const ICC_SGI1R_IRM: u64 = 1 << 40;
+
+/// Priority mask that allows interrupts of all priorities.
+const DEFAULT_PMR: u64 = 0xff;
+
+#[cfg(target_arch = "aarch64")]
+mod regs {
+    use core::arch::asm;
+
+    use aarch64_cpu::asm::barrier;
+
+    macro_rules! read_sysreg {
+        ($fn:ident, $name:literal) => {
+            pub fn $fn() -> u64 {
+                let value: u64;
+                unsafe { asm!(concat!("mrs {}, ", $name), out(reg) value) };
+                value
+            }
+        };
+    }
+
+    macro_rules! write_sysreg {
+        ($fn:ident, $name:literal) => {
+            pub fn $fn(value: u64) {
+                unsafe { asm!(concat!("msr ", $name, ", {}"), in(reg) value) };
+            }
+        };
+    }
+
+    write_sysreg!(write_pmr, "S3_0_C4_C6_0"); // ICC_PMR_EL1
+    read_sysreg!(read_iar1, "S3_0_C12_C12_0"); // ICC_IAR1_EL1
+    write_sysreg!(write_eoir1, "S3_0_C12_C12_1"); // ICC_EOIR1_EL1
+    write_sysreg!(write_bpr1, "S3_0_C12_C12_3"); // ICC_BPR1_EL1
+    read_sysreg!(read_ctlr, "S3_0_C12_C12_4"); // ICC_CTLR_EL1
+    write_sysreg!(write_ctlr, "S3_0_C12_C12_4");
+    write_sysreg!(write_igrpen1, "S3_0_C12_C12_7"); // ICC_IGRPEN1_EL1
+    write_sysreg!(write_sgi1r, "S3_0_C12_C11_5"); // ICC_SGI1R_EL1
+    #[cfg(not(feature = "arm-el2"))]
+    read_sysreg!(read_sre, "S3_0_C12_C12_5"); // ICC_SRE_EL1
+    #[cfg(not(feature = "arm-el2"))]
+    write_sysreg!(write_sre, "S3_0_C12_C12_5");
+    #[cfg(feature = "arm-el2")]
+    read_sysreg!(read_sre, "S3_4_C12_C9_5"); // ICC_SRE_EL2
+    #[cfg(feature = "arm-el2")]
+    write_sysreg!(write_sre, "S3_4_C12_C9_5");
+
+    pub fn isb() {
+        barrier::isb(barrier::SY);
+    }
+
+    pub fn dsb_sy() {
+        barrier::dsb(barrier::SY);
+    }
+
+    pub fn dsb_ishst() {
+        barrier::dsb(barrier::ISHST);
+    }
+}
+
+#[cfg(target_arch = "arm")]
+mod regs {
+    use core::arch::asm;
+
+    macro_rules! read_sysreg {
+        ($fn:ident, $crn:literal, $crm:literal, $op2:literal) => {
+            pub fn $fn() -> u64 {
+                let value: u32;
+                unsafe {
+                    asm!(
+                        concat!("mrc p15, 0, {}, ", $crn, ", ", $crm, ", ", $op2),
+                        out(reg) value,
+                    )
+                };
+                value as u64
+            }
+        };
+    }
+
+    macro_rules! write_sysreg {
+        ($fn:ident, $crn:literal, $crm:literal, $op2:literal) => {
+            pub fn $fn(value: u64) {
+                unsafe {
+                    asm!(
+                        concat!("mcr p15, 0, {}, ", $crn, ", ", $crm, ", ", $op2),
+                        in(reg) value as u32,
+                    )
+                };
+            }
+        };
+    }
+
+    write_sysreg!(write_pmr, "c4", "c6", "0"); // ICC_PMR
+    read_sysreg!(read_iar1, "c12", "c12", "0"); // ICC_IAR1
+    write_sysreg!(write_eoir1, "c12", "c12", "1"); // ICC_EOIR1
+    write_sysreg!(write_bpr1, "c12", "c12", "3"); // ICC_BPR1
+    read_sysreg!(read_ctlr, "c12", "c12", "4"); // ICC_CTLR
+    write_sysreg!(write_ctlr, "c12", "c12", "4");
+    read_sysreg!(read_sre, "c12", "c12", "5"); // ICC_SRE
+    write_sysreg!(write_sre, "c12", "c12", "5");
+    write_sysreg!(write_igrpen1, "c12", "c12", "7"); // ICC_IGRPEN1
+
+    /// Writes the 64-bit `ICC_SGI1R`.
+    pub fn write_sgi1r(value: u64) {
+        unsafe {
+            asm!(
+                "mcrr p15, 0, {}, {}, c12",
+                in(reg) value as u32,
+                in(reg) (value >> 32) as u32,
+            )
+        };
+    }
+
+    pub use aarch32_cpu::asm::{dsb as dsb_sy, dsb as dsb_ishst, isb};
+}
+
+/// Target PEs of an SGI sent by [`send_sgi`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgiTarget {
+    /// The PE with the given affinity value, in the format of `MPIDR`.
+    Mpidr(u64),
+    /// All PEs except the current one.
+    AllOthers,
+}
+
+/// Initializes the GIC CPU interface on the current CPU.
+///
+/// It enables the system register interface, unmasks interrupts of all
+/// priorities, and enables Group 1 interrupts. The EOI mode is set so that
+/// [`eoi`] also deactivates the interrupt.
+pub fn init() {
+    #[cfg(all(target_arch = "aarch64", feature = "arm-el2"))]
+    regs::write_sre(regs::read_sre() | ICC_SRE_SRE | ICC_SRE_ENABLE);
+    #[cfg(not(all(target_arch = "aarch64", feature = "arm-el2")))]
+    regs::write_sre(regs::read_sre() | ICC_SRE_SRE);
+    regs::isb();
+    if regs::read_sre() & ICC_SRE_SRE == 0 {
+        warn!("GICv3 system register interface cannot be enabled");
+        return;
+    }
+
+    regs::write_pmr(DEFAULT_PMR);
+    regs::write_bpr1(0);
+    regs::write_ctlr(regs::read_ctlr() & !ICC_CTLR_EOIMODE);
+    regs::write_igrpen1(1);
+    regs::isb();
+}
+
+/// Acknowledges the highest priority pending Group 1 interrupt, and returns its
+/// INTID, or [`None`] if the interrupt is spurious.
+///
+/// The interrupt becomes active, and [`eoi`] must be called with its INTID
+/// after it is handled.
+#[inline]
+pub fn ack() -> Option<u32> {
+    let intid = (regs::read_iar1() & 0xff_ffff) as u32;
+    // Make sure the reads of device states are after the acknowledgement.
+    regs::dsb_sy();
+    if SPECIAL_INTIDS.contains(&intid) {
+        None
+    } else {
+        Some(intid)
+    }
+}
+
+/// Signals the end of the interrupt acknowledged by [`ack`], which drops the
+/// running priority and deactivates the interrupt.
+#[inline]
+pub fn eoi(intid: u32) {
+    regs::write_eoir1(intid as u64);
+    regs::isb();
+}
+
+/// Sends the SGI with the given INTID (`0..16`) to the target PEs.
+///
+/// Memory writes before the SGI are visible to the target PEs when they
+/// receive it.
+pub fn send_sgi(intid: u32, target: SgiTarget) {
+    assert!(intid < PPI_START, "invalid SGI number {intid}");
+    let sgi1r = match target {
+        SgiTarget::Mpidr(mpidr) => {
+            let aff0 = mpidr & 0xff;
+            let aff1 = (mpidr >> 8) & 0xff;
+            let aff2 = (mpidr >> 16) & 0xff;
+            let aff3 = (mpidr >> 32) & 0xff;
+            (aff3 << 48) | (aff2 << 32) | ((aff0 >> 4) << 44) | (aff1 << 16) | (1 << (aff0 & 0xf))
+        }
+        SgiTarget::AllOthers => ICC_SGI1R_IRM,
+    } | ((intid as u64) << 24);
+    regs::dsb_ishst();
+    regs::write_sgi1r(sgi1r);
+    regs::isb();
+}
@@ -0,0 +1,100 @@
+//! Architecture-independent structures for hardware breakpoints.
+
+use crate::debug::{hw_slot_supports, install_hw_breakpoints, NUM_HW_SLOTS};
+
+/// The kind of accesses that trigger a hardware breakpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HwBreakpointKind {
+    /// Instruction execution, i.e., a breakpoint.
+    Execute,
+    /// Data reads, i.e., a read watchpoint.
+    Read,
+    /// Data writes, i.e., a write watchpoint.
+    Write,
+    /// Data reads and writes, i.e., an access watchpoint.
+    ReadWrite,
+}
+
+/// A hardware breakpoint or watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HwBreakpoint {
+    /// Address of the instruction or data to watch.
+    pub addr: usize,
+    /// Length of the watched data in bytes, which must be 1, 2, 4 or 8, and
+    /// `addr` must be aligned to it. It is ignored for
+    /// [`Execute`](HwBreakpointKind::Execute) breakpoints.
+    pub len: usize,
+    /// The kind of accesses to watch.
+    pub kind: HwBreakpointKind,
+}
+
+/// The hardware breakpoints of a task.
+///
+/// They are programmed into the debug registers of the CPU when the task is
+/// switched to by [`TaskContext::switch_to`](crate::TaskContext::switch_to).
+/// Hits are reported to the [`HW_BREAKPOINT`](crate::trap::HW_BREAKPOINT)
+/// handlers with the slot returned by [`insert`](Self::insert).
+#[derive(Debug, Clone)]
+pub struct HwBreakpoints {
+    slots: [Option<HwBreakpoint>; NUM_HW_SLOTS],
+}
+
+impl HwBreakpoints {
+    /// Creates an empty set of hardware breakpoints.
+    pub const fn new() -> Self {
+        Self {
+            slots: [None; NUM_HW_SLOTS],
+        }
+    }
+
+    /// Whether no breakpoint is inserted.
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(Option::is_none)
+    }
+
+    /// Returns the breakpoint in the given slot.
+    pub fn get(&self, slot: usize) -> Option<HwBreakpoint> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    /// Inserts a breakpoint into a free slot that supports it.
+    ///
+    /// Returns the slot, or [`None`] if the breakpoint is not supported by the
+    /// hardware or all slots supporting it are used.
+    ///
+    /// It takes effect on the next switch to the task, or immediately after
+    /// [`install`](Self::install) if the task is running.
+    pub fn insert(&mut self, bp: HwBreakpoint) -> Option<usize> {
+        let slot =
+            (0..NUM_HW_SLOTS).find(|&i| self.slots[i].is_none() && hw_slot_supports(i, &bp))?;
+        self.slots[slot] = Some(bp);
+        Some(slot)
+    }
+
+    /// Removes the breakpoint in the given slot and returns it.
+    ///
+    /// Like [`insert`](Self::insert), it takes effect on the next switch to
+    /// the task, or immediately after [`install`](Self::install).
+    pub fn remove(&mut self, slot: usize) -> Option<HwBreakpoint> {
+        self.slots.get_mut(slot)?.take()
+    }
+
+    /// Programs the breakpoints into the debug registers of the current CPU.
+    pub fn install(&self) {
+        install_hw_breakpoints(&self.slots);
+    }
+
+    /// Programs the breakpoints of the next task on context switch, which is
+    /// skipped if neither task has any breakpoint.
+    pub(crate) fn switch_to(&self, next: &Self) {
+        if !self.is_empty() || !next.is_empty() {
+            next.install();
+        }
+    }
+}
+
+impl Default for HwBreakpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
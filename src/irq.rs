@@ -0,0 +1,48 @@
+//! Nestable local IRQ masking.
+
+use core::marker::PhantomData;
+
+use crate::asm::{local_irq_restore, local_irq_save};
+
+/// The interrupt state of the current CPU saved by
+/// [`local_irq_save`](crate::asm::local_irq_save).
+///
+/// It holds the raw bits of the architecture-specific interrupt mask:
+/// `RFLAGS.IF` on x86_64, `DAIF` on aarch64, `sstatus.SIE` on riscv,
+/// `CRMD.IE` on loongarch64 and `CPSR.I` on arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqFlags(pub(crate) usize);
+
+/// A guard that disables local IRQs on creation and restores the saved
+/// interrupt state when dropped.
+///
+/// Guards can be nested: IRQs are enabled again only when the outermost guard
+/// is dropped, if they were enabled before it was created. Guards must be
+/// dropped in the reverse order of creation, and must not be sent to other
+/// CPUs.
+pub struct IrqGuard {
+    flags: IrqFlags,
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl IrqGuard {
+    /// Disables local IRQs and saves the previous interrupt state.
+    pub fn new() -> Self {
+        Self {
+            flags: local_irq_save(),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Default for IrqGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for IrqGuard {
+    fn drop(&mut self) {
+        local_irq_restore(self.flags);
+    }
+}
@@ -0,0 +1,84 @@
+//! FP/SIMD usage in kernel mode.
+//!
+//! The FP/SIMD states are only saved and restored on context switches, so
+//! kernel code (e.g., crypto, checksums or `memcpy`) must not touch the FP/SIMD
+//! registers outside of a [`kernel_fpu_begin`] section, which may hold the
+//! states of the current task.
+//!
+//! On entering the section, the live FP/SIMD states of the current task are
+//! saved to the guard, and the FP/SIMD unit is enabled (`CR0.TS` on x86_64,
+//! `CPACR_EL1.FPEN` on aarch64, `FPEXC.EN` on arm, `sstatus.FS` on riscv and
+//! `EUEN.FPE` on loongarch64). On leaving it, the states and the unit are set
+//! back. Local IRQs are disabled in the section, so that the section is neither
+//! preempted nor interrupted by handlers that may use the FP/SIMD unit.
+//!
+//! Only the FP/SIMD registers used by the kernel are saved to the guard. The
+//! SVE and SME states on aarch64 (with the `sve` feature) are saved to a per-CPU
+//! buffer, so the per-CPU data areas of the [`percpu`] crate must be
+//! initialized. The vector instructions on riscv (with the `rvv` feature) are
+//! disabled by `sstatus.VS` in the section, and trap as illegal instructions.
+//!
+//! [`percpu`]: https://docs.rs/percpu/latest/percpu/index.html
+
+use core::marker::PhantomData;
+
+use crate::IrqFlags;
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        type SavedState = crate::ExtendedState;
+    } else if #[cfg(any(
+        target_arch = "aarch64",
+        target_arch = "riscv32",
+        target_arch = "riscv64"
+    ))] {
+        type SavedState = crate::KernelFpState;
+    } else if #[cfg(target_arch = "loongarch64")] {
+        type SavedState = crate::FpuState;
+    } else {
+        type SavedState = crate::FpState;
+    }
+}
+
+/// A guard of a kernel-mode FP/SIMD section, created by [`kernel_fpu_begin`].
+///
+/// The section ends when the guard is dropped. Guards must be dropped in the
+/// reverse order of creation, and must not be sent to other CPUs.
+pub struct KernelFpuGuard {
+    saved: SavedState,
+    live: bool,
+    irq_flags: IrqFlags,
+    _not_send: PhantomData<*mut ()>,
+}
+
+/// Begins a kernel-mode FP/SIMD section, in which the kernel code can use the
+/// FP/SIMD registers freely.
+///
+/// The returned guard holds the saved FP/SIMD states, which may take a few
+/// kilobytes of the stack on x86_64 for the XSAVE area.
+pub fn kernel_fpu_begin() -> KernelFpuGuard {
+    let irq_flags = crate::asm::local_irq_save();
+    let mut saved = SavedState::default();
+    let live = saved.kernel_fpu_begin();
+    KernelFpuGuard {
+        saved,
+        live,
+        irq_flags,
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for KernelFpuGuard {
+    fn drop(&mut self) {
+        self.saved.kernel_fpu_end(self.live);
+        crate::asm::local_irq_restore(self.irq_flags);
+    }
+}
+
+/// Runs `f` in a kernel-mode FP/SIMD section.
+///
+/// See [`kernel_fpu_begin`] for details.
+pub fn with_kernel_fpu<R>(f: impl FnOnce() -> R) -> R {
+    let _guard = kernel_fpu_begin();
+    f()
+}
@@ -0,0 +1,36 @@
+//! Lazy switching of the FP/SIMD state.
+//!
+//! With the `fp-lazy` feature, the FP/SIMD unit is disabled on context switch
+//! instead of restoring the FP state of the next task. The first FP/SIMD
+//! instruction of the task then traps, and the trap handler enables the unit
+//! and restores the FP state of the current task recorded here. The FP state
+//! of the previous task is saved on context switch only if the unit is enabled,
+//! i.e., the task has used it since it was switched to.
+//!
+//! It is supported on x86_64, aarch64, riscv and loongarch64. The FP state is
+//! switched eagerly on other architectures.
+//!
+//! The current FP state is recorded with the [`percpu`] crate, so the per-CPU
+//! data areas must be initialized before any context switch.
+//!
+//! [`percpu`]: https://docs.rs/percpu/latest/percpu/index.html
+
+#[percpu::def_percpu]
+static CURRENT_FP_STATE: usize = 0;
+
+/// Records the FP state of the task being switched to on the current CPU.
+pub(crate) fn set_current<T>(state: &T) {
+    CURRENT_FP_STATE.write_current(state as *const T as usize);
+}
+
+/// Returns the FP state of the task running on the current CPU, or [`None`]
+/// if no task has been switched to.
+///
+/// # Safety
+///
+/// `T` must be the type of the state passed to [`set_current`]. The returned
+/// reference is valid while the task is running.
+#[cfg_attr(not(target_os = "none"), allow(dead_code))] // only used by trap handlers
+pub(crate) unsafe fn current<'a, T>() -> Option<&'a T> {
+    unsafe { (CURRENT_FP_STATE.read_current() as *const T).as_ref() }
+}
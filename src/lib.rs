@@ -11,9 +11,52 @@ extern crate memory_addr;
 #[macro_use]
 pub mod trap;
 
+pub mod extable;
+
+pub mod backtrace;
+
+mod irq;
+
+pub use self::irq::{IrqFlags, IrqGuard};
+
+#[cfg(feature = "gdbstub")]
+#[cfg_attr(docsrs, doc(cfg(feature = "gdbstub")))]
+pub mod gdbstub;
+
+#[cfg(feature = "uspace")]
+mod uaccess;
+
+#[cfg(feature = "hw-breakpoint")]
+mod hw_breakpoint;
+
+#[cfg(all(feature = "fp-lazy", not(target_arch = "arm")))]
+mod lazy_fp;
+
+#[cfg(feature = "fp-simd")]
+mod kernel_fpu;
+
+#[cfg(feature = "fp-simd")]
+#[cfg_attr(docsrs, doc(cfg(feature = "fp-simd")))]
+pub use self::kernel_fpu::{kernel_fpu_begin, with_kernel_fpu, KernelFpuGuard};
+
+#[cfg(all(
+    feature = "uspace",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    )
+))]
+mod signal;
+
 #[cfg(any(doc, target_arch = "arm", target_arch = "aarch64"))]
 pub mod generic_timer;
 
+#[cfg(all(feature = "gicv3", any(target_arch = "arm", target_arch = "aarch64")))]
+#[cfg_attr(docsrs, doc(cfg(feature = "gicv3")))]
+pub mod gicv3;
+
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "x86_64")] {
         mod x86_64;
@@ -5,6 +5,8 @@ use core::arch::asm;
 use loongArch64::register::{crmd, ecfg, eentry, pgdh, pgdl};
 use memory_addr::{PhysAddr, VirtAddr};
 
+use crate::IrqFlags;
+
 /// Allows the current CPU to respond to interrupts.
 #[inline]
 pub fn enable_irqs() {
@@ -23,6 +25,21 @@ pub fn irqs_enabled() -> bool {
     crmd::read().ie()
 }
 
+/// Makes the current CPU to ignore interrupts, and returns the previous state
+/// of `CRMD.IE`.
+#[inline]
+pub fn local_irq_save() -> IrqFlags {
+    let flags = IrqFlags(irqs_enabled() as usize);
+    disable_irqs();
+    flags
+}
+
+/// Restores `CRMD.IE` saved by [`local_irq_save`].
+#[inline]
+pub fn local_irq_restore(flags: IrqFlags) {
+    crmd::set_ie(flags.0 != 0)
+}
+
 /// Relaxes the current CPU and waits for interrupts.
 ///
 /// It must be called with interrupts enabled, otherwise it will never return.
@@ -173,10 +190,22 @@ pub unsafe fn write_thread_pointer(tp: usize) {
 
 /// Enables floating-point instructions by setting `EUEN.FPE`.
 ///
+/// LSX and LASX instructions are also enabled by setting `EUEN.SXE` and
+/// `EUEN.ASXE` if they are supported according to `CPUCFG`, so that their
+/// states are saved and restored with the FPU states.
+///
 /// - `EUEN`: <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#extended-component-unit-enable>
 #[inline]
 pub fn enable_fp() {
-    loongArch64::register::euen::set_fpe(true);
+    use loongArch64::{cpu::CPUCFG, register::euen};
+    euen::set_fpe(true);
+    let cfg = CPUCFG::read(2);
+    if cfg.get_bit(6) {
+        euen::set_sxe(true);
+        if cfg.get_bit(7) {
+            euen::set_asxe(true);
+        }
+    }
 }
 
 /// Enables LSX extension by setting `EUEN.LSX`.
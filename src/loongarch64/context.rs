@@ -43,7 +43,7 @@ pub struct GeneralRegisters {
 }
 
 /// Floating-point registers of LoongArch64
-#[repr(C)]
+#[repr(C, align(32))]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct FpuState {
     /// Floating-point registers (f0-f31)
@@ -52,6 +52,12 @@ pub struct FpuState {
     pub fcc: [u8; 8],
     /// Floating-point Control and Status register
     pub fcsr: u32,
+    /// 256-bit LASX registers (xr0-xr31), or 128-bit LSX registers (vr0-vr31)
+    /// in the lower half, saved only if LASX or LSX is enabled.
+    ///
+    /// The lowest 64 bits of each vector register are the same as `fp`, and
+    /// are always taken from `fp` on restoring.
+    pub vr: [[u64; 4]; 32],
 }
 
 #[cfg(feature = "fp-simd")]
@@ -59,14 +65,92 @@ impl FpuState {
     /// Save the current FPU states from CPU to this structure.
     #[inline]
     pub fn save(&mut self) {
-        unsafe { save_fp_registers(self) }
+        unsafe { save_fp_registers(self) };
+        let euen = loongArch64::register::euen::read();
+        if euen.asxe() {
+            unsafe { save_vector_registers!(self, "xvst", "$xr") };
+        } else if euen.sxe() {
+            unsafe { save_vector_registers!(self, "vst", "$vr") };
+        }
     }
 
     /// Restore FPU states from this structure to CPU.
     #[inline]
     pub fn restore(&self) {
-        unsafe { restore_fp_registers(self) }
+        unsafe { restore_fp_registers(self) };
+        let euen = loongArch64::register::euen::read();
+        if euen.asxe() {
+            unsafe { restore_vector_registers!(self, "xvld", "xvinsgr2vr.d", "$xr") };
+        } else if euen.sxe() {
+            unsafe { restore_vector_registers!(self, "vld", "vinsgr2vr.d", "$vr") };
+        }
+    }
+
+    /// Saves the live FPU states to this structure and enables the FPU by
+    /// `EUEN.FPE` for a kernel-mode FP/SIMD section.
+    ///
+    /// Returns whether the states are live, i.e., `EUEN.FPE` is set.
+    pub(crate) fn kernel_fpu_begin(&mut self) -> bool {
+        let live = loongArch64::register::euen::read().fpe();
+        if live {
+            self.save();
+        } else {
+            crate::asm::enable_fp();
+        }
+        live
+    }
+
+    /// Restores the FPU states saved by [`Self::kernel_fpu_begin`], or
+    /// disables the FPU back if they are not live.
+    pub(crate) fn kernel_fpu_end(&self, live: bool) {
+        use loongArch64::register::euen;
+        if live {
+            self.restore();
+        } else {
+            euen::set_fpe(false);
+            euen::set_sxe(false);
+            euen::set_asxe(false);
+        }
+    }
+}
+
+#[cfg(feature = "fp-lazy")]
+impl FpuState {
+    /// Saves the current FPU states to this structure if they have been used
+    /// since the last switch, and clears `EUEN.FPE` so that the FPU states of
+    /// `next` are restored on their first use.
+    fn lazy_switch_to(&mut self, next: &Self) {
+        use loongArch64::register::euen;
+        if euen::read().fpe() {
+            self.save();
+            euen::set_fpe(false);
+            euen::set_sxe(false);
+            euen::set_asxe(false);
+        }
+        crate::lazy_fp::set_current(next);
+    }
+}
+
+/// Sets `EUEN.FPE` and restores the FPU states of the current task on the
+/// floating-point disabled exception (`FPD`) caused by their first use since
+/// the task was switched to.
+///
+/// The LSX and LASX disabled exceptions (`SXD` and `ASXD`) are handled in the
+/// same way, as `EUEN.SXE` and `EUEN.ASXE` are cleared together with
+/// `EUEN.FPE`.
+///
+/// Returns `false` if `EUEN.FPE` is already set, i.e., the exception is not
+/// caused by lazy switching.
+#[cfg(feature = "fp-lazy")]
+pub(super) fn handle_fp_trap() -> bool {
+    if loongArch64::register::euen::read().fpe() {
+        return false;
+    }
+    crate::asm::enable_fp();
+    if let Some(state) = unsafe { crate::lazy_fp::current::<FpuState>() } {
+        state.restore();
     }
+    true
 }
 
 /// Saved registers when a trap (interrupt or exception) occurs.
@@ -142,6 +226,9 @@ pub struct TaskContext {
     #[cfg(feature = "fp-simd")]
     /// Floating Point Unit states
     pub fpu: FpuState,
+    #[cfg(feature = "hw-breakpoint")]
+    /// Hardware breakpoints of the task.
+    pub hw_breakpoints: super::debug::HwBreakpoints,
 }
 
 impl TaskContext {
@@ -184,11 +271,15 @@ impl TaskContext {
                 crate::asm::flush_tlb(None); // currently flush the entire TLB
             }
         }
-        #[cfg(feature = "fp-simd")]
+        #[cfg(all(feature = "fp-simd", not(feature = "fp-lazy")))]
         {
             self.fpu.save();
             next_ctx.fpu.restore();
         }
+        #[cfg(feature = "fp-lazy")]
+        self.fpu.lazy_switch_to(&next_ctx.fpu);
+        #[cfg(feature = "hw-breakpoint")]
+        self.hw_breakpoints.switch_to(&next_ctx.hw_breakpoints);
         unsafe { context_switch(self, next_ctx) }
     }
 }
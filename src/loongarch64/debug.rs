@@ -0,0 +1,262 @@
+//! Hardware debugging support.
+
+use core::arch::asm;
+
+use super::TrapFrame;
+
+/// `PRMD.PWE`: watchpoints are enabled after `ertn`.
+const PRMD_PWE: usize = 1 << 3;
+/// `FWPS.Skip`: skips the next instruction fetch watchpoint.
+const FWPS_SKIP: usize = 1 << 16;
+/// `IB0CTRL`: enables the watchpoint in PLV0 or PLV3.
+const IBCTRL_PLV0: usize = 1 << 0;
+const IBCTRL_PLV3: usize = 1 << 3;
+
+/// Exception subcode of `WPE` raised by instruction fetch watchpoints.
+const ESUBCODE_WPEF: usize = 0;
+
+/// Reads the CSR named `$csr` in [`include_asm_macros`].
+macro_rules! read_csr {
+    ($csr:literal) => {{
+        let value: usize;
+        unsafe {
+            asm!(
+                include_asm_macros!(),
+                concat!("csrrd {}, ", $csr),
+                out(reg) value,
+            )
+        };
+        value
+    }};
+}
+
+/// Writes `$value` to the CSR named `$csr` in [`include_asm_macros`].
+macro_rules! write_csr {
+    ($csr:literal, $value:expr) => {
+        unsafe {
+            asm!(
+                include_asm_macros!(),
+                concat!("csrwr {}, ", $csr),
+                inout(reg) $value => _,
+            )
+        }
+    };
+}
+
+/// Turns single-stepping on or off for the context in the trap frame.
+///
+/// When it is on, a [`SINGLE_STEP`](crate::trap::SINGLE_STEP) trap is taken
+/// after the context executes one instruction on return from the trap. It uses
+/// the instruction fetch watchpoint 0, which is set to match every address, and
+/// `FWPS.Skip` to let the first instruction pass.
+///
+/// The watchpoint registers are per-CPU, so the context must be returned to on
+/// the current CPU.
+///
+/// Returns `false` if single-stepping is not supported on the architecture.
+pub fn set_single_step(tf: &mut TrapFrame, on: bool) -> bool {
+    if on {
+        let ctrl = if tf.prmd & 0b11 == 0b11 {
+            IBCTRL_PLV3
+        } else {
+            IBCTRL_PLV0
+        };
+        write_csr!("LA_CSR_IB0ADDR", 0usize);
+        write_csr!("LA_CSR_IB0MASK", usize::MAX);
+        write_csr!("LA_CSR_IB0ASID", 0usize);
+        write_csr!("LA_CSR_IB0CTRL", ctrl);
+        write_csr!("LA_CSR_FWPS", FWPS_SKIP);
+        tf.prmd |= PRMD_PWE;
+    } else {
+        write_csr!("LA_CSR_IB0CTRL", 0usize);
+        // Status bits are cleared by writing 1.
+        write_csr!("LA_CSR_FWPS", 1usize);
+        // Hardware breakpoints may still be in use.
+        #[cfg(not(feature = "hw-breakpoint"))]
+        {
+            tf.prmd &= !PRMD_PWE;
+        }
+    }
+    true
+}
+
+/// Handles a watchpoint exception (`WPE`) with the given exception subcode.
+pub(super) fn handle_watchpoint(tf: &mut TrapFrame, esubcode: usize) {
+    if esubcode == ESUBCODE_WPEF {
+        let status = read_csr!("LA_CSR_FWPS");
+        if status & 1 != 0 {
+            set_single_step(tf, false);
+            if !handle_trap!(SINGLE_STEP, tf) {
+                debug!("Single-step WPEF @ {:#x}", tf.era);
+            }
+        }
+        #[cfg(feature = "hw-breakpoint")]
+        handle_hw_breakpoints(tf, true, status & !1);
+    } else {
+        #[cfg(feature = "hw-breakpoint")]
+        handle_hw_breakpoints(tf, false, read_csr!("LA_CSR_MWPS"));
+        #[cfg(not(feature = "hw-breakpoint"))]
+        debug!("Unexpected memory watchpoint @ {:#x}", tf.era);
+    }
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub use crate::hw_breakpoint::{HwBreakpoint, HwBreakpointKind, HwBreakpoints};
+
+/// Number of hardware breakpoint slots.
+///
+/// Slots `0..8` are instruction fetch watchpoints `1..=8` for breakpoints, as
+/// the fetch watchpoint 0 is used for single-stepping, and slots `8..16` are
+/// memory watchpoints `0..8` for watchpoints.
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) const NUM_HW_SLOTS: usize = 16;
+#[cfg(feature = "hw-breakpoint")]
+const NUM_FETCH_SLOTS: usize = 8;
+
+/// Status bits of the watchpoints in `FWPS` and `MWPS`.
+#[cfg(feature = "hw-breakpoint")]
+const WPS_STATUS_MASK: usize = 0x3fff;
+/// `MWPS.Skip`: skips the next memory watchpoint.
+#[cfg(feature = "hw-breakpoint")]
+const MWPS_SKIP: usize = 1 << 16;
+/// `DBnCTRL`: the watchpoint matches loads or stores.
+#[cfg(feature = "hw-breakpoint")]
+const DBCTRL_LOAD: usize = 1 << 8;
+#[cfg(feature = "hw-breakpoint")]
+const DBCTRL_STORE: usize = 1 << 9;
+
+/// Writes the address and the control of the instruction fetch (`IBn*`) or the
+/// memory (`DBn*`) watchpoint `n`, clearing its mask and ASID.
+#[cfg(feature = "hw-breakpoint")]
+macro_rules! write_watchpoint_regs {
+    ($($n:literal),*) => {
+        fn write_watchpoint(fetch: bool, n: usize, addr: usize, ctrl: usize) {
+            macro_rules! write_regs {
+                ($base:expr) => {
+                    unsafe {
+                        asm!(
+                            "csrwr $zero, {ctrl}",
+                            "csrwr {a}, {addr}",
+                            "csrwr $zero, {mask}",
+                            "csrwr $zero, {asid}",
+                            "csrwr {c}, {ctrl}",
+                            a = inout(reg) addr => _,
+                            c = inout(reg) ctrl => _,
+                            addr = const $base,
+                            mask = const $base + 1,
+                            ctrl = const $base + 2,
+                            asid = const $base + 3,
+                        )
+                    }
+                };
+            }
+            match (fetch, n) {
+                $(
+                    (true, $n) => write_regs!(0x390 + 8 * $n),
+                    (false, $n) => write_regs!(0x310 + 8 * $n),
+                )*
+                _ => unreachable!(),
+            }
+        }
+    };
+}
+
+#[cfg(feature = "hw-breakpoint")]
+write_watchpoint_regs!(0, 1, 2, 3, 4, 5, 6, 7, 8);
+
+/// Returns the number of implemented instruction fetch watchpoints and memory
+/// watchpoints.
+#[cfg(feature = "hw-breakpoint")]
+fn num_watchpoints() -> (usize, usize) {
+    (
+        read_csr!("LA_CSR_FWPC") & 0x3f,
+        read_csr!("LA_CSR_MWPC") & 0x3f,
+    )
+}
+
+/// Returns the watchpoint control of the breakpoint.
+#[cfg(feature = "hw-breakpoint")]
+fn encode(bp: &HwBreakpoint) -> Option<usize> {
+    let access = match bp.kind {
+        HwBreakpointKind::Execute => {
+            return bp
+                .addr
+                .is_multiple_of(4)
+                .then_some(IBCTRL_PLV0 | IBCTRL_PLV3);
+        }
+        HwBreakpointKind::Read => DBCTRL_LOAD,
+        HwBreakpointKind::Write => DBCTRL_STORE,
+        HwBreakpointKind::ReadWrite => DBCTRL_LOAD | DBCTRL_STORE,
+    };
+    let len = match bp.len {
+        1 => 0b11,
+        2 => 0b10,
+        4 => 0b01,
+        8 => 0b00,
+        _ => return None,
+    };
+    bp.addr
+        .is_multiple_of(bp.len)
+        .then_some(len << 10 | access | IBCTRL_PLV0 | IBCTRL_PLV3)
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn hw_slot_supports(slot: usize, bp: &HwBreakpoint) -> bool {
+    let (num_fetch, num_mem) = num_watchpoints();
+    let implemented = if slot < NUM_FETCH_SLOTS {
+        bp.kind == HwBreakpointKind::Execute && slot + 1 < num_fetch
+    } else {
+        bp.kind != HwBreakpointKind::Execute && slot - NUM_FETCH_SLOTS < num_mem
+    };
+    implemented && encode(bp).is_some()
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn install_hw_breakpoints(slots: &[Option<HwBreakpoint>; NUM_HW_SLOTS]) {
+    let (num_fetch, num_mem) = num_watchpoints();
+    for (slot, bp) in slots.iter().enumerate() {
+        let (fetch, n, num) = if slot < NUM_FETCH_SLOTS {
+            (true, slot + 1, num_fetch)
+        } else {
+            (false, slot - NUM_FETCH_SLOTS, num_mem)
+        };
+        if n < num {
+            let ctrl = bp.as_ref().and_then(encode).unwrap_or(0);
+            write_watchpoint(fetch, n, bp.map_or(0, |bp| bp.addr), ctrl);
+        }
+    }
+    if slots.iter().any(Option::is_some) {
+        // Watchpoints stay enabled across traps by `PRMD.PWE`.
+        loongArch64::register::crmd::set_we(true);
+    }
+}
+
+/// Reports the hardware breakpoints that hit according to the status bits of
+/// `FWPS` or `MWPS`, and lets the trapped instruction pass them once.
+#[cfg(feature = "hw-breakpoint")]
+fn handle_hw_breakpoints(tf: &mut TrapFrame, fetch: bool, status: usize) {
+    let status = status & WPS_STATUS_MASK;
+    if status == 0 {
+        return;
+    }
+    // Status bits are cleared by writing 1.
+    if fetch {
+        write_csr!("LA_CSR_FWPS", status | FWPS_SKIP);
+    } else {
+        write_csr!("LA_CSR_MWPS", status | MWPS_SKIP);
+    }
+    let addr = if fetch {
+        tf.era
+    } else {
+        loongArch64::register::badv::read().raw()
+    };
+    for n in 0..WPS_STATUS_MASK.count_ones() as usize {
+        if status & (1 << n) == 0 {
+            continue;
+        }
+        let slot = if fetch { n - 1 } else { n + NUM_FETCH_SLOTS };
+        if !handle_trap!(HW_BREAKPOINT, tf, slot, va!(addr)) {
+            debug!("Hardware breakpoint {} hit @ {:#x}", slot, tf.era);
+        }
+    }
+}
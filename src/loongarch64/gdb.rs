@@ -0,0 +1,66 @@
+//! Mapping of LoongArch64 trap frames to the GDB register layout.
+
+use crate::TrapFrame;
+
+/// Number of registers in the `g` packet: `r0` to `r31`, followed by
+/// `orig_a0`, `pc` and `badv`.
+pub(crate) const NUM_REGS: usize = 35;
+
+/// The general registers in the order of `r0` to `r31`.
+fn gregs(tf: &TrapFrame) -> &[usize; 32] {
+    unsafe { &*(&tf.regs as *const _ as *const [usize; 32]) }
+}
+
+/// Reads the `n`-th register of GDB, returning the value and its size in
+/// bytes.
+pub(crate) fn read_reg(tf: &TrapFrame, n: usize) -> Option<(u64, usize)> {
+    Some(match n {
+        0..32 => (gregs(tf)[n] as _, 8),
+        32 => (0, 8),
+        33 => (tf.era as _, 8),
+        34 => (loongArch64::register::badv::read().raw() as _, 8),
+        _ => return None,
+    })
+}
+
+/// Writes the `n`-th register of GDB. Returns `false` if it is not writable.
+pub(crate) fn write_reg(tf: &mut TrapFrame, n: usize, val: u64) -> bool {
+    match n {
+        // `r0` is hardwired to zero, and `orig_a0` is not saved.
+        0 | 32 => {}
+        1..32 => {
+            let gregs = unsafe { &mut *(&mut tf.regs as *mut _ as *mut [usize; 32]) };
+            gregs[n] = val as _;
+        }
+        33 => tf.era = val as _,
+        _ => return false,
+    }
+    true
+}
+
+/// `break 0`
+const BREAK: [u8; 4] = 0x002a_0000u32.to_le_bytes();
+
+/// Returns the breakpoint instruction of the given GDB breakpoint kind.
+pub(crate) fn breakpoint_insn(kind: usize) -> Option<&'static [u8]> {
+    match kind {
+        4 => Some(&BREAK),
+        _ => None,
+    }
+}
+
+/// Returns the address of the breakpoint instruction that caused the trap.
+pub(crate) fn breakpoint_addr(tf: &TrapFrame) -> usize {
+    tf.era
+}
+
+/// Sets the program counter in the trap frame.
+pub(crate) fn set_pc(tf: &mut TrapFrame, pc: usize) {
+    tf.era = pc;
+}
+
+/// Makes the modified instructions in `addr..addr + len` visible to
+/// instruction fetch.
+pub(crate) fn flush_icache(_addr: usize, _len: usize) {
+    unsafe { core::arch::asm!("ibar 0") };
+}
@@ -22,6 +22,14 @@ macro_rules! include_asm_macros {
         .equ LA_CSR_TLBREHI,       0x8e    // TLB refill entryhi
         .equ LA_CSR_DMW0,          0x180
         .equ LA_CSR_DMW1,          0x181
+        .equ LA_CSR_MWPC,          0x300   // Memory watchpoint config
+        .equ LA_CSR_MWPS,          0x301   // Memory watchpoint status
+        .equ LA_CSR_FWPC,          0x380   // Fetch watchpoint config
+        .equ LA_CSR_FWPS,          0x381   // Fetch watchpoint status
+        .equ LA_CSR_IB0ADDR,       0x390   // Fetch watchpoint 0 address
+        .equ LA_CSR_IB0MASK,       0x391   // Fetch watchpoint 0 mask
+        .equ LA_CSR_IB0CTRL,       0x392   // Fetch watchpoint 0 control
+        .equ LA_CSR_IB0ASID,       0x393   // Fetch watchpoint 0 ASID
 
         .equ KSAVE_KSP,            0x30
         .equ KSAVE_TEMP,           0x31
@@ -181,3 +189,37 @@ macro_rules! include_fp_asm_macros {
         .endif"#
     };
 }
+
+/// Saves the vector registers to `FpuState::vr` with the
+/// given store instruction (`vst` or `xvst`) and register prefix (`$vr` or
+/// `$xr`).
+#[cfg(feature = "fp-simd")]
+macro_rules! save_vector_registers {
+    ($state:expr, $st:literal, $reg:literal) => {
+        core::arch::asm!(
+            ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+            concat!($st, " ", $reg, "\\n, {0}, \\n*32"),
+            ".endr",
+            in(reg) $state.vr.as_mut_ptr(),
+        )
+    };
+}
+
+/// Restores the vector registers from `FpuState::vr` with the given load
+/// and insert instructions (`vld` and `vinsgr2vr.d`, or `xvld` and
+/// `xvinsgr2vr.d`), and then inserts `FpuState::fp` as their lowest 64 bits.
+#[cfg(feature = "fp-simd")]
+macro_rules! restore_vector_registers {
+    ($state:expr, $ld:literal, $ins:literal, $reg:literal) => {
+        core::arch::asm!(
+            ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+            concat!($ld, " ", $reg, "\\n, {0}, \\n*32"),
+            "ld.d $t0, {1}, \\n*8",
+            concat!($ins, " ", $reg, "\\n, $t0, 0"),
+            ".endr",
+            in(reg) $state.vr.as_ptr(),
+            in(reg) $state.fp.as_ptr(),
+            out("$t0") _,
+        )
+    };
+}
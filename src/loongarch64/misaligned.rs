@@ -0,0 +1,178 @@
+//! Emulation of misaligned loads and stores.
+//!
+//! Integer loads and stores (`LD.*`, `ST.*`, `LDX.*`, `STX.*`, `LDPTR.*` and
+//! `STPTR.*`) are emulated by accessing the memory byte by byte.
+//! Floating-point, vector and atomic accesses are not emulated and are
+//! reported as exceptions.
+
+use super::TrapFrame;
+
+/// A decoded load or store instruction.
+struct Access {
+    /// Whether it is a store.
+    store: bool,
+    /// Number of bytes to access.
+    len: usize,
+    /// Whether the loaded value is sign-extended.
+    signed: bool,
+    /// The destination register of loads, or the source register of stores.
+    rd: u32,
+    /// The accessed address.
+    addr: usize,
+}
+
+/// Reads the general register `r{n}`.
+fn reg(tf: &TrapFrame, n: u32) -> usize {
+    let regs = unsafe { &*(&tf.regs as *const _ as *const [usize; 32]) };
+    if n == 0 {
+        0
+    } else {
+        regs[n as usize]
+    }
+}
+
+/// Writes the general register `r{n}`. Writes to `r0` are discarded.
+fn set_reg(tf: &mut TrapFrame, n: u32, value: usize) {
+    let regs = unsafe { &mut *(&mut tf.regs as *mut _ as *mut [usize; 32]) };
+    if n != 0 {
+        regs[n as usize] = value;
+    }
+}
+
+/// Returns the access size and whether a load is sign-extended for the
+/// `{LD,ST}[X].{B,H,W,D,BU,HU,WU}` variant numbered in the encoding order.
+const fn variant(n: u32) -> Option<(bool, usize, bool)> {
+    Some(match n {
+        0 => (false, 1, true),
+        1 => (false, 2, true),
+        2 => (false, 4, true),
+        3 => (false, 8, true),
+        4 => (true, 1, false),
+        5 => (true, 2, false),
+        6 => (true, 4, false),
+        7 => (true, 8, false),
+        8 => (false, 1, false),
+        9 => (false, 2, false),
+        10 => (false, 4, false),
+        _ => return None,
+    })
+}
+
+/// Decodes the integer load or store instruction `insn`.
+fn decode(tf: &TrapFrame, insn: u32) -> Option<Access> {
+    let rd = insn & 0x1f;
+    let base = reg(tf, (insn >> 5) & 0x1f);
+    let (store, len, signed, offset) = if insn >> 22 >= 0xa0 && insn >> 22 <= 0xaa {
+        // LD.* and ST.* with a 12-bit signed immediate.
+        let (store, len, signed) = variant((insn >> 22) - 0xa0)?;
+        let si12 = ((insn << 10) as i32 >> 20) as isize;
+        (store, len, signed, si12)
+    } else if insn >> 24 >= 0x24 && insn >> 24 <= 0x27 {
+        // LDPTR.W, STPTR.W, LDPTR.D and STPTR.D with a 14-bit signed
+        // immediate shifted left by 2.
+        let (store, len) = match insn >> 24 {
+            0x24 => (false, 4),
+            0x25 => (true, 4),
+            0x26 => (false, 8),
+            _ => (true, 8),
+        };
+        let si14 = ((insn << 8) as i32 >> 18) as isize;
+        (store, len, true, si14 << 2)
+    } else if insn >> 15 >= 0x7000 && insn >> 15 <= 0x7050 && (insn >> 15).is_multiple_of(8) {
+        // LDX.* and STX.* with an index register.
+        let (store, len, signed) = variant(((insn >> 15) - 0x7000) / 8)?;
+        (store, len, signed, reg(tf, (insn >> 10) & 0x1f) as isize)
+    } else {
+        return None;
+    };
+    Some(Access {
+        store,
+        len,
+        signed,
+        rd,
+        addr: base.wrapping_add_signed(offset),
+    })
+}
+
+/// Reads memory at `addr`, which may be in user space.
+fn read_mem(addr: usize, buf: &mut [u8]) -> bool {
+    #[cfg(feature = "uspace")]
+    let ok = unsafe { crate::uaccess::copy_from_user(buf, addr as _) == 0 };
+    #[cfg(not(feature = "uspace"))]
+    let ok = {
+        unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), buf.len()) };
+        true
+    };
+    ok
+}
+
+/// Writes memory at `addr`, which may be in user space.
+fn write_mem(addr: usize, data: &[u8]) -> bool {
+    #[cfg(feature = "uspace")]
+    let ok = unsafe { crate::uaccess::copy_to_user(addr as _, data) == 0 };
+    #[cfg(not(feature = "uspace"))]
+    let ok = {
+        unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), addr as *mut u8, data.len()) };
+        true
+    };
+    ok
+}
+
+/// Reads the instruction at `pc`.
+///
+/// User instructions are read with fault-safe user access after `pc` is checked
+/// to be in user space, and kernel ones are read directly.
+fn read_insn(pc: usize, from_user: bool) -> Option<u32> {
+    #[cfg(feature = "uspace")]
+    if from_user {
+        let mut insn = [0; 4];
+        let ok = crate::uaccess::is_user_range(pc, insn.len())
+            && unsafe { crate::uaccess::copy_from_user(&mut insn, pc as _) == 0 };
+        return ok.then(|| u32::from_le_bytes(insn));
+    }
+    #[cfg(not(feature = "uspace"))]
+    let _ = from_user;
+    Some(unsafe { (pc as *const u32).read() })
+}
+
+/// Emulates the misaligned load or store at `era`, and moves `era` to the next
+/// instruction.
+///
+/// Returns `false` if the instruction is not an integer load or store, or a
+/// fault occurs when accessing the instruction or the data, in which case the
+/// trap frame is not modified. If the access is `from_user`, it is also refused
+/// when the instruction or the data is not entirely in user space.
+pub(super) fn emulate_misaligned(tf: &mut TrapFrame, from_user: bool) -> bool {
+    let Some(insn) = read_insn(tf.era, from_user) else {
+        return false;
+    };
+    let Some(access) = decode(tf, insn) else {
+        return false;
+    };
+    let len = access.len;
+    #[cfg(feature = "uspace")]
+    if from_user && !crate::uaccess::is_user_range(access.addr, len) {
+        return false;
+    }
+    if access.store {
+        let data = reg(tf, access.rd).to_le_bytes();
+        if !write_mem(access.addr, &data[..len]) {
+            return false;
+        }
+    } else {
+        let mut data = [0; 8];
+        if !read_mem(access.addr, &mut data[..len]) {
+            return false;
+        }
+        let shift = 64 - len as u32 * 8;
+        let value = usize::from_le_bytes(data);
+        let value = if access.signed {
+            ((value << shift) as isize >> shift) as usize
+        } else {
+            value
+        };
+        set_reg(tf, access.rd, value);
+    }
+    tf.era += 4;
+    true
+}
@@ -4,9 +4,16 @@ mod macros;
 mod context;
 mod trap;
 
+#[cfg(feature = "misaligned-emu")]
+mod misaligned;
+
 pub mod asm;
+pub mod debug;
 pub mod init;
 
+#[cfg(feature = "gdbstub")]
+pub(crate) mod gdb;
+
 #[cfg(feature = "uspace")]
 pub mod uspace;
 
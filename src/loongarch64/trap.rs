@@ -2,9 +2,28 @@ use loongArch64::register::{
     badv,
     estat::{self, Exception, Trap},
 };
+use memory_addr::VirtAddr;
 
 use super::context::TrapFrame;
-use crate::trap::PageFaultFlags;
+use crate::extable::search_exception_table;
+#[cfg(feature = "uspace")]
+use crate::trap::SyscallAction;
+use crate::trap::{ExceptionInfo, ExceptionKind, PageFaultFlags};
+
+/// Exception code of the floating-point exception (`FPE`), which is not
+/// recognized by [`Estat::cause`](estat::Estat::cause).
+const ECODE_FPE: usize = 0x12;
+
+/// Exception code of the watchpoint exception (`WPE`), which is not recognized
+/// by [`Estat::cause`](estat::Estat::cause).
+const ECODE_WPE: usize = 0x13;
+
+/// Exception codes of the LSX and LASX disabled exceptions (`SXD` and `ASXD`),
+/// which are not recognized by [`Estat::cause`](estat::Estat::cause).
+#[cfg(feature = "fp-lazy")]
+const ECODE_SXD: usize = 0x10;
+#[cfg(feature = "fp-lazy")]
+const ECODE_ASXD: usize = 0x11;
 
 core::arch::global_asm!(
     include_asm_macros!(),
@@ -12,24 +31,54 @@ core::arch::global_asm!(
     trapframe_size = const (core::mem::size_of::<TrapFrame>()),
 );
 
-fn handle_breakpoint(era: &mut usize) {
-    debug!("Exception(Breakpoint) @ {era:#x} ");
-    *era += 4;
+fn handle_breakpoint(tf: &mut TrapFrame) {
+    count_trap!(breakpoints);
+    if !handle_trap!(BREAKPOINT, tf) {
+        debug!("Exception(Breakpoint) @ {:#x} ", tf.era);
+        tf.era += 4;
+    }
+}
+
+fn handle_exception(
+    tf: &mut TrapFrame,
+    kind: ExceptionKind,
+    vaddr: Option<VirtAddr>,
+    is_user: bool,
+) -> bool {
+    count_trap!(EXCEPTION, kind);
+    if !is_user {
+        if let Some(fixup) = search_exception_table(tf.era as _) {
+            tf.era = fixup as _;
+            return true;
+        }
+    }
+    let info = ExceptionInfo {
+        kind,
+        pc: tf.era,
+        cause: estat::read().raw(),
+        vaddr,
+        is_user,
+    };
+    handle_trap!(EXCEPTION, tf, &info)
 }
 
-fn handle_page_fault(tf: &TrapFrame, mut access_flags: PageFaultFlags, is_user: bool) {
+fn handle_page_fault(tf: &mut TrapFrame, mut access_flags: PageFaultFlags, is_user: bool) {
+    count_trap!(page_faults);
     if is_user {
         access_flags |= PageFaultFlags::USER;
     }
     let vaddr = va!(badv::read().raw());
-    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
+    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
+        && !handle_exception(tf, ExceptionKind::PageFault, Some(vaddr), is_user)
+    {
         panic!(
-            "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
+            "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}\n{}",
             if is_user { "PLV3" } else { "PLV0" },
             tf.era,
             vaddr,
             access_flags,
             tf,
+            crate::backtrace::trap_backtrace(tf),
         );
     }
 }
@@ -37,12 +86,24 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: PageFaultFlags, is_user:
 #[unsafe(no_mangle)]
 fn loongarch64_trap_handler(tf: &mut TrapFrame, from_user: bool) {
     let estat = estat::read();
+    #[cfg(feature = "trap-trace")]
+    let ty = cause_to_type(estat.cause(), estat.ecode());
+    trace_trap!(TRAP_ENTER, ty, tf);
+    let mut is_irq = false;
 
     match estat.cause() {
         #[cfg(feature = "uspace")]
         Trap::Exception(Exception::Syscall) => {
-            tf.regs.a0 = crate::trap::handle_syscall(tf, tf.regs.a7) as usize;
+            let syscall_num = tf.regs.a7;
             tf.era += 4;
+            match crate::trap::handle_syscall(tf, syscall_num) {
+                SyscallAction::Return(ret) => tf.regs.a0 = ret as usize,
+                SyscallAction::Restart => {
+                    tf.regs.a7 = syscall_num;
+                    tf.era -= 4;
+                }
+                SyscallAction::NoReturnValue => {}
+            }
         }
         Trap::Exception(Exception::LoadPageFault)
         | Trap::Exception(Exception::PageNonReadableFault) => {
@@ -56,18 +117,77 @@ fn loongarch64_trap_handler(tf: &mut TrapFrame, from_user: bool) {
         | Trap::Exception(Exception::PageNonExecutableFault) => {
             handle_page_fault(tf, PageFaultFlags::EXECUTE, from_user);
         }
-        Trap::Exception(Exception::Breakpoint) => handle_breakpoint(&mut tf.era),
+        Trap::Exception(Exception::Breakpoint) => handle_breakpoint(tf),
+        #[cfg(feature = "fp-lazy")]
+        Trap::Exception(Exception::FloatingPointUnavailable)
+            if super::context::handle_fp_trap() => {}
+        #[cfg(feature = "fp-lazy")]
+        Trap::Unknown
+            if matches!(estat.ecode(), ECODE_SXD | ECODE_ASXD)
+                && super::context::handle_fp_trap() => {}
+        #[cfg(feature = "misaligned-emu")]
+        Trap::Exception(Exception::AddressNotAligned)
+            if super::misaligned::emulate_misaligned(tf, from_user) => {}
+        Trap::Unknown if estat.ecode() == ECODE_WPE => {
+            super::debug::handle_watchpoint(tf, estat.esubcode())
+        }
         Trap::Interrupt(_) => {
             let irq_num: usize = estat.is().trailing_zeros() as usize;
-            handle_trap!(IRQ, irq_num);
+            let handled = handle_trap!(IRQ, irq_num);
+            count_trap!(IRQ, irq_num, handled);
+            is_irq = true;
         }
-        _ => {
-            panic!(
-                "Unhandled trap {:?} @ {:#x}:\n{:#x?}",
-                estat.cause(),
-                tf.era,
-                tf
-            );
+        cause => {
+            let badv = || Some(va!(badv::read().raw()));
+            let (kind, vaddr) = match cause {
+                Trap::Exception(Exception::InstructionNotExist)
+                | Trap::Exception(Exception::InstructionPrivilegeIllegal) => {
+                    (ExceptionKind::IllegalInstruction, None)
+                }
+                Trap::Exception(Exception::AddressNotAligned) => {
+                    (ExceptionKind::Misaligned, badv())
+                }
+                Trap::Exception(Exception::PagePrivilegeIllegal)
+                | Trap::Exception(Exception::FetchInstructionAddressError)
+                | Trap::Exception(Exception::MemoryAccessAddressError)
+                | Trap::Exception(Exception::BoundsCheckFault) => {
+                    (ExceptionKind::AccessFault, badv())
+                }
+                Trap::Unknown if estat.ecode() == ECODE_FPE => (ExceptionKind::FloatingPoint, None),
+                _ => (ExceptionKind::Other, None),
+            };
+            if !handle_exception(tf, kind, vaddr, from_user) {
+                panic!(
+                    "Unhandled trap {:?} @ {:#x}:\n{:#x?}\n{}",
+                    cause,
+                    tf.era,
+                    tf,
+                    crate::backtrace::trap_backtrace(tf),
+                );
+            }
         }
     }
+    trace_trap!(TRAP_EXIT, ty, tf);
+    if is_irq {
+        irq_return!(tf);
+    }
+}
+
+#[cfg(feature = "trap-trace")]
+fn cause_to_type(cause: Trap, ecode: usize) -> crate::trap::TrapType {
+    use crate::trap::TrapType;
+    match cause {
+        #[cfg(feature = "uspace")]
+        Trap::Exception(Exception::Syscall) => TrapType::Syscall,
+        Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::PageNonReadableFault)
+        | Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::PageModifyFault)
+        | Trap::Exception(Exception::FetchPageFault)
+        | Trap::Exception(Exception::PageNonExecutableFault) => TrapType::PageFault,
+        Trap::Exception(Exception::Breakpoint) => TrapType::Breakpoint,
+        Trap::Unknown if ecode == ECODE_WPE => TrapType::Breakpoint,
+        Trap::Interrupt(_) => TrapType::Irq,
+        _ => TrapType::Exception,
+    }
 }
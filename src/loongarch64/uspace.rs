@@ -1,9 +1,17 @@
 //! Structures and functions for user space.
 
+use core::arch::naked_asm;
+
 use memory_addr::VirtAddr;
 
 use crate::TrapFrame;
 
+pub use crate::uaccess::{
+    clear_user, copy_from_user, copy_to_user, is_user_range, strncpy_from_user,
+};
+
+pub mod signal;
+
 /// Context to enter user space.
 pub struct UspaceContext(TrapFrame);
 
@@ -19,9 +27,15 @@ impl UspaceContext {
         let mut trap_frame = TrapFrame::default();
         const PPLV_UMODE: usize = 0b11;
         const PIE: usize = 1 << 2;
+        // Enables the hardware breakpoints of the task in user space.
+        const PWE: usize = if cfg!(feature = "hw-breakpoint") {
+            1 << 3
+        } else {
+            0
+        };
         trap_frame.regs.sp = ustack_top.as_usize();
         trap_frame.era = entry;
-        trap_frame.prmd = PPLV_UMODE | PIE;
+        trap_frame.prmd = PPLV_UMODE | PIE | PWE;
         trap_frame.regs.a0 = arg0;
         Self(trap_frame)
     }
@@ -96,3 +110,69 @@ impl UspaceContext {
         }
     }
 }
+
+/// Copies `len` bytes from `src` to `dst`, either of which may be in user
+/// space. Returns the number of bytes not copied.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_copy_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> usize {
+    naked_asm!(
+        "
+        beqz    $a2, 4f
+    2:  ld.bu   $t0, $a1, 0
+    3:  st.b    $t0, $a0, 0
+        addi.d  $a0, $a0, 1
+        addi.d  $a1, $a1, 1
+        addi.d  $a2, $a2, -1
+        bnez    $a2, 2b
+    4:  move    $a0, $a2
+        ret",
+        crate::asm_extable!("2b", "4b"),
+        crate::asm_extable!("3b", "4b"),
+    )
+}
+
+/// Fills `len` bytes of user space at `dst` with zeros. Returns the number of
+/// bytes not cleared.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_clear_user(_dst: *mut u8, _len: usize) -> usize {
+    naked_asm!(
+        "
+        beqz    $a1, 4f
+    2:  st.b    $zero, $a0, 0
+        addi.d  $a0, $a0, 1
+        addi.d  $a1, $a1, -1
+        bnez    $a1, 2b
+    4:  move    $a0, $a1
+        ret",
+        crate::asm_extable!("2b", "4b"),
+    )
+}
+
+/// Copies a NUL-terminated string of at most `len` bytes from user space.
+/// Returns the length of the string, `len` if it is not terminated within
+/// `len` bytes, or `-1` on fault.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_strncpy_from_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> isize {
+    naked_asm!(
+        "
+        move    $t1, $zero
+    2:  beq     $t1, $a2, 4f
+    3:  ldx.bu  $t0, $a1, $t1
+        stx.b   $t0, $a0, $t1
+        beqz    $t0, 4f
+        addi.d  $t1, $t1, 1
+        b       2b
+    5:  addi.d  $t1, $zero, -1
+    4:  move    $a0, $t1
+        ret",
+        crate::asm_extable!("3b", "5b"),
+    )
+}
@@ -0,0 +1,186 @@
+//! Signal frames compatible with the loongarch64 Linux ABI.
+
+use super::UspaceContext;
+use crate::signal::{read_from_user, write_to_user};
+use crate::{GeneralRegisters, TrapFrame};
+
+pub use crate::signal::{SignalDelivery, SignalInfo, SignalStack};
+
+/// Magic number of the FPU context record.
+#[cfg(feature = "fp-simd")]
+const FPU_CTX_MAGIC: u32 = 0x4650_5501;
+
+/// `sc_flags`: the FPU context is saved.
+#[cfg(feature = "fp-simd")]
+const SC_USED_FP: u32 = 1 << 0;
+
+/// `struct sigcontext` in Linux, without the trailing `sc_extcontext`.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct SigContext {
+    pc: u64,
+    /// `sc_regs`, which has the same layout as [`GeneralRegisters`].
+    regs: GeneralRegisters,
+    flags: u32,
+    _pad: u32,
+}
+
+/// `struct sctx_info` in Linux, i.e., the header of a context record.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SctxInfo {
+    magic: u32,
+    size: u32,
+    _padding: u64,
+}
+
+/// `struct fpu_context` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FpuContext {
+    regs: [u64; 32],
+    fcc: u64,
+    fcsr: u32,
+    _pad: u32,
+}
+
+/// Context records following `struct sigcontext`, which are terminated by a
+/// record with zero magic.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ExtContext {
+    fpu_info: SctxInfo,
+    fpu: FpuContext,
+    end: SctxInfo,
+}
+
+/// `struct ucontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UContext {
+    flags: u64,
+    link: u64,
+    stack: SignalStack,
+    sigmask: u64,
+    _unused: [u8; 1024 / 8 - 8],
+    _pad: u64,
+    mcontext: SigContext,
+}
+
+/// `struct rt_sigframe` in Linux, followed by the context records.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SignalFrame {
+    info: SignalInfo,
+    uc: UContext,
+    ext: ExtContext,
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<SigContext>(), 272);
+static_assertions::const_assert_eq!(core::mem::offset_of!(SignalFrame, ext), 576);
+
+/// Pushes a signal frame onto the user stack, and redirects the trap frame to
+/// the signal handler.
+///
+/// The FPU context record is saved in the frame if the `fp-simd` feature is
+/// enabled. The address of the trampoline is set to the return address
+/// register (`ra`) of the handler.
+///
+/// Returns `false` if the user stack is not writable, in which case the trap
+/// frame is not modified.
+///
+/// # Safety
+///
+/// The caller must ensure that the user stack to push the frame onto is in
+/// user space.
+pub unsafe fn setup_sigframe(tf: &mut TrapFrame, delivery: &SignalDelivery) -> bool {
+    let sp = delivery.stack_top(tf.regs.sp);
+    let frame_addr = (sp - core::mem::size_of::<SignalFrame>()) & !15;
+
+    let mut frame: SignalFrame = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
+    frame.info = delivery.info;
+    frame.uc.stack = delivery.altstack;
+    frame.uc.sigmask = delivery.sigmask;
+    frame.uc.mcontext.pc = tf.era as _;
+    frame.uc.mcontext.regs = tf.regs;
+    #[cfg(feature = "fp-simd")]
+    {
+        let mut state = crate::FpuState::default();
+        state.save();
+        frame.uc.mcontext.flags = SC_USED_FP;
+        frame.ext.fpu_info.magic = FPU_CTX_MAGIC;
+        frame.ext.fpu_info.size =
+            (core::mem::size_of::<SctxInfo>() + core::mem::size_of::<FpuContext>()) as _;
+        frame.ext.fpu.regs = state.fp;
+        frame.ext.fpu.fcc = u64::from_le_bytes(state.fcc);
+        frame.ext.fpu.fcsr = state.fcsr;
+    }
+    if !unsafe { write_to_user(frame_addr, &frame) } {
+        return false;
+    }
+
+    tf.regs.a0 = delivery.info.signo as _;
+    tf.regs.a1 = frame_addr + core::mem::offset_of!(SignalFrame, info);
+    tf.regs.a2 = frame_addr + core::mem::offset_of!(SignalFrame, uc);
+    tf.regs.ra = delivery.restorer;
+    tf.regs.sp = frame_addr;
+    tf.era = delivery.handler;
+    true
+}
+
+/// Restores the trap frame from the signal frame on the user stack, which is
+/// pushed by [`setup_sigframe`]. It should be called on `rt_sigreturn`.
+///
+/// Returns the signal mask saved in the frame, or [`None`] if the frame is not
+/// readable, in which case the trap frame is not modified.
+///
+/// # Safety
+///
+/// The caller must ensure that the user stack pointer in the trap frame points
+/// to user space.
+pub unsafe fn restore_from_sigframe(tf: &mut TrapFrame) -> Option<u64> {
+    let frame: SignalFrame = unsafe { read_from_user(tf.regs.sp)? };
+    let sc = &frame.uc.mcontext;
+
+    #[cfg(feature = "fp-simd")]
+    if sc.flags & SC_USED_FP != 0 && frame.ext.fpu_info.magic == FPU_CTX_MAGIC {
+        let fpu = &frame.ext.fpu;
+        let state = crate::FpuState {
+            fp: fpu.regs,
+            fcc: fpu.fcc.to_le_bytes(),
+            fcsr: fpu.fcsr,
+            ..Default::default()
+        };
+        state.restore();
+    }
+
+    tf.regs = sc.regs;
+    tf.regs.zero = 0;
+    tf.era = sc.pc as _;
+    Some(frame.uc.sigmask)
+}
+
+impl UspaceContext {
+    /// Pushes a signal frame onto the user stack, and redirects the context to
+    /// the signal handler.
+    ///
+    /// See [`setup_sigframe`] for details.
+    ///
+    /// # Safety
+    ///
+    /// See [`setup_sigframe`].
+    pub unsafe fn setup_sigframe(&mut self, delivery: &SignalDelivery) -> bool {
+        unsafe { setup_sigframe(&mut self.0, delivery) }
+    }
+
+    /// Restores the context from the signal frame on the user stack.
+    ///
+    /// See [`restore_from_sigframe`] for details.
+    ///
+    /// # Safety
+    ///
+    /// See [`restore_from_sigframe`].
+    pub unsafe fn restore_from_sigframe(&mut self) -> Option<u64> {
+        unsafe { restore_from_sigframe(&mut self.0) }
+    }
+}
@@ -4,6 +4,8 @@ use memory_addr::{PhysAddr, VirtAddr};
 use riscv::asm;
 use riscv::register::{satp, sstatus, stvec};
 
+use crate::IrqFlags;
+
 /// Allows the current CPU to respond to interrupts.
 #[inline]
 pub fn enable_irqs() {
@@ -22,6 +24,28 @@ pub fn irqs_enabled() -> bool {
     sstatus::read().sie()
 }
 
+/// `sstatus.SIE`: supervisor interrupts are enabled.
+const SSTATUS_SIE: usize = 1 << 1;
+
+/// Makes the current CPU to ignore interrupts, and returns the previous state
+/// of `sstatus.SIE`.
+#[inline]
+pub fn local_irq_save() -> IrqFlags {
+    let sstatus: usize;
+    unsafe { core::arch::asm!("csrrc {}, sstatus, {}", out(reg) sstatus, in(reg) SSTATUS_SIE) };
+    IrqFlags(sstatus & SSTATUS_SIE)
+}
+
+/// Restores `sstatus.SIE` saved by [`local_irq_save`].
+#[inline]
+pub fn local_irq_restore(flags: IrqFlags) {
+    if flags.0 != 0 {
+        enable_irqs();
+    } else {
+        disable_irqs();
+    }
+}
+
 /// Relaxes the current CPU and waits for interrupts.
 ///
 /// It must be called with interrupts enabled, otherwise it will never return.
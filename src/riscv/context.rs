@@ -49,6 +49,10 @@ pub struct FpState {
     pub fp: [u64; 32],
     pub fcsr: usize,
     pub fs: FS,
+    /// the state of the vector extension, tracked by `sstatus.VS`
+    #[cfg(feature = "rvv")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rvv")))]
+    pub vector: super::VectorState,
 }
 
 impl Default for FpState {
@@ -57,6 +61,8 @@ impl Default for FpState {
             fs: FS::Initial,
             fp: [0; 32],
             fcsr: 0,
+            #[cfg(feature = "rvv")]
+            vector: Default::default(),
         }
     }
 }
@@ -94,18 +100,121 @@ impl FpState {
             // after saving, we set the FP state to clean
             self.fs = FS::Clean;
         }
-        // set the FP state to the next task's FP state
-        unsafe { sstatus::set_fs(next_fp_state.fs) };
-        // restore the next task's FP state
-        match next_fp_state.fs {
-            FS::Clean => next_fp_state.restore(), // the next task's FP state is clean, we should restore it
-            FS::Initial => FpState::clear(),      // restore the FP state as constant values(all 0)
-            FS::Off => {}                         // do nothing
+        #[cfg(not(feature = "fp-lazy"))]
+        next_fp_state.load();
+        #[cfg(feature = "fp-lazy")]
+        {
+            // the next task's FP state is loaded on its first use
+            unsafe { sstatus::set_fs(FS::Off) };
+            crate::lazy_fp::set_current(next_fp_state);
+        }
+        #[cfg(feature = "rvv")]
+        self.vector.switch_to(&next_fp_state.vector);
+    }
+
+    /// Loads this FP state to CPU, and sets the FP state in `sstatus` to
+    /// `self.fs`.
+    fn load(&self) {
+        // set the FP state to the task's FP state
+        unsafe { sstatus::set_fs(self.fs) };
+        // restore the task's FP state
+        match self.fs {
+            FS::Clean => self.restore(), // the task's FP state is clean, we should restore it
+            FS::Initial => FpState::clear(), // restore the FP state as constant values(all 0)
+            FS::Off => {}                // do nothing
             FS::Dirty => unreachable!("FP state of the next task should not be dirty"),
         }
     }
 }
 
+/// Floating-point states saved by a kernel-mode FP/SIMD section.
+///
+/// It has the same layout as the beginning of [`FpState`], without the vector
+/// states, which take several kilobytes. Vector instructions are disabled by
+/// `sstatus.VS` in the section instead, so the vector registers are kept.
+#[cfg(feature = "fp-simd")]
+#[repr(C)]
+pub(crate) struct KernelFpState {
+    fp: [u64; 32],
+    fcsr: usize,
+    fs: FS,
+    #[cfg(feature = "rvv")]
+    vs: riscv::register::mstatus::VS,
+}
+
+#[cfg(feature = "fp-simd")]
+static_assertions::const_assert_eq!(
+    core::mem::offset_of!(KernelFpState, fs),
+    core::mem::offset_of!(FpState, fs)
+);
+
+#[cfg(feature = "fp-simd")]
+impl Default for KernelFpState {
+    fn default() -> Self {
+        Self {
+            fp: [0; 32],
+            fcsr: 0,
+            fs: FS::Off,
+            #[cfg(feature = "rvv")]
+            vs: riscv::register::mstatus::VS::Off,
+        }
+    }
+}
+
+#[cfg(feature = "fp-simd")]
+impl KernelFpState {
+    /// Saves the live floating-point registers and `sstatus.FS` (and
+    /// `sstatus.VS`), enables the FPU and disables vector instructions for a
+    /// kernel-mode FP/SIMD section.
+    ///
+    /// Returns whether the registers are live, i.e., `sstatus.FS` is not `Off`.
+    pub(crate) fn kernel_fpu_begin(&mut self) -> bool {
+        #[cfg(feature = "rvv")]
+        {
+            self.vs = super::vector::read_vs();
+            unsafe { super::vector::write_vs(riscv::register::mstatus::VS::Off) };
+        }
+        self.fs = sstatus::read().fs();
+        let live = self.fs != FS::Off;
+        if live {
+            unsafe { save_fp_registers(self as *mut Self as *mut FpState) };
+        } else {
+            unsafe { sstatus::set_fs(FS::Initial) };
+        }
+        live
+    }
+
+    /// Restores the floating-point registers and `sstatus.FS` (and
+    /// `sstatus.VS`) saved by [`Self::kernel_fpu_begin`].
+    pub(crate) fn kernel_fpu_end(&self, live: bool) {
+        if live {
+            unsafe { restore_fp_registers(self as *const Self as *const FpState) };
+        }
+        unsafe { sstatus::set_fs(self.fs) };
+        #[cfg(feature = "rvv")]
+        unsafe {
+            super::vector::write_vs(self.vs)
+        };
+    }
+}
+
+/// Loads the FP state of the current task on the illegal instruction exception
+/// caused by its first FP instruction since the task was switched to.
+///
+/// Returns `false` if the FP state in `sstatus` is not `Off` or the current
+/// task does not use FP, i.e., the exception is not caused by lazy switching.
+#[cfg(feature = "fp-lazy")]
+pub(super) fn handle_fp_trap() -> bool {
+    if sstatus::read().fs() != FS::Off {
+        return false;
+    }
+    match unsafe { crate::lazy_fp::current::<FpState>() } {
+        Some(state) if state.fs != FS::Off => state.load(),
+        _ => return false,
+    }
+    true
+}
+
 /// Saved registers when a trap (interrupt or exception) occurs.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -198,6 +307,9 @@ pub struct TaskContext {
     pub satp: memory_addr::PhysAddr,
     #[cfg(feature = "fp-simd")]
     pub fp_state: FpState,
+    /// Hardware breakpoints of the task.
+    #[cfg(feature = "hw-breakpoint")]
+    pub hw_breakpoints: super::debug::HwBreakpoints,
 }
 
 impl TaskContext {
@@ -252,14 +364,18 @@ impl TaskContext {
         {
             self.fp_state.switch_to(&next_ctx.fp_state);
         }
+        #[cfg(feature = "hw-breakpoint")]
+        self.hw_breakpoints.switch_to(&next_ctx.hw_breakpoints);
 
         unsafe { context_switch(self, next_ctx) }
     }
 }
 
+/// Saves the floating-point registers and `fcsr` to the beginning of
+/// `fp_state`, which may also be a [`KernelFpState`].
 #[cfg(feature = "fp-simd")]
 #[unsafe(naked)]
-unsafe extern "C" fn save_fp_registers(fp_state: &mut FpState) {
+unsafe extern "C" fn save_fp_registers(fp_state: *mut FpState) {
     naked_asm!(
         include_fp_asm_macros!(),
         "
@@ -270,9 +386,11 @@ unsafe extern "C" fn save_fp_registers(fp_state: &mut FpState) {
     )
 }
 
+/// Restores the floating-point registers and `fcsr` from the beginning of
+/// `fp_state`, which may also be a [`KernelFpState`].
 #[cfg(feature = "fp-simd")]
 #[unsafe(naked)]
-unsafe extern "C" fn restore_fp_registers(fp_state: &FpState) {
+unsafe extern "C" fn restore_fp_registers(fp_state: *const FpState) {
     naked_asm!(
         include_fp_asm_macros!(),
         "
@@ -0,0 +1,559 @@
+//! Hardware debugging support.
+//!
+//! RISC-V has no single-step facility available to supervisor mode, so
+//! single-stepping is emulated: the instruction at the program counter is
+//! decoded to find where the execution goes next, and temporary breakpoints
+//! are inserted there.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use riscv::register::satp;
+
+use super::TrapFrame;
+
+const EBREAK: [u8; 4] = 0x0010_0073u32.to_le_bytes();
+const C_EBREAK: [u8; 2] = 0x9002u16.to_le_bytes();
+
+/// Maximum number of temporary breakpoints. Each stepped context uses at most
+/// two of them, one for each way of a conditional branch.
+const MAX_STEP_BREAKPOINTS: usize = 16;
+
+/// A temporary breakpoint inserted for single-stepping.
+#[derive(Clone, Copy)]
+struct StepBreakpoint {
+    /// `satp` of the address space where the breakpoint is inserted.
+    satp: usize,
+    /// Address of the stepped instruction.
+    origin: usize,
+    /// Address of the breakpoint.
+    addr: usize,
+    /// The original instruction at `addr`.
+    orig: [u8; 4],
+    /// Length of the original instruction, i.e., of the breakpoint.
+    len: usize,
+}
+
+struct StepBreakpoints {
+    locked: AtomicBool,
+    slots: UnsafeCell<[Option<StepBreakpoint>; MAX_STEP_BREAKPOINTS]>,
+}
+
+unsafe impl Sync for StepBreakpoints {}
+
+impl StepBreakpoints {
+    /// Runs `f` on the slots with the lock held and IRQs disabled.
+    ///
+    /// `f` must not access memory that may fault, e.g., user code.
+    fn with<R>(&self, f: impl FnOnce(&mut [Option<StepBreakpoint>]) -> R) -> R {
+        let irqs_enabled = crate::asm::irqs_enabled();
+        crate::asm::disable_irqs();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let ret = f(unsafe { &mut *self.slots.get() });
+        self.locked.store(false, Ordering::Release);
+        if irqs_enabled {
+            crate::asm::enable_irqs();
+        }
+        ret
+    }
+}
+
+static STEP_BREAKPOINTS: StepBreakpoints = StepBreakpoints {
+    locked: AtomicBool::new(false),
+    slots: UnsafeCell::new([None; MAX_STEP_BREAKPOINTS]),
+};
+
+/// Reads code at `addr`, which may be in user space.
+fn read_code(addr: usize, buf: &mut [u8]) -> bool {
+    #[cfg(feature = "uspace")]
+    let ok = unsafe { crate::uaccess::copy_from_user(buf, addr as _) == 0 };
+    #[cfg(not(feature = "uspace"))]
+    let ok = {
+        unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), buf.len()) };
+        true
+    };
+    ok
+}
+
+/// Writes code at `addr`, which may be in user space.
+fn write_code(addr: usize, code: &[u8]) -> bool {
+    #[cfg(feature = "uspace")]
+    let ok = unsafe { crate::uaccess::copy_to_user(addr as _, code) == 0 };
+    #[cfg(not(feature = "uspace"))]
+    let ok = {
+        unsafe { core::ptr::copy_nonoverlapping(code.as_ptr(), addr as *mut u8, code.len()) };
+        true
+    };
+    unsafe { core::arch::asm!("fence.i") };
+    ok
+}
+
+/// Reads the instruction at `pc`, returning it and its length in bytes.
+pub(super) fn read_insn(pc: usize) -> Option<(u32, usize)> {
+    let mut buf = [0; 4];
+    read_code(pc, &mut buf[..2]).then_some(())?;
+    // The lowest two bits of 32-bit instructions are always `0b11`, while those
+    // of compressed instructions are not.
+    if buf[0] & 0b11 != 0b11 {
+        return Some((u16::from_le_bytes([buf[0], buf[1]]) as u32, 2));
+    }
+    read_code(pc + 2, &mut buf[2..]).then_some(())?;
+    Some((u32::from_le_bytes(buf), 4))
+}
+
+/// Reads the general register `x{n}`.
+pub(super) fn xreg(tf: &TrapFrame, n: u32) -> usize {
+    let regs = unsafe { &*(&tf.regs as *const _ as *const [usize; 32]) };
+    if n == 0 {
+        0
+    } else {
+        regs[n as usize]
+    }
+}
+
+/// Extracts bits `lo..=hi` of `insn`, shifted to bit `to`.
+pub(super) const fn bits(insn: u32, hi: u32, lo: u32, to: u32) -> u32 {
+    ((insn >> lo) & ((1 << (hi - lo + 1)) - 1)) << to
+}
+
+/// Sign-extends the lowest `width` bits of `imm`.
+pub(super) const fn sext(imm: u32, width: u32) -> isize {
+    ((imm << (32 - width)) as i32 >> (32 - width)) as isize
+}
+
+/// Returns the addresses where the execution may go after the instruction at
+/// `pc`. The second one is only present for conditional branches.
+fn next_pcs(tf: &TrapFrame, pc: usize) -> Option<(usize, Option<usize>)> {
+    let (insn, len) = read_insn(pc)?;
+    let next = pc + len;
+    let rel = |imm: isize| pc.wrapping_add_signed(imm);
+    if len == 4 {
+        let rs1 = bits(insn, 19, 15, 0);
+        return Some(match insn & 0x7f {
+            // JAL
+            0b110_1111 => {
+                let imm = bits(insn, 31, 31, 20)
+                    | bits(insn, 30, 21, 1)
+                    | bits(insn, 20, 20, 11)
+                    | bits(insn, 19, 12, 12);
+                (rel(sext(imm, 21)), None)
+            }
+            // JALR
+            0b110_0111 => {
+                let target = xreg(tf, rs1).wrapping_add_signed(sext(insn >> 20, 12));
+                (target & !1, None)
+            }
+            // BRANCH
+            0b110_0011 => {
+                let imm = bits(insn, 31, 31, 12)
+                    | bits(insn, 30, 25, 5)
+                    | bits(insn, 11, 8, 1)
+                    | bits(insn, 7, 7, 11);
+                (next, Some(rel(sext(imm, 13))))
+            }
+            _ => (next, None),
+        });
+    }
+
+    let cj_target = || {
+        let imm = bits(insn, 12, 12, 11)
+            | bits(insn, 11, 11, 4)
+            | bits(insn, 10, 9, 8)
+            | bits(insn, 8, 8, 10)
+            | bits(insn, 7, 7, 6)
+            | bits(insn, 6, 6, 7)
+            | bits(insn, 5, 3, 1)
+            | bits(insn, 2, 2, 5);
+        rel(sext(imm, 12))
+    };
+    let rs1 = bits(insn, 11, 7, 0);
+    let rs2 = bits(insn, 6, 2, 0);
+    Some(match (insn & 0b11, insn >> 13) {
+        // C.J
+        (0b01, 0b101) => (cj_target(), None),
+        // C.JAL
+        #[cfg(target_arch = "riscv32")]
+        (0b01, 0b001) => (cj_target(), None),
+        // C.BEQZ, C.BNEZ
+        (0b01, 0b110 | 0b111) => {
+            let imm = bits(insn, 12, 12, 8)
+                | bits(insn, 11, 10, 3)
+                | bits(insn, 6, 5, 6)
+                | bits(insn, 4, 3, 1)
+                | bits(insn, 2, 2, 5);
+            (next, Some(rel(sext(imm, 9))))
+        }
+        // C.JR, C.JALR
+        (0b10, 0b100) if rs1 != 0 && rs2 == 0 => (xreg(tf, rs1) & !1, None),
+        _ => (next, None),
+    })
+}
+
+/// Inserts a temporary breakpoint at `addr` for stepping the instruction at
+/// `origin`.
+///
+/// The slot is reserved under the lock, but the code is read and written
+/// without holding it, as the access may fault and run page fault handlers.
+fn insert(satp: usize, origin: usize, addr: usize) -> bool {
+    let mut orig = [0; 4];
+    let Some((_, len)) = read_insn(addr) else {
+        return false;
+    };
+    if !read_code(addr, &mut orig[..len]) {
+        return false;
+    }
+    let bp = StepBreakpoint {
+        satp,
+        origin,
+        addr,
+        orig,
+        len,
+    };
+    let reserved = STEP_BREAKPOINTS.with(|slots| {
+        // The code read above may be a breakpoint inserted by another context.
+        if slots
+            .iter()
+            .flatten()
+            .any(|bp| bp.satp == satp && bp.addr == addr)
+        {
+            return false;
+        }
+        let Some(slot) = slots.iter_mut().find(|slot| slot.is_none()) else {
+            return false;
+        };
+        *slot = Some(bp);
+        true
+    });
+    if !reserved {
+        return false;
+    }
+    let code: &[u8] = if len == 2 { &C_EBREAK } else { &EBREAK };
+    if !write_code(addr, code) {
+        STEP_BREAKPOINTS.with(|slots| {
+            for slot in slots.iter_mut() {
+                if slot.is_some_and(|bp| bp.satp == satp && bp.addr == addr) {
+                    *slot = None;
+                }
+            }
+        });
+        return false;
+    }
+    true
+}
+
+/// Removes the temporary breakpoints inserted for stepping the instruction at
+/// `origin`, restoring the original instructions.
+///
+/// The original instructions are written back before the slots are freed and
+/// without holding the lock, so that a breakpoint hit in the meantime is still
+/// recognized.
+fn remove(satp: usize, origin: usize) {
+    let mut bps = [None; MAX_STEP_BREAKPOINTS];
+    STEP_BREAKPOINTS.with(|slots| {
+        let found = slots
+            .iter()
+            .flatten()
+            .filter(|bp| bp.satp == satp && bp.origin == origin);
+        for (bp, found) in bps.iter_mut().zip(found) {
+            *bp = Some(*found);
+        }
+    });
+    if bps.iter().all(Option::is_none) {
+        return;
+    }
+    for bp in bps.iter().flatten() {
+        write_code(bp.addr, &bp.orig[..bp.len]);
+    }
+    STEP_BREAKPOINTS.with(|slots| {
+        for slot in slots.iter_mut() {
+            if slot.is_some_and(|bp| bp.satp == satp && bp.origin == origin) {
+                *slot = None;
+            }
+        }
+    });
+}
+
+/// Turns single-stepping on or off for the context in the trap frame.
+///
+/// When it is on, a [`SINGLE_STEP`](crate::trap::SINGLE_STEP) trap is taken
+/// after the context executes one instruction on return from the trap. It is
+/// emulated by inserting temporary breakpoints where the execution goes next,
+/// which are removed when the trap is taken. Therefore:
+///
+/// - The code must be writable by the kernel. If the `uspace` feature is
+///   enabled, user code is written with fault-safe user access, so this
+///   function fails instead of panicking on unwritable code. User text is
+///   usually mapped read-only, in which case the write faults, and succeeds
+///   only if a [`PAGE_FAULT`](crate::trap::PAGE_FAULT) handler makes the page
+///   writable, e.g., by copy-on-write as for `ptrace`.
+/// - The context must be returned to on the current CPU, as only the local
+///   instruction cache is flushed.
+/// - Other contexts in the same address space executing the target
+///   instructions also take the trap.
+/// - A branch or jump to the stepped instruction itself is not trapped, as the
+///   breakpoint would replace the stepped instruction. A conditional branch
+///   to itself is stepped when it falls through.
+///
+/// Returns `false` if the next instruction cannot be decoded, it jumps to
+/// itself unconditionally, or the temporary breakpoints cannot be inserted.
+pub fn set_single_step(tf: &mut TrapFrame, on: bool) -> bool {
+    let satp = satp::read().bits();
+    let pc = tf.sepc;
+    remove(satp, pc);
+    if !on {
+        return true;
+    }
+    let Some((next, branch)) = next_pcs(tf, pc) else {
+        return false;
+    };
+    let branch = branch.filter(|&addr| addr != next);
+    let targets = [Some(next), branch];
+    let mut targets = targets
+        .iter()
+        .flatten()
+        .filter(|&&addr| addr != pc)
+        .peekable();
+    if targets.peek().is_none() {
+        return false;
+    }
+    let ok = targets.all(|&addr| insert(satp, pc, addr));
+    if !ok {
+        remove(satp, pc);
+    }
+    ok
+}
+
+/// Removes the temporary breakpoints if the breakpoint trap is caused by one
+/// of them.
+///
+/// Returns whether the trap is a single-step trap.
+pub(super) fn handle_step_breakpoint(tf: &TrapFrame) -> bool {
+    let satp = satp::read().bits();
+    let origin = STEP_BREAKPOINTS.with(|slots| {
+        slots
+            .iter()
+            .flatten()
+            .find(|bp| bp.satp == satp && bp.addr == tf.sepc)
+            .map(|bp| bp.origin)
+    });
+    if let Some(origin) = origin {
+        remove(satp, origin);
+    }
+    origin.is_some()
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub use crate::hw_breakpoint::{HwBreakpoint, HwBreakpointKind, HwBreakpoints};
+
+/// Maximum number of hardware breakpoint slots, each of which is a trigger of
+/// the Sdtrig extension. Only triggers of the `mcontrol` or `mcontrol6` type
+/// that are accessible in supervisor mode can be used.
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) const NUM_HW_SLOTS: usize = 8;
+
+#[cfg(feature = "hw-breakpoint")]
+mod trigger {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    const TYPE_SHIFT: u32 = usize::BITS - 4;
+    const TYPE_MCONTROL: usize = 2;
+    const TYPE_MCONTROL6: usize = 6;
+    /// The trigger is only writable in debug mode.
+    const DMODE: usize = 1 << (usize::BITS - 5);
+
+    pub const MATCH_NAPOT: usize = 1 << 7;
+    pub const S: usize = 1 << 4;
+    pub const U: usize = 1 << 3;
+    pub const EXECUTE: usize = 1 << 2;
+    pub const STORE: usize = 1 << 1;
+    pub const LOAD: usize = 1 << 0;
+
+    static NUM_TRIGGERS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+    /// Selects the `n`-th trigger and returns its `tdata1`, or [`None`] if
+    /// there is no such trigger or the trigger registers are not accessible.
+    fn select(n: usize) -> Option<usize> {
+        let (selected, tdata1, ok): (usize, usize, usize);
+        unsafe {
+            core::arch::asm!(
+                "2: csrw tselect, {n}",
+                "3: csrr {selected}, tselect",
+                "4: csrr {tdata1}, tdata1",
+                "   li {ok}, 1",
+                "5:",
+                crate::asm_extable!("2b", "5b"),
+                crate::asm_extable!("3b", "5b"),
+                crate::asm_extable!("4b", "5b"),
+                n = in(reg) n,
+                selected = out(reg) selected,
+                tdata1 = out(reg) tdata1,
+                ok = inout(reg) 0usize => ok,
+            )
+        };
+        (ok != 0 && selected == n).then_some(tdata1)
+    }
+
+    /// Returns the number of usable triggers, which are probed on the first
+    /// call.
+    pub fn count() -> usize {
+        let num = NUM_TRIGGERS.load(Ordering::Relaxed);
+        if num != usize::MAX {
+            return num;
+        }
+        let num = (0..super::NUM_HW_SLOTS)
+            .take_while(|&n| {
+                select(n).is_some_and(|tdata1| {
+                    matches!(tdata1 >> TYPE_SHIFT, TYPE_MCONTROL | TYPE_MCONTROL6)
+                        && tdata1 & DMODE == 0
+                })
+            })
+            .count();
+        NUM_TRIGGERS.store(num, Ordering::Relaxed);
+        num
+    }
+
+    /// Reads `tdata1` and `tdata2` of the `n`-th trigger.
+    pub fn read(n: usize) -> (usize, usize) {
+        let (tdata1, tdata2);
+        unsafe {
+            core::arch::asm!(
+                "csrw tselect, {n}",
+                "csrr {tdata1}, tdata1",
+                "csrr {tdata2}, tdata2",
+                n = in(reg) n,
+                tdata1 = out(reg) tdata1,
+                tdata2 = out(reg) tdata2,
+            )
+        };
+        (tdata1, tdata2)
+    }
+
+    /// Writes the `n`-th trigger, keeping its type. `tdata1` holds the fields
+    /// other than the type.
+    pub fn write(n: usize, tdata1: usize, tdata2: usize) {
+        let ty = read(n).0 & !((1 << TYPE_SHIFT) - 1);
+        unsafe {
+            core::arch::asm!(
+                "csrw tselect, {n}",
+                "csrw tdata1, {ty}",
+                "csrw tdata2, {tdata2}",
+                "csrw tdata1, {tdata1}",
+                n = in(reg) n,
+                ty = in(reg) ty,
+                tdata1 = in(reg) ty | tdata1,
+                tdata2 = in(reg) tdata2,
+            )
+        };
+    }
+
+    /// Returns the range of addresses that the trigger matches.
+    pub fn range(tdata1: usize, tdata2: usize) -> core::ops::Range<usize> {
+        if tdata1 & MATCH_NAPOT != 0 {
+            // The size is twice of the value of the trailing ones.
+            let size = 1 << (tdata2.trailing_ones() + 1);
+            let base = tdata2 & !(size - 1);
+            base..base + size
+        } else {
+            tdata2..tdata2 + 1
+        }
+    }
+}
+
+/// Returns the `tdata1` fields other than the type and `tdata2` of the
+/// breakpoint.
+#[cfg(feature = "hw-breakpoint")]
+fn encode(bp: &HwBreakpoint) -> Option<(usize, usize)> {
+    use trigger::*;
+    let access = match bp.kind {
+        HwBreakpointKind::Execute => {
+            return bp
+                .addr
+                .is_multiple_of(2)
+                .then_some((EXECUTE | S | U, bp.addr));
+        }
+        HwBreakpointKind::Read => LOAD,
+        HwBreakpointKind::Write => STORE,
+        HwBreakpointKind::ReadWrite => LOAD | STORE,
+    };
+    match bp.len {
+        1 => Some((access | S | U, bp.addr)),
+        2 | 4 | 8 if bp.addr.is_multiple_of(bp.len) => {
+            Some((MATCH_NAPOT | access | S | U, bp.addr | (bp.len / 2 - 1)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn hw_slot_supports(slot: usize, bp: &HwBreakpoint) -> bool {
+    slot < trigger::count() && encode(bp).is_some()
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn install_hw_breakpoints(slots: &[Option<HwBreakpoint>; NUM_HW_SLOTS]) {
+    for (n, bp) in slots.iter().take(trigger::count()).enumerate() {
+        let (tdata1, tdata2) = bp.and_then(|bp| encode(&bp)).unwrap_or((0, 0));
+        trigger::write(n, tdata1, tdata2);
+    }
+}
+
+/// Reports the hardware breakpoints that hit if the breakpoint trap is caused
+/// by triggers.
+///
+/// Triggers fire before the instruction is executed, so the triggers that hit
+/// are disabled, and the instruction is single-stepped before they are enabled
+/// again by [`rearm_hw_breakpoints`].
+///
+/// Returns whether any trigger hits.
+#[cfg(feature = "hw-breakpoint")]
+pub(super) fn handle_hw_breakpoints(tf: &mut TrapFrame) -> bool {
+    use trigger::*;
+    let stval = riscv::register::stval::read();
+    let mut hit = false;
+    for n in 0..count() {
+        let (tdata1, tdata2) = read(n);
+        let addr = if tdata1 & EXECUTE != 0 {
+            tf.sepc
+        } else {
+            stval
+        };
+        if tdata1 & (S | U) == 0 || !range(tdata1, tdata2).contains(&addr) {
+            continue;
+        }
+        hit = true;
+        write(n, tdata1 & !(S | U), tdata2);
+        if !handle_trap!(HW_BREAKPOINT, tf, n, va!(addr)) {
+            debug!("Hardware breakpoint {} hit @ {:#x}", n, tf.sepc);
+        }
+    }
+    if hit && !set_single_step(tf, true) {
+        warn!("Failed to step over hardware breakpoints @ {:#x}", tf.sepc);
+    }
+    hit
+}
+
+/// Enables the triggers disabled by [`handle_hw_breakpoints`] after the
+/// instruction is single-stepped.
+///
+/// Returns `false` if no trigger is disabled, i.e., the single-step trap is not
+/// for stepping over a hardware breakpoint.
+#[cfg(feature = "hw-breakpoint")]
+pub(super) fn rearm_hw_breakpoints() -> bool {
+    use trigger::*;
+    let mut rearmed = false;
+    for n in 0..count() {
+        let (tdata1, tdata2) = read(n);
+        // Unused triggers match no access.
+        if tdata1 & (EXECUTE | STORE | LOAD) != 0 && tdata1 & (S | U) == 0 {
+            write(n, tdata1 | S | U, tdata2);
+            rearmed = true;
+        }
+    }
+    rearmed
+}
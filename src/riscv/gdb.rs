@@ -0,0 +1,68 @@
+//! Mapping of RISC-V trap frames to the GDB register layout.
+
+use crate::TrapFrame;
+
+/// Number of registers in the `g` packet: `x0` to `x31`, followed by `pc`.
+pub(crate) const NUM_REGS: usize = 33;
+
+const XLEN: usize = size_of::<usize>();
+
+/// The general registers in the order of `x0` to `x31`.
+fn xregs(tf: &TrapFrame) -> &[usize; 32] {
+    unsafe { &*(&tf.regs as *const _ as *const [usize; 32]) }
+}
+
+/// Reads the `n`-th register of GDB, returning the value and its size in
+/// bytes.
+pub(crate) fn read_reg(tf: &TrapFrame, n: usize) -> Option<(u64, usize)> {
+    Some(match n {
+        0..32 => (xregs(tf)[n] as _, XLEN),
+        32 => (tf.sepc as _, XLEN),
+        _ => return None,
+    })
+}
+
+/// Writes the `n`-th register of GDB. Returns `false` if it is not writable.
+pub(crate) fn write_reg(tf: &mut TrapFrame, n: usize, val: u64) -> bool {
+    match n {
+        // `x0` is hardwired to zero.
+        0 => {}
+        1..32 => {
+            let xregs = unsafe { &mut *(&mut tf.regs as *mut _ as *mut [usize; 32]) };
+            xregs[n] = val as _;
+        }
+        32 => tf.sepc = val as _,
+        _ => return false,
+    }
+    true
+}
+
+/// `ebreak`
+const EBREAK: [u8; 4] = 0x0010_0073u32.to_le_bytes();
+/// `c.ebreak`
+const C_EBREAK: [u8; 2] = 0x9002u16.to_le_bytes();
+
+/// Returns the breakpoint instruction of the given GDB breakpoint kind.
+pub(crate) fn breakpoint_insn(kind: usize) -> Option<&'static [u8]> {
+    match kind {
+        2 => Some(&C_EBREAK),
+        4 => Some(&EBREAK),
+        _ => None,
+    }
+}
+
+/// Returns the address of the breakpoint instruction that caused the trap.
+pub(crate) fn breakpoint_addr(tf: &TrapFrame) -> usize {
+    tf.sepc
+}
+
+/// Sets the program counter in the trap frame.
+pub(crate) fn set_pc(tf: &mut TrapFrame, pc: usize) {
+    tf.sepc = pc;
+}
+
+/// Makes the modified instructions in `addr..addr + len` visible to
+/// instruction fetch.
+pub(crate) fn flush_icache(_addr: usize, _len: usize) {
+    unsafe { core::arch::asm!("fence.i") };
+}
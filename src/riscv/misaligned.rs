@@ -0,0 +1,217 @@
+//! Emulation of misaligned loads and stores.
+//!
+//! Integer loads and stores, including the compressed forms, are emulated by
+//! accessing the memory byte by byte. Floating-point and atomic accesses are
+//! not emulated and are reported as exceptions.
+
+use super::debug::{bits, read_insn, sext, xreg};
+use super::TrapFrame;
+
+/// A decoded load or store instruction.
+struct Access {
+    /// Whether it is a store.
+    store: bool,
+    /// Number of bytes to access.
+    len: usize,
+    /// Whether the loaded value is sign-extended.
+    signed: bool,
+    /// The destination register of loads, or the source register of stores.
+    reg: u32,
+    /// The base address register.
+    base: u32,
+    /// Offset from the base address.
+    offset: isize,
+}
+
+impl Access {
+    const fn new(
+        store: bool,
+        len: usize,
+        signed: bool,
+        reg: u32,
+        base: u32,
+        offset: isize,
+    ) -> Self {
+        Self {
+            store,
+            len,
+            signed,
+            reg,
+            base,
+            offset,
+        }
+    }
+}
+
+/// Decodes the integer load or store instruction `insn` of `len` bytes.
+fn decode(insn: u32, len: usize) -> Option<Access> {
+    let rv64 = usize::BITS == 64;
+    if len == 4 {
+        let funct3 = bits(insn, 14, 12, 0);
+        let rs1 = bits(insn, 19, 15, 0);
+        return match insn & 0x7f {
+            // LB, LH, LW, LD, LBU, LHU, LWU
+            0b000_0011 => {
+                let (len, signed) = match funct3 {
+                    0b000 => (1, true),
+                    0b001 => (2, true),
+                    0b010 => (4, true),
+                    0b011 if rv64 => (8, true),
+                    0b100 => (1, false),
+                    0b101 => (2, false),
+                    0b110 if rv64 => (4, false),
+                    _ => return None,
+                };
+                let imm = bits(insn, 31, 20, 0);
+                let rd = bits(insn, 11, 7, 0);
+                Some(Access::new(false, len, signed, rd, rs1, sext(imm, 12)))
+            }
+            // SB, SH, SW, SD
+            0b010_0011 => {
+                let len = match funct3 {
+                    0b000 => 1,
+                    0b001 => 2,
+                    0b010 => 4,
+                    0b011 if rv64 => 8,
+                    _ => return None,
+                };
+                let imm = bits(insn, 31, 25, 5) | bits(insn, 11, 7, 0);
+                let rs2 = bits(insn, 24, 20, 0);
+                Some(Access::new(true, len, false, rs2, rs1, sext(imm, 12)))
+            }
+            _ => None,
+        };
+    }
+
+    // Registers `x8` to `x15` in the 3-bit fields of compressed instructions.
+    let rd_ = bits(insn, 4, 2, 0) + 8;
+    let rs1_ = bits(insn, 9, 7, 0) + 8;
+    let word_imm = (bits(insn, 12, 10, 3) | bits(insn, 6, 6, 2) | bits(insn, 5, 5, 6)) as isize;
+    let double_imm = (bits(insn, 12, 10, 3) | bits(insn, 6, 5, 6)) as isize;
+    let sp = 2;
+    Some(match (insn & 0b11, bits(insn, 15, 13, 0)) {
+        // C.LW
+        (0b00, 0b010) => Access::new(false, 4, true, rd_, rs1_, word_imm),
+        // C.LD
+        (0b00, 0b011) if rv64 => Access::new(false, 8, true, rd_, rs1_, double_imm),
+        // C.LH, C.LHU and C.SH of the Zcb extension. Byte accesses are never
+        // misaligned.
+        (0b00, 0b100) => {
+            let imm = bits(insn, 5, 5, 1) as isize;
+            let bit6 = bits(insn, 6, 6, 0) != 0;
+            match bits(insn, 12, 10, 0) {
+                0b001 => Access::new(false, 2, bit6, rd_, rs1_, imm),
+                0b011 if !bit6 => Access::new(true, 2, false, rd_, rs1_, imm),
+                _ => return None,
+            }
+        }
+        // C.SW
+        (0b00, 0b110) => Access::new(true, 4, false, rd_, rs1_, word_imm),
+        // C.SD
+        (0b00, 0b111) if rv64 => Access::new(true, 8, false, rd_, rs1_, double_imm),
+        // C.LWSP
+        (0b10, 0b010) => {
+            let imm = bits(insn, 12, 12, 5) | bits(insn, 6, 4, 2) | bits(insn, 3, 2, 6);
+            Access::new(false, 4, true, bits(insn, 11, 7, 0), sp, imm as isize)
+        }
+        // C.LDSP
+        (0b10, 0b011) if rv64 => {
+            let imm = bits(insn, 12, 12, 5) | bits(insn, 6, 5, 3) | bits(insn, 4, 2, 6);
+            Access::new(false, 8, true, bits(insn, 11, 7, 0), sp, imm as isize)
+        }
+        // C.SWSP
+        (0b10, 0b110) => {
+            let imm = bits(insn, 12, 9, 2) | bits(insn, 8, 7, 6);
+            Access::new(true, 4, false, bits(insn, 6, 2, 0), sp, imm as isize)
+        }
+        // C.SDSP
+        (0b10, 0b111) if rv64 => {
+            let imm = bits(insn, 12, 10, 3) | bits(insn, 9, 7, 6);
+            Access::new(true, 8, false, bits(insn, 6, 2, 0), sp, imm as isize)
+        }
+        _ => return None,
+    })
+}
+
+/// Writes the general register `x{n}`. Writes to `x0` are discarded.
+fn set_xreg(tf: &mut TrapFrame, n: u32, value: usize) {
+    let regs = unsafe { &mut *(&mut tf.regs as *mut _ as *mut [usize; 32]) };
+    if n != 0 {
+        regs[n as usize] = value;
+    }
+}
+
+/// Reads data at `addr`, which may be in user space.
+fn read_data(addr: usize, buf: &mut [u8]) -> bool {
+    #[cfg(feature = "uspace")]
+    let ok = unsafe { crate::uaccess::copy_from_user(buf, addr as _) == 0 };
+    #[cfg(not(feature = "uspace"))]
+    let ok = {
+        unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), buf.len()) };
+        true
+    };
+    ok
+}
+
+/// Writes data at `addr`, which may be in user space.
+fn write_data(addr: usize, data: &[u8]) -> bool {
+    #[cfg(feature = "uspace")]
+    let ok = unsafe { crate::uaccess::copy_to_user(addr as _, data) == 0 };
+    #[cfg(not(feature = "uspace"))]
+    let ok = {
+        unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), addr as *mut u8, data.len()) };
+        true
+    };
+    ok
+}
+
+/// Emulates the misaligned load or store at `sepc`, and moves `sepc` to the
+/// next instruction.
+///
+/// Returns `false` if the instruction is not an integer load or store, or a
+/// fault occurs when accessing the instruction or the data, in which case the
+/// trap frame is not modified. If the access is `from_user`, it is also refused
+/// when the instruction or the data is not entirely in user space.
+pub(super) fn emulate_misaligned(tf: &mut TrapFrame, from_user: bool) -> bool {
+    // Both halves of a 32-bit instruction are read, so the whole 4 bytes are
+    // checked even for compressed instructions.
+    #[cfg(feature = "uspace")]
+    if from_user && !crate::uaccess::is_user_range(tf.sepc, 4) {
+        return false;
+    }
+    let Some((insn, insn_len)) = read_insn(tf.sepc) else {
+        return false;
+    };
+    let Some(access) = decode(insn, insn_len) else {
+        return false;
+    };
+    let addr = xreg(tf, access.base).wrapping_add_signed(access.offset);
+    let len = access.len;
+    #[cfg(feature = "uspace")]
+    if from_user && !crate::uaccess::is_user_range(addr, len) {
+        return false;
+    }
+    #[cfg(not(feature = "uspace"))]
+    let _ = from_user;
+    if access.store {
+        let data = xreg(tf, access.reg).to_le_bytes();
+        if !write_data(addr, &data[..len]) {
+            return false;
+        }
+    } else {
+        let mut data = [0; size_of::<usize>()];
+        if !read_data(addr, &mut data[..len]) {
+            return false;
+        }
+        let shift = usize::BITS - len as u32 * 8;
+        let value = usize::from_le_bytes(data);
+        let value = if access.signed {
+            ((value << shift) as isize >> shift) as usize
+        } else {
+            value
+        };
+        set_xreg(tf, access.reg, value);
+    }
+    tf.sepc += insn_len;
+    true
+}
@@ -4,10 +4,24 @@ mod macros;
 mod context;
 mod trap;
 
+#[cfg(feature = "misaligned-emu")]
+mod misaligned;
+#[cfg(feature = "rvv")]
+mod vector;
+
 pub mod asm;
+pub mod debug;
 pub mod init;
 
+#[cfg(feature = "gdbstub")]
+pub(crate) mod gdb;
+
 #[cfg(feature = "uspace")]
 pub mod uspace;
 
+#[cfg(feature = "fp-simd")]
+pub(crate) use self::context::KernelFpState;
 pub use self::context::{FpState, GeneralRegisters, TaskContext, TrapFrame};
+#[cfg(feature = "rvv")]
+#[cfg_attr(docsrs, doc(cfg(feature = "rvv")))]
+pub use self::vector::{vlenb, VectorState, RVV_MAX_VLENB};
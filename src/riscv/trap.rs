@@ -1,3 +1,4 @@
+use memory_addr::VirtAddr;
 use riscv::interrupt::supervisor::{Exception as E, Interrupt as I};
 use riscv::interrupt::Trap;
 #[cfg(feature = "fp-simd")]
@@ -5,7 +6,10 @@ use riscv::register::sstatus;
 use riscv::register::{scause, stval};
 
 use super::TrapFrame;
-use crate::trap::PageFaultFlags;
+use crate::extable::search_exception_table;
+#[cfg(feature = "uspace")]
+use crate::trap::SyscallAction;
+use crate::trap::{ExceptionInfo, ExceptionKind, PageFaultFlags};
 
 core::arch::global_asm!(
     include_asm_macros!(),
@@ -13,24 +17,75 @@ core::arch::global_asm!(
     trapframe_size = const core::mem::size_of::<TrapFrame>(),
 );
 
-fn handle_breakpoint(sepc: &mut usize) {
-    debug!("Exception(Breakpoint) @ {sepc:#x} ");
-    *sepc += 2
+fn handle_breakpoint(tf: &mut TrapFrame) {
+    if super::debug::handle_step_breakpoint(tf) {
+        // Steps over hardware breakpoints are not reported.
+        #[cfg(feature = "hw-breakpoint")]
+        let stepped_over = super::debug::rearm_hw_breakpoints();
+        #[cfg(not(feature = "hw-breakpoint"))]
+        let stepped_over = false;
+        if !stepped_over && !handle_trap!(SINGLE_STEP, tf) {
+            debug!("Single-step @ {:#x}", tf.sepc);
+        }
+        return;
+    }
+    #[cfg(feature = "hw-breakpoint")]
+    if super::debug::handle_hw_breakpoints(tf) {
+        return;
+    }
+    count_trap!(breakpoints);
+    if !handle_trap!(BREAKPOINT, tf) {
+        debug!("Exception(Breakpoint) @ {:#x} ", tf.sepc);
+        tf.sepc += breakpoint_len(tf.sepc);
+    }
 }
 
-fn handle_page_fault(tf: &TrapFrame, mut access_flags: PageFaultFlags, is_user: bool) {
+/// Returns the length of the `ebreak` (4 bytes) or `c.ebreak` (2 bytes)
+/// instruction at `pc`, or 4 if it cannot be read.
+fn breakpoint_len(pc: usize) -> usize {
+    super::debug::read_insn(pc).map_or(4, |(_, len)| len)
+}
+
+fn handle_exception(
+    tf: &mut TrapFrame,
+    kind: ExceptionKind,
+    vaddr: Option<VirtAddr>,
+    is_user: bool,
+) -> bool {
+    count_trap!(EXCEPTION, kind);
+    if !is_user {
+        if let Some(fixup) = search_exception_table(tf.sepc as _) {
+            tf.sepc = fixup as _;
+            return true;
+        }
+    }
+    let info = ExceptionInfo {
+        kind,
+        pc: tf.sepc,
+        cause: scause::read().bits(),
+        vaddr,
+        is_user,
+    };
+    handle_trap!(EXCEPTION, tf, &info)
+}
+
+fn handle_page_fault(tf: &mut TrapFrame, mut access_flags: PageFaultFlags, is_user: bool) {
+    count_trap!(page_faults);
     if is_user {
         access_flags |= PageFaultFlags::USER;
     }
     let vaddr = va!(stval::read());
-    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
+    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
+        && !handle_exception(tf, ExceptionKind::PageFault, Some(vaddr), is_user)
+    {
         panic!(
-            "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
+            "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}\n{}",
             if is_user { "User" } else { "Supervisor" },
             tf.sepc,
             vaddr,
             access_flags,
             tf,
+            crate::backtrace::trap_backtrace(tf),
         );
     }
 }
@@ -38,12 +93,24 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: PageFaultFlags, is_user:
 #[unsafe(no_mangle)]
 fn riscv_trap_handler(tf: &mut TrapFrame, from_user: bool) {
     let scause = scause::read();
+    #[cfg(feature = "trap-trace")]
+    let ty = cause_to_type(scause);
+    trace_trap!(TRAP_ENTER, ty, tf);
+    let mut is_irq = false;
     if let Ok(cause) = scause.cause().try_into::<I, E>() {
         match cause {
             #[cfg(feature = "uspace")]
             Trap::Exception(E::UserEnvCall) => {
-                tf.regs.a0 = crate::trap::handle_syscall(tf, tf.regs.a7) as usize;
+                let syscall_num = tf.regs.a7;
                 tf.sepc += 4;
+                match crate::trap::handle_syscall(tf, syscall_num) {
+                    SyscallAction::Return(ret) => tf.regs.a0 = ret as usize,
+                    SyscallAction::Restart => {
+                        tf.regs.a7 = syscall_num;
+                        tf.sepc -= 4;
+                    }
+                    SyscallAction::NoReturnValue => {}
+                }
             }
             Trap::Exception(E::LoadPageFault) => {
                 handle_page_fault(tf, PageFaultFlags::READ, from_user)
@@ -54,25 +121,74 @@ fn riscv_trap_handler(tf: &mut TrapFrame, from_user: bool) {
             Trap::Exception(E::InstructionPageFault) => {
                 handle_page_fault(tf, PageFaultFlags::EXECUTE, from_user)
             }
-            Trap::Exception(E::Breakpoint) => handle_breakpoint(&mut tf.sepc),
+            Trap::Exception(E::Breakpoint) => handle_breakpoint(tf),
+            #[cfg(feature = "fp-lazy")]
+            Trap::Exception(E::IllegalInstruction) if super::context::handle_fp_trap() => {}
+            #[cfg(feature = "misaligned-emu")]
+            Trap::Exception(E::LoadMisaligned | E::StoreMisaligned)
+                if super::misaligned::emulate_misaligned(tf, from_user) => {}
             Trap::Interrupt(_) => {
-                handle_trap!(IRQ, scause.bits());
+                let handled = handle_trap!(IRQ, scause.bits());
+                count_trap!(IRQ, scause.code(), handled);
+                is_irq = true;
             }
-            _ => {
-                panic!("Unhandled trap {:?} @ {:#x}:\n{:#x?}", cause, tf.sepc, tf);
+            Trap::Exception(e) => {
+                let (kind, vaddr) = match e {
+                    E::IllegalInstruction => (ExceptionKind::IllegalInstruction, None),
+                    E::InstructionMisaligned | E::LoadMisaligned | E::StoreMisaligned => {
+                        (ExceptionKind::Misaligned, Some(va!(stval::read())))
+                    }
+                    E::InstructionFault | E::LoadFault | E::StoreFault => {
+                        (ExceptionKind::AccessFault, Some(va!(stval::read())))
+                    }
+                    _ => (ExceptionKind::Other, None),
+                };
+                if !handle_exception(tf, kind, vaddr, from_user) {
+                    panic!(
+                        "Unhandled trap {:?} @ {:#x}:\n{:#x?}\n{}",
+                        cause,
+                        tf.sepc,
+                        tf,
+                        crate::backtrace::trap_backtrace(tf),
+                    );
+                }
             }
         }
-    } else {
+    } else if !handle_exception(tf, ExceptionKind::Other, None, from_user) {
         panic!(
-            "Unknown trap {:#x?} @ {:#x}:\n{:#x?}",
+            "Unknown trap {:#x?} @ {:#x}:\n{:#x?}\n{}",
             scause.cause(),
             tf.sepc,
-            tf
+            tf,
+            crate::backtrace::trap_backtrace(tf),
         );
     }
 
-    // Update tf.sstatus to preserve current hardware FS state
+    trace_trap!(TRAP_EXIT, ty, tf);
+    if is_irq {
+        irq_return!(tf);
+    }
+    // Update tf.sstatus to preserve current hardware FS (and VS) state
     // This replaces the assembly-level FS handling workaround
+    //
+    // Done last, since the hooks above may switch tasks and change the state.
     #[cfg(feature = "fp-simd")]
     tf.sstatus.set_fs(sstatus::read().fs());
+    #[cfg(feature = "rvv")]
+    super::vector::set_sstatus_vs(&mut tf.sstatus, super::vector::read_vs());
+}
+
+#[cfg(feature = "trap-trace")]
+fn cause_to_type(scause: scause::Scause) -> crate::trap::TrapType {
+    use crate::trap::TrapType;
+    match scause.cause().try_into::<I, E>() {
+        #[cfg(feature = "uspace")]
+        Ok(Trap::Exception(E::UserEnvCall)) => TrapType::Syscall,
+        Ok(Trap::Exception(E::LoadPageFault))
+        | Ok(Trap::Exception(E::StorePageFault))
+        | Ok(Trap::Exception(E::InstructionPageFault)) => TrapType::PageFault,
+        Ok(Trap::Exception(E::Breakpoint)) => TrapType::Breakpoint,
+        Ok(Trap::Interrupt(_)) => TrapType::Irq,
+        _ => TrapType::Exception,
+    }
 }
@@ -1,5 +1,7 @@
 //! Structures and functions for user space.
 
+use core::arch::naked_asm;
+
 use memory_addr::VirtAddr;
 use riscv::register::sstatus::Sstatus;
 #[cfg(feature = "fp-simd")]
@@ -7,6 +9,13 @@ use riscv::register::sstatus::FS;
 
 use crate::{GeneralRegisters, TrapFrame};
 
+pub use crate::uaccess::{
+    clear_user, copy_from_user, copy_to_user, is_user_range, strncpy_from_user,
+};
+
+#[cfg(target_arch = "riscv64")]
+pub mod signal;
+
 /// Context to enter user space.
 pub struct UspaceContext(TrapFrame);
 
@@ -26,6 +35,11 @@ impl UspaceContext {
         {
             sstatus.set_fs(FS::Initial); // set the FPU to initial state
         }
+        #[cfg(feature = "rvv")]
+        if super::vlenb() != 0 {
+            // set the vector unit to initial state
+            super::vector::set_sstatus_vs(&mut sstatus, riscv::register::mstatus::VS::Initial);
+        }
 
         Self(TrapFrame {
             regs: GeneralRegisters {
@@ -84,6 +98,14 @@ impl UspaceContext {
         crate::asm::disable_irqs();
         // Address of the top of the kernel stack after saving the trap frame.
         let kernel_trap_addr = kstack_top.as_usize() - core::mem::size_of::<TrapFrame>();
+        #[allow(unused_mut)]
+        let mut sstatus = self.0.sstatus;
+        // With lazy switching, the FP state of the current task is not loaded
+        // until its first use, and `sstatus.FS` is `Off` until then. Keep it
+        // instead of the saved one, which would otherwise let the user code
+        // run on the registers of the previous task.
+        #[cfg(feature = "fp-lazy")]
+        sstatus.set_fs(riscv::register::sstatus::read().fs());
         unsafe {
             sscratch::write(kstack_top.as_usize());
             sepc::write(self.0.sepc);
@@ -98,15 +120,116 @@ impl UspaceContext {
                 STR     tp, {kernel_trap_addr}, 4
                 LDR     tp, sp, 4
 
-                LDR     t0, sp, 33
-                csrw    sstatus, t0
+                csrw    sstatus, {sstatus}
                 POP_GENERAL_REGS
                 LDR     sp, sp, 2
                 sret",
                 tf = in(reg) &(self.0),
                 kernel_trap_addr = in(reg) kernel_trap_addr,
+                sstatus = in(reg) sstatus.bits(),
                 options(noreturn),
             )
         }
     }
 }
+
+/// Sets `sstatus.SUM` to permit supervisor user memory access, and keeps the
+/// original `sstatus` in `t2`. The mask of `SUM` is kept in `t1`.
+macro_rules! user_access_begin {
+    () => {
+        "
+        li      t1, 1 << 18     // sstatus.SUM
+        csrrs   t2, sstatus, t1"
+    };
+}
+
+/// Restores `sstatus.SUM`.
+macro_rules! user_access_end {
+    () => {
+        "
+        and     t2, t2, t1
+        bnez    t2, 9f
+        csrc    sstatus, t1
+    9:"
+    };
+}
+
+/// Copies `len` bytes from `src` to `dst`, either of which may be in user
+/// space. Returns the number of bytes not copied.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_copy_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> usize {
+    naked_asm!(
+        user_access_begin!(),
+        "
+        beqz    a2, 4f
+    2:  lbu     t0, 0(a1)
+    3:  sb      t0, 0(a0)
+        addi    a0, a0, 1
+        addi    a1, a1, 1
+        addi    a2, a2, -1
+        bnez    a2, 2b
+    4:",
+        user_access_end!(),
+        "
+        mv      a0, a2
+        ret",
+        crate::asm_extable!("2b", "4b"),
+        crate::asm_extable!("3b", "4b"),
+    )
+}
+
+/// Fills `len` bytes of user space at `dst` with zeros. Returns the number of
+/// bytes not cleared.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_clear_user(_dst: *mut u8, _len: usize) -> usize {
+    naked_asm!(
+        user_access_begin!(),
+        "
+        beqz    a1, 4f
+    2:  sb      zero, 0(a0)
+        addi    a0, a0, 1
+        addi    a1, a1, -1
+        bnez    a1, 2b
+    4:",
+        user_access_end!(),
+        "
+        mv      a0, a1
+        ret",
+        crate::asm_extable!("2b", "4b"),
+    )
+}
+
+/// Copies a NUL-terminated string of at most `len` bytes from user space.
+/// Returns the length of the string, `len` if it is not terminated within
+/// `len` bytes, or `-1` on fault.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_strncpy_from_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> isize {
+    naked_asm!(
+        user_access_begin!(),
+        "
+        li      t3, 0
+    2:  beq     t3, a2, 4f
+        add     t4, a1, t3
+    3:  lbu     t0, 0(t4)
+        add     t4, a0, t3
+        sb      t0, 0(t4)
+        beqz    t0, 4f
+        addi    t3, t3, 1
+        j       2b
+    5:  li      t3, -1
+    4:",
+        user_access_end!(),
+        "
+        mv      a0, t3
+        ret",
+        crate::asm_extable!("3b", "5b"),
+    )
+}
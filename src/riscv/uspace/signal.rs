@@ -0,0 +1,156 @@
+//! Signal frames compatible with the riscv64 Linux ABI.
+
+use super::UspaceContext;
+use crate::signal::{read_from_user, write_to_user};
+use crate::{GeneralRegisters, TrapFrame};
+
+pub use crate::signal::{SignalDelivery, SignalInfo, SignalStack};
+
+/// `union __riscv_fp_state` in Linux, of which only the D extension part is
+/// used.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct FpRegs {
+    f: [u64; 32],
+    fcsr: u32,
+    _reserved: [u32; 67],
+}
+
+/// `struct sigcontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SigContext {
+    /// `struct user_regs_struct`, which has the same layout as
+    /// [`GeneralRegisters`] except that `pc` takes the place of `zero`.
+    regs: GeneralRegisters,
+    fpregs: FpRegs,
+}
+
+/// `struct ucontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UContext {
+    flags: u64,
+    link: u64,
+    stack: SignalStack,
+    sigmask: u64,
+    _unused: [u8; 1024 / 8 - 8],
+    _pad: u64,
+    mcontext: SigContext,
+}
+
+/// `struct rt_sigframe` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SignalFrame {
+    info: SignalInfo,
+    uc: UContext,
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<SigContext>(), 784);
+static_assertions::const_assert_eq!(core::mem::size_of::<SignalFrame>(), 1088);
+
+/// Pushes a signal frame onto the user stack, and redirects the trap frame to
+/// the signal handler.
+///
+/// The floating-point registers are saved in the frame if the `fp-simd`
+/// feature is enabled. The address of the trampoline is set to the return
+/// address register (`ra`) of the handler.
+///
+/// Returns `false` if the user stack is not writable, in which case the trap
+/// frame is not modified.
+///
+/// # Safety
+///
+/// The caller must ensure that the user stack to push the frame onto is in
+/// user space.
+pub unsafe fn setup_sigframe(tf: &mut TrapFrame, delivery: &SignalDelivery) -> bool {
+    let sp = delivery.stack_top(tf.regs.sp);
+    let frame_addr = (sp - core::mem::size_of::<SignalFrame>()) & !15;
+
+    let mut frame: SignalFrame = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
+    frame.info = delivery.info;
+    frame.uc.stack = delivery.altstack;
+    frame.uc.sigmask = delivery.sigmask;
+    let sc = &mut frame.uc.mcontext;
+    sc.regs = tf.regs;
+    sc.regs.zero = tf.sepc;
+    #[cfg(feature = "fp-simd")]
+    if riscv::register::sstatus::read().fs() != riscv::register::sstatus::FS::Off {
+        let mut state = crate::FpState::default();
+        state.save();
+        sc.fpregs.f = state.fp;
+        sc.fpregs.fcsr = state.fcsr as _;
+    }
+    if !unsafe { write_to_user(frame_addr, &frame) } {
+        return false;
+    }
+
+    tf.regs.a0 = delivery.info.signo as _;
+    tf.regs.a1 = frame_addr + core::mem::offset_of!(SignalFrame, info);
+    tf.regs.a2 = frame_addr + core::mem::offset_of!(SignalFrame, uc);
+    tf.regs.ra = delivery.restorer;
+    tf.regs.sp = frame_addr;
+    tf.sepc = delivery.handler;
+    true
+}
+
+/// Restores the trap frame from the signal frame on the user stack, which is
+/// pushed by [`setup_sigframe`]. It should be called on `rt_sigreturn`.
+///
+/// Returns the signal mask saved in the frame, or [`None`] if the frame is not
+/// readable, in which case the trap frame is not modified.
+///
+/// # Safety
+///
+/// The caller must ensure that the user stack pointer in the trap frame points
+/// to user space.
+pub unsafe fn restore_from_sigframe(tf: &mut TrapFrame) -> Option<u64> {
+    let frame: SignalFrame = unsafe { read_from_user(tf.regs.sp)? };
+    let sc = &frame.uc.mcontext;
+
+    #[cfg(feature = "fp-simd")]
+    {
+        use riscv::register::sstatus::{self, FS};
+
+        let state = crate::FpState {
+            fp: sc.fpregs.f,
+            fcsr: sc.fpregs.fcsr as _,
+            fs: FS::Dirty,
+            #[cfg(feature = "rvv")]
+            vector: Default::default(),
+        };
+        unsafe { sstatus::set_fs(FS::Dirty) };
+        state.restore();
+    }
+
+    tf.regs = sc.regs;
+    tf.regs.zero = 0;
+    tf.sepc = sc.regs.zero;
+    Some(frame.uc.sigmask)
+}
+
+impl UspaceContext {
+    /// Pushes a signal frame onto the user stack, and redirects the context to
+    /// the signal handler.
+    ///
+    /// See [`setup_sigframe`] for details.
+    ///
+    /// # Safety
+    ///
+    /// See [`setup_sigframe`].
+    pub unsafe fn setup_sigframe(&mut self, delivery: &SignalDelivery) -> bool {
+        unsafe { setup_sigframe(&mut self.0, delivery) }
+    }
+
+    /// Restores the context from the signal frame on the user stack.
+    ///
+    /// See [`restore_from_sigframe`] for details.
+    ///
+    /// # Safety
+    ///
+    /// See [`restore_from_sigframe`].
+    pub unsafe fn restore_from_sigframe(&mut self) -> Option<u64> {
+        unsafe { restore_from_sigframe(&mut self.0) }
+    }
+}
@@ -0,0 +1,290 @@
+//! Vector extension (RVV) states.
+//!
+//! The vector states are tracked by `sstatus.VS` in the same way as the
+//! floating-point states by `sstatus.FS`: they are saved on context switches
+//! only if they are `Dirty`, restored if `Clean`, and cleared if `Initial`.
+//!
+//! The vector extension is detected by whether `sstatus.VS` is writable, and
+//! only supported if `vlenb` is not larger than [`RVV_MAX_VLENB`].
+
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use riscv::register::{mstatus::VS, sstatus::Sstatus};
+
+/// Maximum length in bytes of a vector register (`vlenb`).
+pub const RVV_MAX_VLENB: usize = 64;
+
+/// Offset of `sstatus.VS`, which is not defined in [`Sstatus`].
+const SSTATUS_VS_SHIFT: usize = 9;
+const SSTATUS_VS_MASK: usize = 0b11 << SSTATUS_VS_SHIFT;
+
+/// Detected `vlenb`, `0` if the vector extension is not supported, or
+/// `usize::MAX` if not detected yet.
+static VLENB: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn vs_from_bits(bits: usize) -> VS {
+    match (bits & SSTATUS_VS_MASK) >> SSTATUS_VS_SHIFT {
+        0 => VS::Off,
+        1 => VS::Initial,
+        2 => VS::Clean,
+        _ => VS::Dirty,
+    }
+}
+
+/// Reads `sstatus.VS`.
+pub(super) fn read_vs() -> VS {
+    let sstatus: usize;
+    unsafe { asm!("csrr {}, sstatus", out(reg) sstatus) };
+    vs_from_bits(sstatus)
+}
+
+/// Writes `sstatus.VS`.
+pub(super) unsafe fn write_vs(vs: VS) {
+    unsafe {
+        asm!(
+            "csrc sstatus, {mask}",
+            "csrs sstatus, {vs}",
+            mask = in(reg) SSTATUS_VS_MASK,
+            vs = in(reg) (vs as usize) << SSTATUS_VS_SHIFT,
+        )
+    }
+}
+
+/// Sets the `VS` field of the given `sstatus` value, which cannot be set by
+/// the methods of [`Sstatus`].
+pub(super) fn set_sstatus_vs(sstatus: &mut Sstatus, vs: VS) {
+    // SAFETY: `Sstatus` is a `#[repr(C)]` wrapper of the raw bits.
+    let bits = unsafe { &mut *(sstatus as *mut Sstatus as *mut usize) };
+    *bits = (*bits & !SSTATUS_VS_MASK) | (vs as usize) << SSTATUS_VS_SHIFT;
+}
+
+/// Returns the length in bytes of a vector register, or `0` if the vector
+/// extension is not supported.
+pub fn vlenb() -> usize {
+    let vlenb = VLENB.load(Ordering::Relaxed);
+    if vlenb != usize::MAX {
+        return vlenb;
+    }
+    let old_vs = read_vs();
+    unsafe { write_vs(VS::Initial) };
+    let vlenb = if read_vs() == VS::Off {
+        0
+    } else {
+        let vlenb: usize;
+        unsafe {
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "csrr {}, vlenb",
+                ".option pop",
+                out(reg) vlenb,
+            )
+        };
+        if vlenb > RVV_MAX_VLENB {
+            warn!("vlenb {vlenb} is larger than {RVV_MAX_VLENB}, vector extension disabled");
+            0
+        } else {
+            vlenb
+        }
+    };
+    unsafe { write_vs(old_vs) };
+    VLENB.store(vlenb, Ordering::Relaxed);
+    vlenb
+}
+
+/// Vector registers and CSRs of RISC-V.
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+pub struct VectorState {
+    /// Vector registers (v0-v31), each of `vlenb` bytes.
+    pub v: [u8; 32 * RVV_MAX_VLENB],
+    /// Vector start position (`vstart`).
+    pub vstart: usize,
+    /// Vector length (`vl`).
+    pub vl: usize,
+    /// Vector data type register (`vtype`).
+    pub vtype: usize,
+    /// Vector control and status register (`vcsr`).
+    pub vcsr: usize,
+    /// The state of the vector extension.
+    pub vs: VS,
+}
+
+impl Default for VectorState {
+    fn default() -> Self {
+        Self {
+            v: [0; 32 * RVV_MAX_VLENB],
+            vstart: 0,
+            vl: 0,
+            vtype: 0,
+            vcsr: 0,
+            vs: VS::Initial,
+        }
+    }
+}
+
+impl fmt::Debug for VectorState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VectorState")
+            .field("vstart", &self.vstart)
+            .field("vl", &self.vl)
+            .field("vtype", &self.vtype)
+            .field("vcsr", &self.vcsr)
+            .field("vs", &self.vs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VectorState {
+    /// Saves the current vector registers to this vector state.
+    ///
+    /// `sstatus.VS` must not be `Off`.
+    pub fn save(&mut self) {
+        unsafe {
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "csrr {vstart}, vstart",
+                "csrr {vl}, vl",
+                "csrr {vtype}, vtype",
+                "csrr {vcsr}, vcsr",
+                // Whole register stores start from `vstart`.
+                "csrw vstart, zero",
+                "csrr {t}, vlenb",
+                "slli {t}, {t}, 3",
+                "vs8r.v v0, ({v})",
+                "add {v}, {v}, {t}",
+                "vs8r.v v8, ({v})",
+                "add {v}, {v}, {t}",
+                "vs8r.v v16, ({v})",
+                "add {v}, {v}, {t}",
+                "vs8r.v v24, ({v})",
+                ".option pop",
+                v = inout(reg) self.v.as_mut_ptr() => _,
+                t = out(reg) _,
+                vstart = out(reg) self.vstart,
+                vl = out(reg) self.vl,
+                vtype = out(reg) self.vtype,
+                vcsr = out(reg) self.vcsr,
+            )
+        }
+    }
+
+    /// Restores the vector registers from this vector state.
+    ///
+    /// `sstatus.VS` must not be `Off`.
+    pub fn restore(&self) {
+        unsafe {
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "csrw vstart, zero",
+                "csrr {t}, vlenb",
+                "slli {t}, {t}, 3",
+                "vl8re8.v v0, ({v})",
+                "add {v}, {v}, {t}",
+                "vl8re8.v v8, ({v})",
+                "add {v}, {v}, {t}",
+                "vl8re8.v v16, ({v})",
+                "add {v}, {v}, {t}",
+                "vl8re8.v v24, ({v})",
+                "vsetvl zero, {vl}, {vtype}",
+                "csrw vstart, {vstart}",
+                "csrw vcsr, {vcsr}",
+                ".option pop",
+                v = inout(reg) self.v.as_ptr() => _,
+                t = out(reg) _,
+                vl = in(reg) self.vl,
+                vtype = in(reg) self.vtype,
+                vstart = in(reg) self.vstart,
+                vcsr = in(reg) self.vcsr,
+            )
+        }
+    }
+
+    /// Clears all vector registers and CSRs to zero.
+    ///
+    /// `sstatus.VS` must not be `Off`.
+    pub fn clear() {
+        unsafe {
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                // A non-zero `rd` with `rs1 = zero` sets `vl` to VLMAX, so the
+                // whole register groups are written.
+                "vsetvli {t}, zero, e8, m8, ta, ma",
+                "vmv.v.i v0, 0",
+                "vmv.v.i v8, 0",
+                "vmv.v.i v16, 0",
+                "vmv.v.i v24, 0",
+                "vsetivli zero, 0, e8, m1, ta, ma",
+                "csrw vstart, zero",
+                "csrw vcsr, zero",
+                ".option pop",
+                t = out(reg) _,
+            )
+        }
+    }
+
+    /// Returns whether all vector registers (v0-v31) are zero.
+    ///
+    /// `sstatus.VS` must not be `Off`. `vl` and `vtype` are left as by
+    /// [`VectorState::clear`].
+    fn is_cleared() -> bool {
+        let bits: usize;
+        unsafe {
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "vsetvli {t}, zero, e8, m8, ta, ma",
+                // Fold all register groups into v24-v31, which are unchanged if
+                // all zero, then reduce them into the first element of v24.
+                "vor.vv v24, v24, v0",
+                "vor.vv v24, v24, v8",
+                "vor.vv v24, v24, v16",
+                "vredor.vs v24, v24, v24",
+                "vmv.x.s {bits}, v24",
+                "vsetivli zero, 0, e8, m1, ta, ma",
+                ".option pop",
+                t = out(reg) _,
+                bits = out(reg) bits,
+            )
+        }
+        bits == 0
+    }
+
+    /// Handles vector state context switching, in the same way as
+    /// [`FpState::switch_to`](super::FpState::switch_to).
+    ///
+    /// The vector states are always switched eagerly, even with the `fp-lazy`
+    /// feature.
+    pub fn switch_to(&mut self, next_vector_state: &VectorState) {
+        if vlenb() == 0 {
+            return;
+        }
+        if read_vs() == VS::Dirty {
+            self.save();
+            self.vs = VS::Clean;
+        }
+        next_vector_state.load();
+    }
+
+    /// Loads this vector state to CPU, and sets `sstatus.VS` to `self.vs`.
+    fn load(&self) {
+        unsafe { write_vs(self.vs) };
+        match self.vs {
+            VS::Clean => self.restore(),
+            VS::Initial => {
+                VectorState::clear();
+                debug_assert!(
+                    VectorState::is_cleared(),
+                    "vector registers are not cleared"
+                );
+            }
+            VS::Off => {}
+            VS::Dirty => unreachable!("vector state of the next task should not be dirty"),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Architecture-independent structures for signal delivery.
+
+use crate::uaccess::{copy_from_user, copy_to_user};
+
+/// Information about a signal, i.e., `siginfo_t` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalInfo {
+    /// Signal number.
+    pub signo: i32,
+    /// An errno value associated with the signal.
+    pub errno: i32,
+    /// Signal code, indicating why the signal was sent.
+    pub code: i32,
+    _pad: i32,
+    /// Signal-specific fields, i.e., the union in `siginfo_t`.
+    pub fields: [u64; 14],
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<SignalInfo>(), 128);
+
+impl SignalInfo {
+    /// Creates a new signal information with the given signal number and code,
+    /// and all other fields set to zero.
+    pub const fn new(signo: i32, code: i32) -> Self {
+        Self {
+            signo,
+            errno: 0,
+            code,
+            _pad: 0,
+            fields: [0; 14],
+        }
+    }
+}
+
+/// Alternate signal stack, i.e., `stack_t` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalStack {
+    /// Base address of the stack.
+    pub sp: usize,
+    /// Flags of the stack, i.e., `SS_ONSTACK` or `SS_DISABLE`.
+    pub flags: i32,
+    _pad: i32,
+    /// Size of the stack.
+    pub size: usize,
+}
+
+impl SignalStack {
+    /// Creates a new signal stack with the given base address, flags and size.
+    pub const fn new(sp: usize, flags: i32, size: usize) -> Self {
+        Self {
+            sp,
+            flags,
+            _pad: 0,
+            size,
+        }
+    }
+}
+
+/// How to deliver a signal to user space.
+#[derive(Debug, Clone, Copy)]
+pub struct SignalDelivery {
+    /// Information about the signal, which is copied to the signal frame.
+    pub info: SignalInfo,
+    /// Address of the signal handler.
+    pub handler: usize,
+    /// Address of the trampoline which invokes `rt_sigreturn`, i.e.,
+    /// `sa_restorer`. The handler returns to it.
+    pub restorer: usize,
+    /// The signal mask to be restored by `rt_sigreturn`.
+    pub sigmask: u64,
+    /// The alternate signal stack of the thread, which is saved in the signal
+    /// frame as `uc_stack`.
+    pub altstack: SignalStack,
+    /// Whether to push the signal frame onto the alternate signal stack instead
+    /// of the current user stack.
+    pub use_altstack: bool,
+}
+
+impl SignalDelivery {
+    /// Returns the stack top to push the signal frame onto.
+    pub(crate) const fn stack_top(&self, user_sp: usize) -> usize {
+        if self.use_altstack {
+            self.altstack.sp + self.altstack.size
+        } else {
+            user_sp
+        }
+    }
+}
+
+/// Writes `val` to user space at `addr`. Returns `false` on fault.
+///
+/// `T` must not contain padding bytes.
+pub(crate) unsafe fn write_to_user<T>(addr: usize, val: &T) -> bool {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(val as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    unsafe { copy_to_user(addr as *mut u8, bytes) == 0 }
+}
+
+/// Reads a `T` from user space at `addr`. Returns [`None`] on fault.
+///
+/// `T` must be valid for any bit pattern.
+pub(crate) unsafe fn read_from_user<T>(addr: usize) -> Option<T> {
+    let mut val = core::mem::MaybeUninit::<T>::zeroed();
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, core::mem::size_of::<T>())
+    };
+    unsafe { (copy_from_user(bytes, addr as *const u8) == 0).then(|| val.assume_init()) }
+}
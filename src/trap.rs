@@ -1,4 +1,29 @@
 //! Trap handling.
+//!
+//! Handlers of the [`IRQ`], [`PAGE_FAULT`], [`EXCEPTION`], [`BREAKPOINT`] and
+//! [`SINGLE_STEP`] traps are chained: each entry of the slice is a [`TrapHandler`] carrying an
+//! explicit priority, and handlers are invoked from the highest priority to the
+//! lowest until one of them returns `true`. Handlers with the same priority are
+//! invoked in link order.
+//!
+//! Besides the link-time slices, handlers can also be installed and removed at
+//! runtime through the registries in the [`dynamic`] module. They are consulted
+//! after all handlers in the corresponding slice.
+//!
+//! ```ignore
+//! use axcpu::trap::{register_trap_handler, IrqHandler, TrapHandler, IRQ};
+//!
+//! #[register_trap_handler(IRQ)]
+//! static TIMER_IRQ: TrapHandler<IrqHandler> = TrapHandler::new(10, handle_timer_irq);
+//!
+//! fn handle_timer_irq(vector: usize) -> bool {
+//!     // returns `false` to pass the IRQ to handlers with lower priority
+//!     vector == TIMER_IRQ_NUM
+//! }
+//! ```
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicPtr, AtomicU16, AtomicU8, AtomicUsize, Ordering};
 
 use memory_addr::VirtAddr;
 
@@ -7,38 +32,813 @@ pub use linkme::distributed_slice as def_trap_handler;
 pub use linkme::distributed_slice as register_trap_handler;
 pub use page_table_entry::MappingFlags as PageFaultFlags;
 
+#[cfg(feature = "trap-stats")]
+#[cfg_attr(docsrs, doc(cfg(feature = "trap-stats")))]
+pub mod stats;
+
+/// Signature of IRQ handlers, which receive the IRQ number and return whether
+/// the IRQ is handled.
+pub type IrqHandler = fn(usize) -> bool;
+
+/// Signature of page fault handlers, which receive the fault address, the
+/// access flags and whether the fault is from user space, and return whether
+/// the fault is handled.
+pub type PageFaultHandler = fn(VirtAddr, PageFaultFlags, bool) -> bool;
+
+/// Signature of syscall handlers, which receive the trap frame and the syscall
+/// number, and return the action to take after the syscall.
+///
+/// When the handler is invoked, the program counter in the trap frame already
+/// points to the instruction after the syscall instruction.
+#[cfg(feature = "uspace")]
+#[cfg_attr(docsrs, doc(cfg(feature = "uspace")))]
+pub type SyscallHandler = fn(&mut TrapFrame, usize) -> SyscallAction;
+
+/// The action to take after a syscall handler returns.
+#[cfg(feature = "uspace")]
+#[cfg_attr(docsrs, doc(cfg(feature = "uspace")))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallAction {
+    /// Writes the value to the return value register and returns to user
+    /// space.
+    Return(isize),
+    /// Rewinds the program counter to re-execute the syscall instruction, with
+    /// the syscall number restored.
+    Restart,
+    /// Returns to user space without touching the trap frame, e.g., because
+    /// the handler has rewritten the whole user register set.
+    NoReturnValue,
+}
+
+/// Converts the return value of a syscall handler written for the previous
+/// `fn(&mut TrapFrame, usize) -> isize` signature.
+#[cfg(feature = "uspace")]
+impl From<isize> for SyscallAction {
+    fn from(ret: isize) -> Self {
+        Self::Return(ret)
+    }
+}
+
+/// Signature of exception handlers, which receive the trap frame and the
+/// information about the exception, and return whether the exception is
+/// handled.
+pub type ExceptionHandler = fn(&mut TrapFrame, &ExceptionInfo) -> bool;
+
+/// Signature of breakpoint handlers, which receive the trap frame and return
+/// whether the breakpoint is handled.
+pub type BreakpointHandler = fn(&mut TrapFrame) -> bool;
+
+/// Signature of single-step handlers, which receive the trap frame and return
+/// whether the single-step trap is handled.
+pub type SingleStepHandler = fn(&mut TrapFrame) -> bool;
+
+/// Signature of hardware breakpoint handlers, which receive the trap frame,
+/// the slot of the breakpoint that hits and the address that hits it, and
+/// return whether the hit is handled.
+#[cfg(feature = "hw-breakpoint")]
+#[cfg_attr(docsrs, doc(cfg(feature = "hw-breakpoint")))]
+pub type HwBreakpointHandler = fn(&mut TrapFrame, usize, VirtAddr) -> bool;
+
+/// Signature of IRQ return hooks, which receive the trap frame of the
+/// interrupted context.
+pub type IrqReturnHook = fn(&TrapFrame);
+
+/// Signature of trap trace hooks, which receive the type of the trap and the
+/// trap frame.
+#[cfg(feature = "trap-trace")]
+#[cfg_attr(docsrs, doc(cfg(feature = "trap-trace")))]
+pub type TrapTraceHook = fn(TrapType, &TrapFrame);
+
+/// Architecture-independent classification of traps, which is passed to trace
+/// hooks.
+#[cfg(feature = "trap-trace")]
+#[cfg_attr(docsrs, doc(cfg(feature = "trap-trace")))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapType {
+    /// Interrupt request.
+    Irq,
+    /// Page fault.
+    PageFault,
+    /// System call.
+    Syscall,
+    /// Breakpoint.
+    Breakpoint,
+    /// Other exceptions.
+    Exception,
+}
+
+/// Architecture-independent classification of exceptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionKind {
+    /// Undefined or illegal instruction.
+    IllegalInstruction,
+    /// Integer divide error.
+    DivideError,
+    /// Misaligned instruction fetch or memory access.
+    Misaligned,
+    /// Page fault that is not handled by any [`PAGE_FAULT`] handler.
+    PageFault,
+    /// Memory access fault other than page faults, e.g., general protection
+    /// fault or external abort.
+    AccessFault,
+    /// Floating-point or SIMD exception.
+    FloatingPoint,
+    /// Other exceptions.
+    Other,
+}
+
+/// Information about an exception passed to [`EXCEPTION`] handlers.
+#[derive(Debug, Clone, Copy)]
+pub struct ExceptionInfo {
+    /// The kind of the exception.
+    pub kind: ExceptionKind,
+    /// Address of the faulting instruction.
+    pub pc: usize,
+    /// The architecture-specific cause of the exception, i.e., the vector
+    /// number on x86_64, `ESR_EL1` on aarch64, `scause` on RISC-V, `ESTAT` on
+    /// LoongArch64, and `IFSR`/`DFSR` or the exception type on ARMv7-A.
+    pub cause: usize,
+    /// The faulting virtual address, if any.
+    pub vaddr: Option<VirtAddr>,
+    /// Whether the exception is from user space.
+    pub is_user: bool,
+}
+
+/// A trap handler with an explicit priority.
+#[derive(Debug, Clone, Copy)]
+pub struct TrapHandler<F> {
+    /// Priority of the handler. Handlers with higher priority are invoked
+    /// first.
+    pub priority: i32,
+    /// The handler function.
+    pub handler: F,
+}
+
+impl<F> TrapHandler<F> {
+    /// The priority for handlers that do not care about ordering.
+    pub const DEFAULT_PRIORITY: i32 = 0;
+
+    /// Creates a new trap handler with the given priority.
+    pub const fn new(priority: i32, handler: F) -> Self {
+        Self { priority, handler }
+    }
+}
+
 /// A slice of IRQ handler functions.
 #[def_trap_handler]
-pub static IRQ: [fn(usize) -> bool];
+pub static IRQ: [TrapHandler<IrqHandler>];
 
 /// A slice of page fault handler functions.
 #[def_trap_handler]
-pub static PAGE_FAULT: [fn(VirtAddr, PageFaultFlags, bool) -> bool];
+pub static PAGE_FAULT: [TrapHandler<PageFaultHandler>];
+
+/// A slice of exception handler functions.
+///
+/// Exceptions that are not recognized by the architecture-specific trap
+/// handler, as well as unhandled page faults, are passed to these handlers.
+/// The kernel panics if none of them handles the exception.
+///
+/// Kernel-mode exceptions raised by instructions in the
+/// [exception table](crate::extable) are fixed up before reaching these
+/// handlers.
+#[def_trap_handler]
+pub static EXCEPTION: [TrapHandler<ExceptionHandler>];
+
+/// A slice of breakpoint handler functions.
+///
+/// The program counter in the trap frame points to the breakpoint instruction,
+/// except on x86_64 where it points to the instruction after `int3`. A handler
+/// that handles the breakpoint is responsible for updating the program counter
+/// to where the execution should continue. If no handler handles it, the
+/// breakpoint instruction is skipped.
+#[def_trap_handler]
+pub static BREAKPOINT: [TrapHandler<BreakpointHandler>];
+
+/// A slice of single-step handler functions.
+///
+/// They are invoked after an instruction is stepped over with
+/// [`set_single_step`](crate::debug::set_single_step). Single-stepping is
+/// one-shot: it has been turned off in the trap frame when the handlers are
+/// invoked, and a handler that wants to step further turns it on again.
+#[def_trap_handler]
+pub static SINGLE_STEP: [TrapHandler<SingleStepHandler>];
+
+/// A slice of hardware breakpoint handler functions.
+///
+/// They are invoked when a breakpoint in [`HwBreakpoints`] hits. The address
+/// is the accessed data address for watchpoints if the hardware reports it (it
+/// is the watched address on x86_64), or the instruction address for
+/// breakpoints. Execution continues after the instruction that hits, whether
+/// the hit is handled or not.
+///
+/// [`HwBreakpoints`]: crate::debug::HwBreakpoints
+#[cfg(feature = "hw-breakpoint")]
+#[cfg_attr(docsrs, doc(cfg(feature = "hw-breakpoint")))]
+#[def_trap_handler]
+pub static HW_BREAKPOINT: [TrapHandler<HwBreakpointHandler>];
+
+/// A slice of hooks invoked at the tail of the IRQ path, after the IRQ is
+/// dispatched to its handlers and before the interrupted context is restored.
+/// With the `trap-trace` feature, they run after the `TRAP_EXIT` hooks.
+///
+/// The hooks are invoked with local IRQs disabled on the kernel stack of the
+/// interrupted task. They may preempt the task by switching to another one with
+/// [`TaskContext::switch_to`], and the interrupted context is restored when the
+/// task is switched back to.
+///
+/// ```ignore
+/// use axcpu::trap::{register_trap_handler, IrqReturnHook, TrapFrame, IRQ_RETURN};
+///
+/// #[register_trap_handler(IRQ_RETURN)]
+/// static PREEMPT: IrqReturnHook = preempt;
+///
+/// fn preempt(tf: &TrapFrame) {
+///     // switch to the next task if the time slice of the current one is used up
+/// }
+/// ```
+///
+/// [`TaskContext::switch_to`]: crate::TaskContext::switch_to
+#[def_trap_handler]
+pub static IRQ_RETURN: [IrqReturnHook];
 
 /// A slice of syscall handler functions.
 #[cfg(feature = "uspace")]
 #[cfg_attr(docsrs, doc(cfg(feature = "uspace")))]
 #[def_trap_handler]
-pub static SYSCALL: [fn(&TrapFrame, usize) -> isize];
+pub static SYSCALL: [SyscallHandler];
+
+/// A slice of hooks invoked on trap entry, before the trap is dispatched to
+/// its handlers.
+///
+/// ```ignore
+/// use axcpu::trap::{register_trap_handler, TrapFrame, TrapTraceHook, TrapType, TRAP_ENTER};
+///
+/// #[register_trap_handler(TRAP_ENTER)]
+/// static TRACE_ENTER: TrapTraceHook = trace_enter;
+///
+/// fn trace_enter(ty: TrapType, tf: &TrapFrame) {
+///     // record the timestamp of the trap
+/// }
+/// ```
+#[cfg(feature = "trap-trace")]
+#[cfg_attr(docsrs, doc(cfg(feature = "trap-trace")))]
+#[def_trap_handler]
+pub static TRAP_ENTER: [TrapTraceHook];
+
+/// A slice of hooks invoked on trap exit, after the trap is dispatched to its
+/// handlers.
+///
+/// They are not invoked if the trap does not return, e.g., a kernel panic.
+#[cfg(feature = "trap-trace")]
+#[cfg_attr(docsrs, doc(cfg(feature = "trap-trace")))]
+#[def_trap_handler]
+pub static TRAP_EXIT: [TrapTraceHook];
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// Function pointer types that can be stored in a [`DynamicTrapHandlers`]
+/// registry.
+pub trait HandlerFn: Copy + private::Sealed {
+    #[doc(hidden)]
+    fn into_raw(self) -> *mut ();
+    #[doc(hidden)]
+    unsafe fn from_raw(raw: *mut ()) -> Self;
+}
+
+macro_rules! impl_handler_fn {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl private::Sealed for $ty {}
+            impl HandlerFn for $ty {
+                fn into_raw(self) -> *mut () {
+                    self as *mut ()
+                }
+                unsafe fn from_raw(raw: *mut ()) -> Self {
+                    unsafe { core::mem::transmute::<*mut (), Self>(raw) }
+                }
+            }
+        )*
+    };
+}
+
+impl_handler_fn!(
+    IrqHandler,
+    PageFaultHandler,
+    ExceptionHandler,
+    BreakpointHandler,
+);
+#[cfg(feature = "uspace")]
+impl_handler_fn!(SyscallHandler);
+#[cfg(feature = "hw-breakpoint")]
+impl_handler_fn!(HwBreakpointHandler);
+
+/// A handle to a handler installed in a [`DynamicTrapHandlers`] registry,
+/// which is used to remove the handler later.
+#[derive(Debug)]
+#[must_use = "the handler can only be unregistered with this handle"]
+pub struct DynamicHandlerHandle {
+    slot: usize,
+    generation: usize,
+}
+
+/// The generation of a free slot.
+const SLOT_FREE: usize = 0;
+/// The generation of a slot whose handler is being removed.
+const SLOT_REMOVING: usize = usize::MAX;
+
+/// The generation assigned to the next registered handler, which is unique
+/// across all registries.
+static NEXT_GENERATION: AtomicUsize = AtomicUsize::new(1);
+
+/// A lock-free registry of trap handlers that can be installed and removed at
+/// runtime.
+///
+/// It has a fixed capacity of `N` handlers. Handlers are invoked in the order
+/// of their slots, i.e., not necessarily the order of registration.
+pub struct DynamicTrapHandlers<F, const N: usize = 16> {
+    slots: [AtomicPtr<()>; N],
+    /// The generation of the handler owning each slot, or [`SLOT_FREE`].
+    generations: [AtomicUsize; N],
+    _marker: PhantomData<F>,
+}
+
+impl<F: HandlerFn, const N: usize> DynamicTrapHandlers<F, N> {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { AtomicPtr::new(core::ptr::null_mut()) }; N],
+            generations: [const { AtomicUsize::new(SLOT_FREE) }; N],
+            _marker: PhantomData,
+        }
+    }
+
+    /// Installs a handler into a free slot.
+    ///
+    /// Returns a handle to unregister it, or [`None`] if the registry is full.
+    pub fn register(&self, handler: F) -> Option<DynamicHandlerHandle> {
+        let generation = loop {
+            let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+            if generation != SLOT_FREE && generation != SLOT_REMOVING {
+                break generation;
+            }
+        };
+        let slot = self.generations.iter().position(|gen| {
+            gen.compare_exchange(SLOT_FREE, generation, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        self.slots[slot].store(handler.into_raw(), Ordering::Release);
+        Some(DynamicHandlerHandle { slot, generation })
+    }
+
+    /// Removes the handler identified by the given handle.
+    ///
+    /// Returns `false` if the handle does not belong to this registry.
+    ///
+    /// The handler may still be running on other CPUs, or in an interrupted
+    /// trap on this CPU, when this function returns. Callers must wait for a
+    /// quiescent period, e.g., until every CPU has returned from the traps
+    /// taken before the removal, before freeing the handler or the data it
+    /// uses.
+    pub fn unregister(&self, handle: DynamicHandlerHandle) -> bool {
+        let Some(gen) = self.generations.get(handle.slot) else {
+            return false;
+        };
+        if gen
+            .compare_exchange(
+                handle.generation,
+                SLOT_REMOVING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return false;
+        }
+        self.slots[handle.slot].store(core::ptr::null_mut(), Ordering::Release);
+        gen.store(SLOT_FREE, Ordering::Release);
+        true
+    }
+
+    /// Whether no handler is installed.
+    pub fn is_empty(&self) -> bool {
+        self.slots
+            .iter()
+            .all(|ptr| ptr.load(Ordering::Acquire).is_null())
+    }
+
+    /// Returns an iterator over the installed handlers.
+    pub fn iter(&self) -> impl Iterator<Item = F> + '_ {
+        self.slots.iter().filter_map(|ptr| {
+            let raw = ptr.load(Ordering::Acquire);
+            (!raw.is_null()).then(|| unsafe { F::from_raw(raw) })
+        })
+    }
+}
+
+/// Registries of trap handlers that can be installed and removed at runtime.
+///
+/// Each registry has the same name as the trap slice it complements.
+pub mod dynamic {
+    use super::*;
+
+    /// IRQ handlers installed at runtime.
+    pub static IRQ: DynamicTrapHandlers<IrqHandler> = DynamicTrapHandlers::new();
+
+    /// Page fault handlers installed at runtime.
+    pub static PAGE_FAULT: DynamicTrapHandlers<PageFaultHandler> = DynamicTrapHandlers::new();
+
+    /// Exception handlers installed at runtime.
+    pub static EXCEPTION: DynamicTrapHandlers<ExceptionHandler> = DynamicTrapHandlers::new();
+
+    /// Breakpoint handlers installed at runtime.
+    pub static BREAKPOINT: DynamicTrapHandlers<BreakpointHandler> = DynamicTrapHandlers::new();
+
+    /// Single-step handlers installed at runtime.
+    pub static SINGLE_STEP: DynamicTrapHandlers<SingleStepHandler> = DynamicTrapHandlers::new();
+
+    /// Hardware breakpoint handlers installed at runtime.
+    #[cfg(feature = "hw-breakpoint")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hw-breakpoint")))]
+    pub static HW_BREAKPOINT: DynamicTrapHandlers<HwBreakpointHandler> = DynamicTrapHandlers::new();
+
+    /// Syscall handlers installed at runtime.
+    ///
+    /// Only the first one is invoked, and only if the [`SYSCALL`] slice is
+    /// empty.
+    ///
+    /// [`SYSCALL`]: super::SYSCALL
+    #[cfg(feature = "uspace")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "uspace")))]
+    pub static SYSCALL: DynamicTrapHandlers<SyscallHandler> = DynamicTrapHandlers::new();
+}
+
+/// Returns an iterator over the handler functions in descending order of
+/// priority. Handlers with the same priority keep their order in the slice.
+///
+/// It scans the slice for each handler, and is only used when the order is not
+/// cached in a [`PriorityOrder`].
+#[allow(dead_code)]
+pub(crate) fn iter_by_priority<F: Copy>(
+    handlers: &[TrapHandler<F>],
+) -> impl Iterator<Item = F> + '_ {
+    let mut last: Option<(i32, usize)> = None;
+    core::iter::from_fn(move || {
+        let (idx, next) = handlers
+            .iter()
+            .enumerate()
+            .filter(|&(i, h)| match last {
+                None => true,
+                Some((prio, j)) => h.priority < prio || (h.priority == prio && i > j),
+            })
+            .min_by_key(|&(i, h)| (core::cmp::Reverse(h.priority), i))?;
+        last = Some((next.priority, idx));
+        Some(next.handler)
+    })
+}
+
+/// Maximum number of handlers in a trap slice whose order can be cached in a
+/// [`PriorityOrder`].
+const MAX_ORDERED_HANDLERS: usize = 64;
+
+const ORDER_UNINIT: u8 = 0;
+const ORDER_BUILDING: u8 = 1;
+const ORDER_READY: u8 = 2;
+
+/// The priority order of the handlers in a trap slice, which is sorted once on
+/// the first dispatch and cached for later ones.
+///
+/// Dispatches that race with the sorting, e.g., from nested traps or other
+/// CPUs, fall back to [`iter_by_priority`] instead of waiting for it, as do
+/// slices with more than [`MAX_ORDERED_HANDLERS`] handlers.
+#[allow(dead_code)]
+pub(crate) struct PriorityOrder {
+    state: AtomicU8,
+    indices: [AtomicU16; MAX_ORDERED_HANDLERS],
+}
+
+#[allow(dead_code)]
+impl PriorityOrder {
+    /// Creates an order that is not sorted yet.
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(ORDER_UNINIT),
+            indices: [const { AtomicU16::new(0) }; MAX_ORDERED_HANDLERS],
+        }
+    }
+
+    /// Sorts the handlers if not sorted yet, and returns whether the cached
+    /// order is ready for use.
+    fn ensure_sorted<F>(&self, handlers: &[TrapHandler<F>]) -> bool {
+        match self.state.load(Ordering::Acquire) {
+            ORDER_READY => return true,
+            ORDER_BUILDING => return false,
+            _ => {}
+        }
+        if handlers.len() > MAX_ORDERED_HANDLERS
+            || self
+                .state
+                .compare_exchange(
+                    ORDER_UNINIT,
+                    ORDER_BUILDING,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            return false;
+        }
+
+        // Stable insertion sort in descending order of priority.
+        let mut order = [0u16; MAX_ORDERED_HANDLERS];
+        for i in 0..handlers.len() {
+            let mut j = i;
+            while j > 0 && handlers[order[j - 1] as usize].priority < handlers[i].priority {
+                order[j] = order[j - 1];
+                j -= 1;
+            }
+            order[j] = i as u16;
+        }
+        for (idx, &i) in self.indices.iter().zip(&order[..handlers.len()]) {
+            idx.store(i, Ordering::Relaxed);
+        }
+        self.state.store(ORDER_READY, Ordering::Release);
+        true
+    }
+
+    /// Returns an iterator over the handler functions in descending order of
+    /// priority. Handlers with the same priority keep their order in the slice.
+    ///
+    /// The same order must always be used with the same slice.
+    pub(crate) fn iter<'a, F: Copy>(
+        &'a self,
+        handlers: &'a [TrapHandler<F>],
+    ) -> impl Iterator<Item = F> + 'a {
+        let sorted = self.ensure_sorted(handlers).then(|| {
+            self.indices[..handlers.len()]
+                .iter()
+                .map(|i| handlers[i.load(Ordering::Relaxed) as usize].handler)
+        });
+        let scanned = sorted.is_none().then(|| iter_by_priority(handlers));
+        sorted
+            .into_iter()
+            .flatten()
+            .chain(scanned.into_iter().flatten())
+    }
+}
+
+/// Cached priority orders of the trap slices.
+///
+/// Each order has the same name as the trap slice it sorts.
+#[allow(dead_code)]
+pub(crate) mod order {
+    use super::PriorityOrder;
+
+    pub(crate) static IRQ: PriorityOrder = PriorityOrder::new();
+    pub(crate) static PAGE_FAULT: PriorityOrder = PriorityOrder::new();
+    pub(crate) static EXCEPTION: PriorityOrder = PriorityOrder::new();
+    pub(crate) static BREAKPOINT: PriorityOrder = PriorityOrder::new();
+    pub(crate) static SINGLE_STEP: PriorityOrder = PriorityOrder::new();
+    #[cfg(feature = "hw-breakpoint")]
+    pub(crate) static HW_BREAKPOINT: PriorityOrder = PriorityOrder::new();
+}
 
 #[allow(unused_macros)]
 macro_rules! handle_trap {
     ($trap:ident, $($args:tt)*) => {{
-        let mut iter = $crate::trap::$trap.iter();
-        if let Some(func) = iter.next() {
-            if iter.next().is_some() {
-                warn!("Multiple handlers for trap {} are not currently supported", stringify!($trap));
-            }
-            func($($args)*)
-        } else {
+        let handlers = &$crate::trap::$trap;
+        let dyn_handlers = &$crate::trap::dynamic::$trap;
+        if handlers.is_empty() && dyn_handlers.is_empty() {
             warn!("No registered handler for trap {}", stringify!($trap));
+        }
+        'dispatch: {
+            let ordered = $crate::trap::order::$trap.iter(handlers);
+            for func in ordered.chain(dyn_handlers.iter()) {
+                if func($($args)*) {
+                    break 'dispatch true;
+                }
+            }
             false
         }
     }}
 }
 
+/// Counts a trap in the per-CPU [statistics](stats) if the `trap-stats`
+/// feature is enabled.
+///
+/// `count_trap!(IRQ, irq_num, handled)` counts an IRQ,
+/// `count_trap!(EXCEPTION, kind)` counts an exception about to be fixed up or
+/// dispatched to the [`EXCEPTION`] handlers, and `count_trap!(counter)`
+/// increments the named counter.
+///
+/// Page faults are passed to the [`EXCEPTION`] handlers if no [`PAGE_FAULT`]
+/// handler resolves them, but they are only counted as page faults.
+#[allow(unused_macros)]
+macro_rules! count_trap {
+    (IRQ, $irq:expr, $handled:expr) => {{
+        #[cfg(feature = "trap-stats")]
+        $crate::trap::stats::count_irq($irq, $handled);
+        #[cfg(not(feature = "trap-stats"))]
+        let _: (usize, bool) = ($irq, $handled);
+    }};
+    (EXCEPTION, $kind:expr) => {{
+        let kind: $crate::trap::ExceptionKind = $kind;
+        #[cfg(feature = "trap-stats")]
+        if kind != $crate::trap::ExceptionKind::PageFault {
+            $crate::trap::stats::count(|c| &c.exceptions);
+        }
+        #[cfg(not(feature = "trap-stats"))]
+        let _ = kind;
+    }};
+    ($counter:ident) => {{
+        #[cfg(feature = "trap-stats")]
+        $crate::trap::stats::count(|c| &c.$counter);
+    }};
+}
+
+/// Invokes the hooks in the [`IRQ_RETURN`] slice.
+///
+/// The hooks may switch to another task, so it must come after the trap exit
+/// is traced. Only the restore of the live hardware state into the trap frame
+/// (e.g. `sstatus.FS` on RISC-V) may follow it.
+#[allow(unused_macros)]
+macro_rules! irq_return {
+    ($tf:expr) => {{
+        for hook in $crate::trap::IRQ_RETURN.iter() {
+            hook($tf);
+        }
+    }};
+}
+
+/// Invokes the trace hooks in the [`TRAP_ENTER`] or [`TRAP_EXIT`] slice if the
+/// `trap-trace` feature is enabled.
+///
+/// The trap type is only evaluated when the feature is enabled.
+#[allow(unused_macros)]
+macro_rules! trace_trap {
+    ($hooks:ident, $ty:expr, $tf:expr) => {{
+        #[cfg(feature = "trap-trace")]
+        for hook in $crate::trap::$hooks.iter() {
+            hook($ty, $tf);
+        }
+        #[cfg(not(feature = "trap-trace"))]
+        let _ = $tf;
+    }};
+}
+
 /// Call the external syscall handler.
 #[cfg(feature = "uspace")]
-pub(crate) fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
-    SYSCALL[0](tf, syscall_num)
+pub(crate) fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> SyscallAction {
+    count_trap!(syscalls);
+    let handler = SYSCALL
+        .first()
+        .copied()
+        .or_else(|| dynamic::SYSCALL.iter().next())
+        .expect("No registered handler for trap SYSCALL");
+    handler(tf, syscall_num)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handlers(priorities: &[i32]) -> Vec<TrapHandler<usize>> {
+        priorities
+            .iter()
+            .enumerate()
+            .map(|(i, &prio)| TrapHandler::new(prio, i))
+            .collect()
+    }
+
+    fn expected_order(handlers: &[TrapHandler<usize>]) -> Vec<usize> {
+        let mut order: Vec<_> = handlers.iter().map(|h| h.handler).collect();
+        order.sort_by_key(|&i| core::cmp::Reverse(handlers[i].priority)); // stable
+        order
+    }
+
+    #[test]
+    fn priority_order() {
+        let handlers = handlers(&[0, 10, -5, 10, 0, 3]);
+        let expected = [1, 3, 5, 0, 4, 2];
+        assert_eq!(iter_by_priority(&handlers).collect::<Vec<_>>(), expected);
+
+        let order = PriorityOrder::new();
+        // The first dispatch sorts, and later ones use the cached order.
+        assert_eq!(order.iter(&handlers).collect::<Vec<_>>(), expected);
+        assert_eq!(order.state.load(Ordering::Relaxed), ORDER_READY);
+        assert_eq!(order.iter(&handlers).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn priority_order_empty() {
+        let order = PriorityOrder::new();
+        assert_eq!(order.iter::<usize>(&[]).count(), 0);
+        assert_eq!(iter_by_priority::<usize>(&[]).count(), 0);
+    }
+
+    #[test]
+    fn priority_order_too_many() {
+        let priorities: Vec<_> = (0..MAX_ORDERED_HANDLERS as i32 + 6)
+            .map(|i| i % 3)
+            .collect();
+        let handlers = handlers(&priorities);
+        let order = PriorityOrder::new();
+        assert_eq!(
+            order.iter(&handlers).collect::<Vec<_>>(),
+            expected_order(&handlers)
+        );
+        assert_eq!(order.state.load(Ordering::Relaxed), ORDER_UNINIT);
+    }
+
+    #[test]
+    fn priority_order_while_building() {
+        let handlers = handlers(&[1, 2, 3]);
+        let order = PriorityOrder::new();
+        order.state.store(ORDER_BUILDING, Ordering::Relaxed);
+        assert_eq!(order.iter(&handlers).collect::<Vec<_>>(), [2, 1, 0]);
+    }
+
+    /// IDs of the handlers invoked by [`dispatch_falls_through`], one decimal
+    /// digit for each.
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn record(id: usize) {
+        CALLS.store(CALLS.load(Ordering::Relaxed) * 10 + id, Ordering::Relaxed);
+    }
+
+    #[register_trap_handler(IRQ)]
+    static LOW_IRQ: TrapHandler<IrqHandler> = TrapHandler::new(-10, |irq| {
+        record(2);
+        irq == 2
+    });
+
+    #[register_trap_handler(IRQ)]
+    static HIGH_IRQ: TrapHandler<IrqHandler> = TrapHandler::new(10, |irq| {
+        record(1);
+        irq == 1
+    });
+
+    fn dynamic_irq(irq: usize) -> bool {
+        record(3);
+        irq == 3
+    }
+
+    #[test]
+    fn dispatch_falls_through() {
+        let dispatch = |irq: usize| {
+            CALLS.store(0, Ordering::Relaxed);
+            let handled = handle_trap!(IRQ, irq);
+            (handled, CALLS.load(Ordering::Relaxed))
+        };
+        assert_eq!(dispatch(1), (true, 1));
+        assert_eq!(dispatch(2), (true, 12));
+        assert_eq!(dispatch(3), (false, 12));
+
+        let handle = dynamic::IRQ.register(dynamic_irq).unwrap();
+        assert_eq!(dispatch(3), (true, 123));
+        assert_eq!(dispatch(4), (false, 123));
+        assert!(dynamic::IRQ.unregister(handle));
+        assert_eq!(dispatch(3), (false, 12));
+    }
+
+    #[test]
+    fn dynamic_handles() {
+        let irqs = DynamicTrapHandlers::<IrqHandler, 2>::new();
+        let other = DynamicTrapHandlers::<IrqHandler, 2>::new();
+        assert!(irqs.is_empty());
+
+        let first = irqs.register(dynamic_irq).unwrap();
+        let second = irqs.register(dynamic_irq).unwrap();
+        assert!(irqs.register(dynamic_irq).is_none());
+        assert_eq!(irqs.iter().count(), 2);
+
+        // A handle for the same slot and handler in another registry does
+        // not remove it.
+        let foreign = other.register(dynamic_irq).unwrap();
+        assert_eq!(foreign.slot, first.slot);
+        assert!(!irqs.unregister(foreign));
+        assert_eq!(irqs.iter().count(), 2);
+
+        // The slot is reused by a new registration with a new generation.
+        let (slot, generation) = (first.slot, first.generation);
+        assert!(irqs.unregister(first));
+        let reused = irqs.register(dynamic_irq).unwrap();
+        assert_eq!(reused.slot, slot);
+        assert_ne!(reused.generation, generation);
+        let stale = DynamicHandlerHandle { slot, generation };
+        assert!(!irqs.unregister(stale));
+
+        assert!(irqs.unregister(reused));
+        assert!(irqs.unregister(second));
+        assert!(irqs.is_empty());
+    }
+
+    #[cfg(feature = "uspace")]
+    #[test]
+    fn syscall_action_from_isize() {
+        assert_eq!(SyscallAction::from(-22), SyscallAction::Return(-22));
+        let action: SyscallAction = 0.into();
+        assert_eq!(action, SyscallAction::Return(0));
+    }
 }
@@ -0,0 +1,162 @@
+//! Per-CPU trap statistics.
+//!
+//! Each CPU counts the traps it takes in its own per-CPU area, so counting
+//! does not involve any cross-CPU synchronization. The counters can be read
+//! with [`snapshot`] or [`snapshot_of`], e.g., to render `/proc/interrupts`:
+//!
+//! ```ignore
+//! for cpu in 0..cpu_num {
+//!     let stats = unsafe { axcpu::trap::stats::snapshot_of(cpu) };
+//!     for (irq, count) in stats.irqs() {
+//!         // ...
+//!     }
+//! }
+//! ```
+//!
+//! The counters are stored with the [`percpu`] crate, so the per-CPU data
+//! areas must be initialized before any trap is taken.
+//!
+//! [`percpu`]: https://docs.rs/percpu/latest/percpu/index.html
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of IRQs that are counted individually.
+///
+/// IRQs with larger numbers are counted together in
+/// [`TrapStats::other_irqs`].
+pub const MAX_IRQS: usize = 1024;
+
+pub(crate) struct TrapCounters {
+    irqs: [AtomicUsize; MAX_IRQS],
+    other_irqs: AtomicUsize,
+    pub(crate) unhandled_irqs: AtomicUsize,
+    pub(crate) page_faults: AtomicUsize,
+    pub(crate) syscalls: AtomicUsize,
+    pub(crate) exceptions: AtomicUsize,
+    pub(crate) breakpoints: AtomicUsize,
+}
+
+impl TrapCounters {
+    const fn new() -> Self {
+        Self {
+            irqs: [const { AtomicUsize::new(0) }; MAX_IRQS],
+            other_irqs: AtomicUsize::new(0),
+            unhandled_irqs: AtomicUsize::new(0),
+            page_faults: AtomicUsize::new(0),
+            syscalls: AtomicUsize::new(0),
+            exceptions: AtomicUsize::new(0),
+            breakpoints: AtomicUsize::new(0),
+        }
+    }
+
+    fn snapshot(&self) -> TrapStats {
+        let load = |c: &AtomicUsize| c.load(Ordering::Relaxed);
+        TrapStats {
+            irqs: core::array::from_fn(|i| load(&self.irqs[i])),
+            other_irqs: load(&self.other_irqs),
+            unhandled_irqs: load(&self.unhandled_irqs),
+            page_faults: load(&self.page_faults),
+            syscalls: load(&self.syscalls),
+            exceptions: load(&self.exceptions),
+            breakpoints: load(&self.breakpoints),
+        }
+    }
+}
+
+#[percpu::def_percpu]
+static TRAP_COUNTERS: TrapCounters = TrapCounters::new();
+
+/// A snapshot of the trap counters of a CPU.
+#[derive(Debug, Clone)]
+pub struct TrapStats {
+    /// Number of IRQs taken, indexed by the IRQ number.
+    ///
+    /// The IRQ number is the one passed to [`IRQ`](super::IRQ) handlers,
+    /// except on RISC-V where the interrupt bit of `scause` is stripped.
+    pub irqs: [usize; MAX_IRQS],
+    /// Number of IRQs taken whose numbers are not less than [`MAX_IRQS`].
+    pub other_irqs: usize,
+    /// Number of IRQs not handled by any handler, i.e., all handlers returned
+    /// `false` or no handler is registered. They are usually spurious.
+    ///
+    /// With the `gicv3` feature, it also counts the spurious INTIDs read when
+    /// acknowledging an IRQ, which are not dispatched to any handler nor
+    /// counted in [`irqs`](Self::irqs).
+    pub unhandled_irqs: usize,
+    /// Number of page faults, including those not handled by any
+    /// [`PAGE_FAULT`](super::PAGE_FAULT) handler.
+    pub page_faults: usize,
+    /// Number of syscalls.
+    pub syscalls: usize,
+    /// Number of exceptions fixed up by the exception table or dispatched to
+    /// [`EXCEPTION`](super::EXCEPTION) handlers, whether handled or not.
+    ///
+    /// Page faults, syscalls, breakpoints and IRQs are only counted in their
+    /// own counters, and so are not the exceptions resolved internally, e.g.,
+    /// emulated misaligned accesses and lazy FP traps.
+    pub exceptions: usize,
+    /// Number of breakpoints.
+    pub breakpoints: usize,
+}
+
+impl TrapStats {
+    /// Returns an iterator over `(irq_num, count)` of the IRQs that have been
+    /// taken at least once, in ascending order of the IRQ number.
+    pub fn irqs(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.irqs
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count != 0)
+            .map(|(irq, &count)| (irq, count))
+    }
+
+    /// Total number of IRQs taken.
+    pub fn total_irqs(&self) -> usize {
+        self.irqs.iter().sum::<usize>() + self.other_irqs
+    }
+}
+
+/// Returns a snapshot of the trap counters of the current CPU.
+pub fn snapshot() -> TrapStats {
+    unsafe { TRAP_COUNTERS.current_ref_raw() }.snapshot()
+}
+
+/// Returns a snapshot of the trap counters of the given CPU.
+///
+/// The counters are read while the CPU may be updating them, so the snapshot
+/// is not necessarily consistent across counters.
+///
+/// # Safety
+///
+/// The per-CPU data area of `cpu_id` must have been initialized.
+pub unsafe fn snapshot_of(cpu_id: usize) -> TrapStats {
+    unsafe { TRAP_COUNTERS.remote_ref_raw(cpu_id) }.snapshot()
+}
+
+/// Increments a counter of the current CPU.
+///
+/// Counters are only written by their own CPU in trap context, so a plain
+/// load and store is enough, and cheaper than an atomic read-modify-write.
+#[inline]
+fn inc(counter: &AtomicUsize) {
+    let value = counter.load(Ordering::Relaxed).wrapping_add(1);
+    counter.store(value, Ordering::Relaxed);
+}
+
+/// Increments the counter selected by `f` on the current CPU.
+#[inline]
+#[allow(dead_code)]
+pub(crate) fn count(f: impl FnOnce(&TrapCounters) -> &AtomicUsize) {
+    inc(f(unsafe { TRAP_COUNTERS.current_ref_raw() }));
+}
+
+/// Counts an IRQ on the current CPU, and whether it is handled.
+#[inline]
+#[allow(dead_code)]
+pub(crate) fn count_irq(irq: usize, handled: bool) {
+    let counters = unsafe { TRAP_COUNTERS.current_ref_raw() };
+    inc(counters.irqs.get(irq).unwrap_or(&counters.other_irqs));
+    if !handled {
+        inc(&counters.unhandled_irqs);
+    }
+}
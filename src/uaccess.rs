@@ -0,0 +1,98 @@
+//! Fault-safe access to user memory.
+//!
+//! These functions access user memory with instructions registered in the
+//! [exception table](crate::extable), so that a fault on an invalid user
+//! address, which is not resolved by any
+//! [`PAGE_FAULT`](crate::trap::PAGE_FAULT) handler, makes them return early
+//! instead of panicking. User memory access is temporarily granted to the
+//! kernel while copying if the architecture restricts it (SMAP on x86_64, PAN
+//! on aarch64, and `sstatus.SUM` on RISC-V).
+//!
+//! The user addresses are NOT checked to be in user space. Callers must
+//! validate them before calling these functions, e.g., with
+//! [`is_user_range`], otherwise kernel memory may be accessed.
+
+use crate::uspace::{raw_clear_user, raw_copy_user, raw_strncpy_from_user};
+
+/// The end of user space, which is the lower half of the address space on all
+/// supported architectures (with `TTBCR.N = 1` on ARMv7-A, as set by
+/// `init::init_mmu`).
+const USER_SPACE_END: usize = 1 << (usize::BITS - 1);
+
+/// Whether `addr..addr + len` is a range of user space, i.e., of the lower half
+/// of the address space.
+///
+/// It only checks the range, not whether the memory is mapped. It should be
+/// used to validate user addresses before passing them to [`copy_from_user`]
+/// and other functions in this module.
+pub fn is_user_range(addr: usize, len: usize) -> bool {
+    addr.checked_add(len)
+        .is_some_and(|end| end <= USER_SPACE_END)
+}
+
+/// Copies `dst.len()` bytes from user space at `src` to `dst`.
+///
+/// Returns the number of bytes that could not be copied, i.e., `0` on success.
+///
+/// # Safety
+///
+/// The caller must ensure that `src..src + dst.len()` is a range of user space.
+pub unsafe fn copy_from_user(dst: &mut [u8], src: *const u8) -> usize {
+    unsafe { raw_copy_user(dst.as_mut_ptr(), src, dst.len()) }
+}
+
+/// Copies `src` to user space at `dst`.
+///
+/// Returns the number of bytes that could not be copied, i.e., `0` on success.
+///
+/// # Safety
+///
+/// The caller must ensure that `dst..dst + src.len()` is a range of user space.
+pub unsafe fn copy_to_user(dst: *mut u8, src: &[u8]) -> usize {
+    unsafe { raw_copy_user(dst, src.as_ptr(), src.len()) }
+}
+
+/// Fills `len` bytes of user space at `dst` with zeros.
+///
+/// Returns the number of bytes that could not be cleared, i.e., `0` on
+/// success.
+///
+/// # Safety
+///
+/// The caller must ensure that `dst..dst + len` is a range of user space.
+pub unsafe fn clear_user(dst: *mut u8, len: usize) -> usize {
+    unsafe { raw_clear_user(dst, len) }
+}
+
+/// Copies a NUL-terminated string from user space at `src` to `dst`, copying
+/// at most `dst.len()` bytes.
+///
+/// Returns the length of the string (excluding the trailing NUL), or
+/// `dst.len()` if no NUL is found within `dst.len()` bytes, in which case `dst`
+/// is not NUL-terminated. Returns [`None`] if a fault occurs.
+///
+/// # Safety
+///
+/// The caller must ensure that the bytes read from `src` are in user space.
+pub unsafe fn strncpy_from_user(dst: &mut [u8], src: *const u8) -> Option<usize> {
+    let ret = unsafe { raw_strncpy_from_user(dst.as_mut_ptr(), src, dst.len()) };
+    (ret >= 0).then_some(ret as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_range() {
+        assert!(is_user_range(0x1000, 8));
+        assert!(is_user_range(USER_SPACE_END - 8, 8));
+        assert!(is_user_range(0, 0));
+
+        // Misaligned user accesses to kernel addresses are refused.
+        assert!(!is_user_range(USER_SPACE_END - 4, 8));
+        assert!(!is_user_range(USER_SPACE_END, 1));
+        assert!(!is_user_range(0xffff_ffc0_8020_0001_u64 as usize, 8));
+        assert!(!is_user_range(usize::MAX - 2, 8));
+    }
+}
@@ -6,6 +6,8 @@ use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
 use x86::{controlregs, msr, tlb};
 use x86_64::instructions::interrupts;
 
+use crate::IrqFlags;
+
 /// Allows the current CPU to respond to interrupts.
 #[inline]
 pub fn enable_irqs() {
@@ -34,6 +36,26 @@ pub fn irqs_enabled() -> bool {
     interrupts::are_enabled()
 }
 
+/// Makes the current CPU to ignore interrupts, and returns the previous state
+/// of `RFLAGS.IF`.
+#[inline]
+pub fn local_irq_save() -> IrqFlags {
+    use x86_64::registers::rflags::{self, RFlags};
+    let flags = rflags::read_raw() & RFlags::INTERRUPT_FLAG.bits();
+    disable_irqs();
+    IrqFlags(flags as usize)
+}
+
+/// Restores `RFLAGS.IF` saved by [`local_irq_save`].
+#[inline]
+pub fn local_irq_restore(flags: IrqFlags) {
+    if flags.0 != 0 {
+        enable_irqs();
+    } else {
+        disable_irqs();
+    }
+}
+
 /// Relaxes the current CPU and waits for interrupts.
 ///
 /// It must be called with interrupts enabled, otherwise it will never return.
@@ -139,3 +161,23 @@ pub fn read_thread_pointer() -> usize {
 pub unsafe fn write_thread_pointer(fs_base: usize) {
     unsafe { msr::wrmsr(msr::IA32_FS_BASE, fs_base as u64) }
 }
+
+/// Enables FP/SIMD instructions and the `FXSAVE`/`FXRSTOR` instructions by
+/// setting `CR0` and `CR4`, and enables the XSAVE feature set for AVX and
+/// AVX-512 states if supported.
+///
+/// It should be called on each CPU before any context switch.
+#[cfg(feature = "fp-simd")]
+pub fn enable_fp() {
+    use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+    unsafe {
+        Cr0::update(|cr0| {
+            cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|cr4| {
+            cr4.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+        });
+    }
+    super::xsave::init();
+}
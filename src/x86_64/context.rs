@@ -106,41 +106,177 @@ pub struct FxsaveArea {
 
 static_assertions::const_assert_eq!(core::mem::size_of::<FxsaveArea>(), 512);
 
+/// Header of the XSAVE area.
+///
+/// See Section 13.4.2 of the Intel SDM, Volume 1 for more details.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct XsaveHeader {
+    /// State components not in their initial configuration.
+    pub xstate_bv: u64,
+    /// Bit 63 indicates whether the area is in the compacted format, and
+    /// other bits are the state components in the compacted area.
+    pub xcomp_bv: u64,
+    _reserved: [u64; 6],
+}
+
+/// Size of the XSAVE area in [`ExtendedState`], which is enough for the x87,
+/// SSE, AVX and AVX-512 states.
+pub(super) const XSAVE_AREA_SIZE: usize = 2688;
+
 /// Extended state of a task, such as FP/SIMD states.
+///
+/// It is an XSAVE area, whose first 512 bytes (the legacy region) are the same
+/// as [`FxsaveArea`]. The XSAVE feature set is used to save and restore the
+/// states if it is enabled by [`enable_fp`](crate::asm::enable_fp), otherwise
+/// the `FXSAVE` and `FXRSTOR` instructions are used.
+#[repr(C, align(64))]
 pub struct ExtendedState {
-    /// Memory region for the FXSAVE/FXRSTOR instruction.
+    /// Memory region for the FXSAVE/FXRSTOR instruction, which is also the
+    /// legacy region of the XSAVE area.
     pub fxsave_area: FxsaveArea,
+    /// Header of the XSAVE area.
+    ///
+    /// The x87 and SSE states in [`fxsave_area`](Self::fxsave_area) are
+    /// restored only if the corresponding bits in
+    /// [`xstate_bv`](XsaveHeader::xstate_bv) are set.
+    pub xsave_header: XsaveHeader,
+    /// Extended region of the XSAVE area, e.g., for AVX and AVX-512 states.
+    xsave_ext_area: [u8; XSAVE_AREA_SIZE - 512 - 64],
 }
 
+static_assertions::const_assert_eq!(core::mem::size_of::<ExtendedState>(), XSAVE_AREA_SIZE);
+
 #[cfg(feature = "fp-simd")]
 impl ExtendedState {
     /// Saves the current extended states from CPU to this structure.
+    ///
+    /// It may skip the states not modified since they were last restored from
+    /// this structure, so it must not be used for temporary structures, which
+    /// may be at the same address as a previous one.
     #[inline]
     pub fn save(&mut self) {
-        unsafe { core::arch::x86_64::_fxsave64(&mut self.fxsave_area as *mut _ as *mut u8) }
+        super::xsave::save(self)
+    }
+
+    /// Saves all the current extended states from CPU to this structure,
+    /// which may be a temporary one.
+    #[inline]
+    pub(crate) fn save_full(&mut self) {
+        super::xsave::save_full(self)
     }
 
     /// Restores the extended states from this structure to CPU.
     #[inline]
     pub fn restore(&self) {
-        unsafe { core::arch::x86_64::_fxrstor64(&self.fxsave_area as *const _ as *const u8) }
+        super::xsave::restore(self)
+    }
+
+    /// Returns the enabled state components and the size of the XSAVE area in
+    /// the standard format, or [`None`] if XSAVE is not enabled and only the
+    /// [`FxsaveArea`] is used.
+    pub(crate) fn standard_layout() -> Option<(u64, usize)> {
+        super::xsave::standard_layout()
+    }
+
+    /// Saves all the current extended states from CPU to this structure in the
+    /// standard format, i.e., the layout exposed to user space.
+    pub(crate) fn save_standard(&mut self) {
+        super::xsave::save_standard(self)
+    }
+
+    /// Restores the extended states in the standard format from this
+    /// structure, which may be filled by user space.
+    ///
+    /// The fields that would make the restore fault are fixed first: the
+    /// reserved bits of `MXCSR` are cleared with `mxcsr_mask`, and the header
+    /// only keeps the enabled state components in the standard format.
+    pub(crate) fn restore_standard(&mut self, mxcsr_mask: u32) {
+        self.fxsave_area.mxcsr &= mxcsr_mask;
+        if let Some((features, _)) = Self::standard_layout() {
+            self.xsave_header.xstate_bv &= features;
+            self.xsave_header.xcomp_bv = 0;
+            self.xsave_header._reserved = [0; 6];
+        }
+        super::xsave::restore_standard(self)
     }
 
     /// Returns the extended state with initialized values.
     pub const fn default() -> Self {
-        let mut area: FxsaveArea = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
-        area.fcw = 0x37f;
-        area.ftw = 0xffff;
-        area.mxcsr = 0x1f80;
-        Self { fxsave_area: area }
+        let mut state: Self = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
+        state.fxsave_area.fcw = 0x37f;
+        state.fxsave_area.ftw = 0xffff;
+        state.fxsave_area.mxcsr = 0x1f80;
+        state
+    }
+
+    /// Saves the live extended states to this structure and clears `CR0.TS`
+    /// for a kernel-mode FP/SIMD section.
+    ///
+    /// Returns whether the states are live, i.e., `CR0.TS` is not set.
+    pub(crate) fn kernel_fpu_begin(&mut self) -> bool {
+        use x86_64::registers::control::{Cr0, Cr0Flags};
+        let live = !Cr0::read().contains(Cr0Flags::TASK_SWITCHED);
+        if live {
+            self.save_full();
+        } else {
+            unsafe { Cr0::update(|cr0| cr0.remove(Cr0Flags::TASK_SWITCHED)) };
+        }
+        live
+    }
+
+    /// Restores the extended states saved by [`Self::kernel_fpu_begin`], or
+    /// sets `CR0.TS` back if they are not live.
+    pub(crate) fn kernel_fpu_end(&self, live: bool) {
+        use x86_64::registers::control::{Cr0, Cr0Flags};
+        if live {
+            self.restore();
+        } else {
+            unsafe { Cr0::update(|cr0| cr0.insert(Cr0Flags::TASK_SWITCHED)) };
+        }
+    }
+}
+
+#[cfg(feature = "fp-lazy")]
+impl ExtendedState {
+    /// Saves the current extended states to this structure if they have been
+    /// used since the last switch, and sets `CR0.TS` so that the extended
+    /// states of `next` are restored on their first use.
+    fn lazy_switch_to(&mut self, next: &Self) {
+        use x86_64::registers::control::{Cr0, Cr0Flags};
+        if !Cr0::read().contains(Cr0Flags::TASK_SWITCHED) {
+            self.save();
+            unsafe { Cr0::update(|cr0| cr0.insert(Cr0Flags::TASK_SWITCHED)) };
+        }
+        crate::lazy_fp::set_current(next);
+    }
+}
+
+/// Clears `CR0.TS` and restores the extended states of the current task on the
+/// device-not-available exception (`#NM`) caused by their first use since the
+/// task was switched to.
+///
+/// Returns `false` if `CR0.TS` is not set, i.e., the exception is not caused
+/// by lazy switching.
+#[cfg(all(feature = "fp-lazy", target_os = "none"))]
+pub(super) fn handle_fp_trap() -> bool {
+    use x86_64::registers::control::{Cr0, Cr0Flags};
+    if !Cr0::read().contains(Cr0Flags::TASK_SWITCHED) {
+        return false;
+    }
+    unsafe { Cr0::update(|cr0| cr0.remove(Cr0Flags::TASK_SWITCHED)) };
+    if let Some(state) = unsafe { crate::lazy_fp::current::<ExtendedState>() } {
+        state.restore();
     }
+    true
 }
 
 impl fmt::Debug for ExtendedState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("ExtendedState")
             .field("fxsave_area", &self.fxsave_area)
-            .finish()
+            .field("xsave_header", &self.xsave_header)
+            .finish_non_exhaustive()
     }
 }
 
@@ -180,6 +316,9 @@ pub struct TaskContext {
     /// The `CR3` register value, i.e., the page table root.
     #[cfg(feature = "uspace")]
     pub cr3: memory_addr::PhysAddr,
+    /// Hardware breakpoints of the task.
+    #[cfg(feature = "hw-breakpoint")]
+    pub hw_breakpoints: super::debug::HwBreakpoints,
 }
 
 impl TaskContext {
@@ -201,6 +340,8 @@ impl TaskContext {
             ext_state: ExtendedState::default(),
             #[cfg(feature = "uspace")]
             gs_base: 0,
+            #[cfg(feature = "hw-breakpoint")]
+            hw_breakpoints: super::debug::HwBreakpoints::new(),
         }
     }
 
@@ -240,11 +381,13 @@ impl TaskContext {
     /// It first saves the current task's context from CPU to this place, and then
     /// restores the next task's context from `next_ctx` to CPU.
     pub fn switch_to(&mut self, next_ctx: &Self) {
-        #[cfg(feature = "fp-simd")]
+        #[cfg(all(feature = "fp-simd", not(feature = "fp-lazy")))]
         {
             self.ext_state.save();
             next_ctx.ext_state.restore();
         }
+        #[cfg(feature = "fp-lazy")]
+        self.ext_state.lazy_switch_to(&next_ctx.ext_state);
         #[cfg(any(feature = "tls", feature = "uspace"))]
         unsafe {
             self.fs_base = crate::asm::read_thread_pointer();
@@ -261,6 +404,8 @@ impl TaskContext {
                 // writing to CR3 has flushed the TLB
             }
         }
+        #[cfg(feature = "hw-breakpoint")]
+        self.hw_breakpoints.switch_to(&next_ctx.hw_breakpoints);
         unsafe { context_switch(&mut self.rsp, &next_ctx.rsp) }
     }
 }
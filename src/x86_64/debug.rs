@@ -0,0 +1,103 @@
+//! Hardware debugging support.
+
+#[cfg(feature = "hw-breakpoint")]
+use x86::debugregs::{dr7_write, BreakCondition, BreakSize, Breakpoint, Dr7};
+use x86_64::registers::rflags::RFlags;
+
+use super::TrapFrame;
+
+/// Turns single-stepping on or off for the context in the trap frame.
+///
+/// When it is on, a [`SINGLE_STEP`](crate::trap::SINGLE_STEP) trap is taken
+/// after the context executes one instruction on return from the trap. It uses
+/// the trap flag (`RFLAGS.TF`), which raises a debug exception (`#DB`).
+///
+/// Returns `false` if single-stepping is not supported on the architecture.
+pub fn set_single_step(tf: &mut TrapFrame, on: bool) -> bool {
+    if on {
+        tf.rflags |= RFlags::TRAP_FLAG.bits();
+    } else {
+        tf.rflags &= !RFlags::TRAP_FLAG.bits();
+    }
+    true
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub use crate::hw_breakpoint::{HwBreakpoint, HwBreakpointKind, HwBreakpoints};
+
+/// Number of hardware breakpoint slots, i.e., `DR0` to `DR3`.
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) const NUM_HW_SLOTS: usize = 4;
+
+#[cfg(feature = "hw-breakpoint")]
+const DEBUG_REGS: [Breakpoint; NUM_HW_SLOTS] = [
+    Breakpoint::Dr0,
+    Breakpoint::Dr1,
+    Breakpoint::Dr2,
+    Breakpoint::Dr3,
+];
+
+/// Returns the condition and size fields in `DR7` of the breakpoint, or
+/// [`None`] if it is not supported. Read-only watchpoints are not supported.
+#[cfg(feature = "hw-breakpoint")]
+fn encode(bp: &HwBreakpoint) -> Option<(BreakCondition, BreakSize)> {
+    let cond = match bp.kind {
+        HwBreakpointKind::Execute => {
+            return Some((BreakCondition::Instructions, BreakSize::Bytes1))
+        }
+        HwBreakpointKind::Read => return None,
+        HwBreakpointKind::Write => BreakCondition::DataWrites,
+        HwBreakpointKind::ReadWrite => BreakCondition::DataReadsWrites,
+    };
+    let size = match bp.len {
+        1 => BreakSize::Bytes1,
+        2 => BreakSize::Bytes2,
+        4 => BreakSize::Bytes4,
+        8 => BreakSize::Bytes8,
+        _ => return None,
+    };
+    bp.addr.is_multiple_of(bp.len).then_some((cond, size))
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn hw_slot_supports(_slot: usize, bp: &HwBreakpoint) -> bool {
+    encode(bp).is_some()
+}
+
+#[cfg(feature = "hw-breakpoint")]
+pub(crate) fn install_hw_breakpoints(slots: &[Option<HwBreakpoint>; NUM_HW_SLOTS]) {
+    let mut dr7 = Dr7::default();
+    for (reg, bp) in DEBUG_REGS.iter().zip(slots) {
+        match bp.and_then(|bp| Some((bp.addr, encode(&bp)?))) {
+            Some((addr, (cond, size))) => {
+                unsafe { reg.write(addr) };
+                dr7.configure_bp(*reg, cond, size);
+                dr7.enable_bp(*reg, false);
+            }
+            None => unsafe { reg.write(0) },
+        }
+    }
+    unsafe { dr7_write(dr7) };
+}
+
+/// Reports the hardware breakpoints that hit according to `DR6`.
+#[cfg(all(feature = "hw-breakpoint", target_os = "none"))]
+pub(super) fn handle_hw_breakpoints(tf: &mut TrapFrame, dr6: x86::debugregs::Dr6) {
+    let dr7 = unsafe { x86::debugregs::dr7() };
+    for (slot, reg) in DEBUG_REGS.iter().enumerate() {
+        // B0 to B3 may be set for disabled breakpoints.
+        if dr6.bits() & (1 << slot) == 0 || dr7.0 & (1 << (slot * 2)) == 0 {
+            continue;
+        }
+        if (dr7.0 >> (16 + slot * 4)) & 0b11 == BreakCondition::Instructions as usize {
+            // Execution breakpoints are faults, so the instruction is executed
+            // without hitting the breakpoint again after the trap only if the
+            // resume flag is set.
+            tf.rflags |= RFlags::RESUME_FLAG.bits();
+        }
+        let addr = unsafe { reg.dr() };
+        if !handle_trap!(HW_BREAKPOINT, tf, slot, va!(addr)) {
+            debug!("Hardware breakpoint {} hit @ {:#x}", slot, tf.rip);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Mapping of x86_64 trap frames to the GDB register layout.
+
+use crate::TrapFrame;
+
+/// Number of registers in the `g` packet: 17 64-bit registers (`rax` to
+/// `rip`), followed by `eflags` and 6 segment registers of 32 bits.
+pub(crate) const NUM_REGS: usize = 24;
+
+/// Reads the `n`-th register of GDB, returning the value and its size in
+/// bytes.
+pub(crate) fn read_reg(tf: &TrapFrame, n: usize) -> Option<(u64, usize)> {
+    Some(match n {
+        0 => (tf.rax, 8),
+        1 => (tf.rbx, 8),
+        2 => (tf.rcx, 8),
+        3 => (tf.rdx, 8),
+        4 => (tf.rsi, 8),
+        5 => (tf.rdi, 8),
+        6 => (tf.rbp, 8),
+        7 => (tf.rsp, 8),
+        8 => (tf.r8, 8),
+        9 => (tf.r9, 8),
+        10 => (tf.r10, 8),
+        11 => (tf.r11, 8),
+        12 => (tf.r12, 8),
+        13 => (tf.r13, 8),
+        14 => (tf.r14, 8),
+        15 => (tf.r15, 8),
+        16 => (tf.rip, 8),
+        17 => (tf.rflags, 4),
+        18 => (tf.cs, 4),
+        19 => (tf.ss, 4),
+        // ds, es, fs, gs
+        20..NUM_REGS => (0, 4),
+        _ => return None,
+    })
+}
+
+/// Writes the `n`-th register of GDB. Returns `false` if it is not writable.
+pub(crate) fn write_reg(tf: &mut TrapFrame, n: usize, val: u64) -> bool {
+    let reg = match n {
+        0 => &mut tf.rax,
+        1 => &mut tf.rbx,
+        2 => &mut tf.rcx,
+        3 => &mut tf.rdx,
+        4 => &mut tf.rsi,
+        5 => &mut tf.rdi,
+        6 => &mut tf.rbp,
+        7 => &mut tf.rsp,
+        8 => &mut tf.r8,
+        9 => &mut tf.r9,
+        10 => &mut tf.r10,
+        11 => &mut tf.r11,
+        12 => &mut tf.r12,
+        13 => &mut tf.r13,
+        14 => &mut tf.r14,
+        15 => &mut tf.r15,
+        16 => &mut tf.rip,
+        17 => &mut tf.rflags,
+        _ => return false,
+    };
+    *reg = val;
+    true
+}
+
+/// `int3`
+const INT3: [u8; 1] = [0xcc];
+
+/// Returns the breakpoint instruction of the given GDB breakpoint kind.
+pub(crate) fn breakpoint_insn(kind: usize) -> Option<&'static [u8]> {
+    match kind {
+        1 => Some(&INT3),
+        _ => None,
+    }
+}
+
+/// Returns the address of the breakpoint instruction that caused the trap.
+pub(crate) fn breakpoint_addr(tf: &TrapFrame) -> usize {
+    // `rip` points to the instruction after `int3`.
+    tf.rip as usize - 1
+}
+
+/// Sets the program counter in the trap frame.
+pub(crate) fn set_pc(tf: &mut TrapFrame, pc: usize) {
+    tf.rip = pc as _;
+}
+
+/// Makes the modified instructions in `addr..addr + len` visible to
+/// instruction fetch.
+pub(crate) fn flush_icache(_addr: usize, _len: usize) {
+    // The instruction cache is coherent on x86_64.
+}
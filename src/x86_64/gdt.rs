@@ -1,6 +1,7 @@
 use core::fmt;
 
 use lazyinit::LazyInit;
+use x86::irq::{DOUBLE_FAULT_VECTOR, MACHINE_CHECK_VECTOR, NONMASKABLE_INTERRUPT_VECTOR};
 use x86_64::instructions::tables::{lgdt, load_tss};
 use x86_64::registers::segmentation::{Segment, SegmentSelector, CS};
 use x86_64::structures::gdt::{Descriptor, DescriptorFlags};
@@ -14,6 +15,30 @@ static TSS: TaskStateSegment = TaskStateSegment::new();
 #[percpu::def_percpu]
 static GDT: LazyInit<GdtStruct> = LazyInit::new();
 
+/// Size of each per-CPU interrupt stack in the IST.
+const IST_STACK_SIZE: usize = 0x4000;
+
+/// Exceptions that are handled on dedicated interrupt stacks, so that they can
+/// be handled even if the kernel stack is corrupted or overflowed.
+///
+/// The position in this array is the IST index (starting from 0, i.e., IST1).
+///
+/// `#DB` is not included: it can be raised by a data breakpoint while already
+/// running on an IST stack, and the nested exception would reset the stack
+/// pointer and overwrite the frames of the outer one.
+pub(super) const IST_VECTORS: [u8; 3] = [
+    DOUBLE_FAULT_VECTOR,
+    NONMASKABLE_INTERRUPT_VECTOR,
+    MACHINE_CHECK_VECTOR,
+];
+
+#[repr(C, align(16))]
+struct IstStack([u8; IST_STACK_SIZE]);
+
+#[percpu::def_percpu]
+static IST_STACKS: [IstStack; IST_VECTORS.len()] =
+    [const { IstStack([0; IST_STACK_SIZE]) }; IST_VECTORS.len()];
+
 /// A wrapper of the Global Descriptor Table (GDT) with maximum 16 entries.
 #[repr(align(16))]
 pub struct GdtStruct {
@@ -101,8 +126,17 @@ impl fmt::Debug for GdtStruct {
 
 /// Initializes the per-CPU TSS and GDT structures and loads them into the
 /// current CPU.
+///
+/// The interrupt stack table (IST) of the TSS is also filled with the per-CPU
+/// interrupt stacks.
 pub fn init_gdt() {
     unsafe {
+        let tss = TSS.current_ref_mut_raw();
+        let stacks = IST_STACKS.current_ref_raw();
+        for (i, stack) in stacks.iter().enumerate() {
+            tss.interrupt_stack_table[i] = VirtAddr::from_ptr(stack.0.as_ptr_range().end);
+        }
+
         let gdt = GDT.current_ref_raw();
         gdt.init_once(GdtStruct::new(TSS.current_ref_raw()));
         gdt.load();
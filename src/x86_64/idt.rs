@@ -5,6 +5,8 @@ use x86_64::addr::VirtAddr;
 use x86_64::structures::idt::{Entry, HandlerFunc, InterruptDescriptorTable};
 use x86_64::structures::DescriptorTablePointer;
 
+use super::gdt::IST_VECTORS;
+
 const NUM_INT: usize = 256;
 
 static IDT: LazyInit<IdtStruct> = LazyInit::new();
@@ -18,6 +20,10 @@ pub struct IdtStruct {
 impl IdtStruct {
     /// Constructs a new IDT struct that filled with entries from
     /// `trap_handler_table`.
+    ///
+    /// The double fault, NMI and machine check exceptions are set to switch to
+    /// their own interrupt stacks, while the others, including `#DB`, run on
+    /// the current kernel stack.
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         unsafe extern "C" {
@@ -41,6 +47,14 @@ impl IdtStruct {
                 // enable user space breakpoints and legacy int 0x80 syscall
                 opt.set_privilege_level(x86_64::PrivilegeLevel::Ring3);
             }
+            // `#DB` deliberately stays on the regular stack. A data breakpoint
+            // or single step can hit while an IST handler is running, and
+            // since an IST entry always resets the stack pointer to the top of
+            // its stack, a nested `#DB` on an IST stack would overwrite the
+            // frames of the one it interrupted.
+            if let Some(index) = IST_VECTORS.iter().position(|&v| v as usize == i) {
+                unsafe { opt.set_stack_index(index as u16) };
+            }
         }
         idt
     }
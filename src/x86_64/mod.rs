@@ -3,18 +3,25 @@ mod gdt;
 mod idt;
 
 pub mod asm;
+pub mod debug;
 pub mod init;
 
+#[cfg(feature = "gdbstub")]
+pub(crate) mod gdb;
+
 #[cfg(target_os = "none")]
 mod trap;
 
 #[cfg(feature = "uspace")]
 mod syscall;
 
+#[cfg(feature = "fp-simd")]
+mod xsave;
+
 #[cfg(feature = "uspace")]
 pub mod uspace;
 
-pub use self::context::{ExtendedState, FxsaveArea, TaskContext, TrapFrame};
+pub use self::context::{ExtendedState, FxsaveArea, TaskContext, TrapFrame, XsaveHeader};
 pub use self::gdt::GdtStruct;
 pub use self::idt::IdtStruct;
 pub use x86_64::structures::tss::TaskStateSegment;
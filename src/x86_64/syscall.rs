@@ -4,6 +4,7 @@ use x86_64::registers::rflags::RFlags;
 use x86_64::structures::tss::TaskStateSegment;
 
 use super::{GdtStruct, TrapFrame};
+use crate::trap::SyscallAction;
 
 #[unsafe(no_mangle)]
 #[percpu::def_percpu]
@@ -15,8 +16,24 @@ core::arch::global_asm!(
 );
 
 #[unsafe(no_mangle)]
-pub(super) fn x86_syscall_handler(tf: &mut TrapFrame) {
-    tf.rax = crate::trap::handle_syscall(tf, tf.rax as usize) as u64;
+fn x86_syscall_handler(tf: &mut TrapFrame) {
+    trace_trap!(TRAP_ENTER, crate::trap::TrapType::Syscall, tf);
+    handle_syscall(tf);
+    trace_trap!(TRAP_EXIT, crate::trap::TrapType::Syscall, tf);
+}
+
+/// Handles a syscall from either the `syscall` instruction or `int 0x80`.
+pub(super) fn handle_syscall(tf: &mut TrapFrame) {
+    let syscall_num = tf.rax;
+    match crate::trap::handle_syscall(tf, syscall_num as usize) {
+        SyscallAction::Return(ret) => tf.rax = ret as u64,
+        SyscallAction::Restart => {
+            // Both `syscall` and `int 0x80` are 2 bytes long.
+            tf.rax = syscall_num;
+            tf.rip -= 2;
+        }
+        SyscallAction::NoReturnValue => {}
+    }
 }
 
 /// Initializes syscall support and setups the syscall handler.
@@ -1,8 +1,10 @@
-use x86::{controlregs::cr2, irq::*};
+use memory_addr::VirtAddr;
+use x86::{controlregs::cr2, debugregs, irq::*};
 use x86_64::structures::idt::PageFaultErrorCode;
 
 use super::context::TrapFrame;
-use crate::trap::PageFaultFlags;
+use crate::extable::search_exception_table;
+use crate::trap::{ExceptionInfo, ExceptionKind, PageFaultFlags};
 
 core::arch::global_asm!(include_str!("trap.S"));
 
@@ -12,50 +14,152 @@ const LEGACY_SYSCALL_VECTOR: u8 = 0x80;
 const IRQ_VECTOR_START: u8 = 0x20;
 const IRQ_VECTOR_END: u8 = 0xff;
 
-fn handle_page_fault(tf: &TrapFrame) {
+fn handle_exception(tf: &mut TrapFrame, kind: ExceptionKind, vaddr: Option<VirtAddr>) -> bool {
+    count_trap!(EXCEPTION, kind);
+    if !tf.is_user() {
+        if let Some(fixup) = search_exception_table(tf.rip as _) {
+            tf.rip = fixup as _;
+            return true;
+        }
+    }
+    let info = ExceptionInfo {
+        kind,
+        pc: tf.rip as _,
+        cause: tf.vector as _,
+        vaddr,
+        is_user: tf.is_user(),
+    };
+    handle_trap!(EXCEPTION, tf, &info)
+}
+
+fn handle_page_fault(tf: &mut TrapFrame) {
+    count_trap!(page_faults);
     let access_flags = err_code_to_flags(tf.error_code)
         .unwrap_or_else(|e| panic!("Invalid #PF error code: {:#x}", e));
     let vaddr = va!(unsafe { cr2() });
-    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user()) {
+    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user())
+        && !handle_exception(tf, ExceptionKind::PageFault, Some(vaddr))
+    {
         panic!(
-            "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
+            "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}\n{}",
             if tf.is_user() { "user" } else { "kernel" },
             tf.rip,
             vaddr,
             tf.error_code,
             access_flags,
             tf,
+            crate::backtrace::trap_backtrace(tf),
         );
     }
 }
 
+/// Handles the double fault, which is an abort and cannot be recovered from.
+///
+/// It runs on a dedicated interrupt stack, so it still works if the double
+/// fault is caused by a kernel stack overflow.
+fn handle_double_fault(tf: &TrapFrame) -> ! {
+    crate::asm::disable_irqs();
+    error!(
+        "#DF @ {:#x}, rsp={:#x}, error_code={:#x}:\n{:#x?}",
+        tf.rip, tf.rsp, tf.error_code, tf
+    );
+    loop {
+        crate::asm::halt();
+    }
+}
+
+fn handle_debug(tf: &mut TrapFrame) {
+    let dr6 = unsafe { debugregs::dr6() };
+    if dr6.contains(debugregs::Dr6::BS) {
+        super::debug::set_single_step(tf, false);
+        if !handle_trap!(SINGLE_STEP, tf) {
+            debug!("Single-step #DB @ {:#x}", tf.rip);
+        }
+    }
+    #[cfg(feature = "hw-breakpoint")]
+    super::debug::handle_hw_breakpoints(tf, dr6);
+    // DR6 is never cleared by the processor.
+    unsafe { debugregs::dr6_write(debugregs::Dr6::empty()) };
+}
+
 #[unsafe(no_mangle)]
 fn x86_trap_handler(tf: &mut TrapFrame) {
+    #[cfg(feature = "trap-trace")]
+    let ty = vec_to_type(tf.vector as u8);
+    trace_trap!(TRAP_ENTER, ty, tf);
+    let mut is_irq = false;
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
-        BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
+        DOUBLE_FAULT_VECTOR => handle_double_fault(tf),
+        DEBUG_VECTOR => handle_debug(tf),
+        #[cfg(feature = "fp-lazy")]
+        DEVICE_NOT_AVAILABLE_VECTOR if super::context::handle_fp_trap() => {}
+        BREAKPOINT_VECTOR => {
+            count_trap!(breakpoints);
+            if !handle_trap!(BREAKPOINT, tf) {
+                debug!("#BP @ {:#x} ", tf.rip);
+            }
+        }
         GENERAL_PROTECTION_FAULT_VECTOR => {
-            panic!(
-                "#GP @ {:#x}, error_code={:#x}:\n{:#x?}",
-                tf.rip, tf.error_code, tf
-            );
+            if !handle_exception(tf, ExceptionKind::AccessFault, None) {
+                panic!(
+                    "#GP @ {:#x}, error_code={:#x}:\n{:#x?}\n{}",
+                    tf.rip,
+                    tf.error_code,
+                    tf,
+                    crate::backtrace::trap_backtrace(tf),
+                );
+            }
         }
         #[cfg(feature = "uspace")]
-        LEGACY_SYSCALL_VECTOR => super::syscall::x86_syscall_handler(tf),
+        LEGACY_SYSCALL_VECTOR => super::syscall::handle_syscall(tf),
         IRQ_VECTOR_START..=IRQ_VECTOR_END => {
-            handle_trap!(IRQ, tf.vector as _);
+            let handled = handle_trap!(IRQ, tf.vector as _);
+            count_trap!(IRQ, tf.vector as _, handled);
+            is_irq = true;
         }
-        _ => {
-            panic!(
-                "Unhandled exception {} ({}, error_code={:#x}) @ {:#x}:\n{:#x?}",
-                tf.vector,
-                vec_to_str(tf.vector),
-                tf.error_code,
-                tf.rip,
-                tf
-            );
+        vector => {
+            if !handle_exception(tf, vec_to_kind(vector), None) {
+                panic!(
+                    "Unhandled exception {} ({}, error_code={:#x}) @ {:#x}:\n{:#x?}\n{}",
+                    tf.vector,
+                    vec_to_str(tf.vector),
+                    tf.error_code,
+                    tf.rip,
+                    tf,
+                    crate::backtrace::trap_backtrace(tf),
+                );
+            }
         }
     }
+    trace_trap!(TRAP_EXIT, ty, tf);
+    if is_irq {
+        irq_return!(tf);
+    }
+}
+
+#[cfg(feature = "trap-trace")]
+fn vec_to_type(vec: u8) -> crate::trap::TrapType {
+    use crate::trap::TrapType;
+    match vec {
+        PAGE_FAULT_VECTOR => TrapType::PageFault,
+        BREAKPOINT_VECTOR | DEBUG_VECTOR => TrapType::Breakpoint,
+        #[cfg(feature = "uspace")]
+        LEGACY_SYSCALL_VECTOR => TrapType::Syscall,
+        IRQ_VECTOR_START..=IRQ_VECTOR_END => TrapType::Irq,
+        _ => TrapType::Exception,
+    }
+}
+
+fn vec_to_kind(vec: u8) -> ExceptionKind {
+    match vec {
+        DIVIDE_ERROR_VECTOR => ExceptionKind::DivideError,
+        INVALID_OPCODE_VECTOR => ExceptionKind::IllegalInstruction,
+        SEGMENT_NOT_PRESENT_VECTOR | STACK_SEGEMENT_FAULT_VECTOR => ExceptionKind::AccessFault,
+        X87_FPU_VECTOR | SIMD_FLOATING_POINT_VECTOR => ExceptionKind::FloatingPoint,
+        ALIGNMENT_CHECK_VECTOR => ExceptionKind::Misaligned,
+        _ => ExceptionKind::Other,
+    }
 }
 
 fn vec_to_str(vec: u64) -> &'static str {
@@ -1,9 +1,17 @@
 //! Structures and functions for user space.
 
+use core::arch::naked_asm;
+
 use memory_addr::VirtAddr;
 
 use crate::TrapFrame;
 
+pub use crate::uaccess::{
+    clear_user, copy_from_user, copy_to_user, is_user_range, strncpy_from_user,
+};
+
+pub mod signal;
+
 /// Context to enter user space.
 pub struct UspaceContext(TrapFrame);
 
@@ -106,3 +114,98 @@ impl UspaceContext {
         }
     }
 }
+
+/// Sets `RFLAGS.AC` to allow supervisor access to user pages if SMAP is
+/// enabled, and keeps whether it is enabled in `r8`.
+macro_rules! user_access_begin {
+    () => {
+        "
+        mov     r8, cr4
+        and     r8, 1 << 21     // CR4.SMAP
+        jz      9f
+        stac
+    9:"
+    };
+}
+
+/// Clears `RFLAGS.AC` if SMAP is enabled.
+macro_rules! user_access_end {
+    () => {
+        "
+        test    r8, r8
+        jz      9f
+        clac
+    9:"
+    };
+}
+
+/// Copies `len` bytes from `src` to `dst`, either of which may be in user
+/// space. Returns the number of bytes not copied.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_copy_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> usize {
+    naked_asm!(
+        "mov    rcx, rdx",
+        user_access_begin!(),
+        "
+    2:  rep movsb
+    3:",
+        user_access_end!(),
+        "
+        mov     rax, rcx
+        ret",
+        crate::asm_extable!("2b", "3b"),
+    )
+}
+
+/// Fills `len` bytes of user space at `dst` with zeros. Returns the number of
+/// bytes not cleared.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_clear_user(_dst: *mut u8, _len: usize) -> usize {
+    naked_asm!(
+        "
+        mov     rcx, rsi
+        xor     eax, eax",
+        user_access_begin!(),
+        "
+    2:  rep stosb
+    3:",
+        user_access_end!(),
+        "
+        mov     rax, rcx
+        ret",
+        crate::asm_extable!("2b", "3b"),
+    )
+}
+
+/// Copies a NUL-terminated string of at most `len` bytes from user space.
+/// Returns the length of the string, `len` if it is not terminated within
+/// `len` bytes, or `-1` on fault.
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn raw_strncpy_from_user(
+    _dst: *mut u8,
+    _src: *const u8,
+    _len: usize,
+) -> isize {
+    naked_asm!(
+        "xor    eax, eax",
+        user_access_begin!(),
+        "
+    2:  cmp     rax, rdx
+        je      4f
+    3:  mov     cl, byte ptr [rsi + rax]
+        mov     byte ptr [rdi + rax], cl
+        test    cl, cl
+        jz      4f
+        inc     rax
+        jmp     2b
+    5:  mov     rax, -1
+    4:",
+        user_access_end!(),
+        "ret",
+        crate::asm_extable!("3b", "5b"),
+    )
+}
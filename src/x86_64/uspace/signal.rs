@@ -0,0 +1,369 @@
+//! Signal frames compatible with the x86_64 Linux ABI.
+
+use x86_64::registers::rflags::RFlags;
+
+use super::UspaceContext;
+use crate::signal::{read_from_user, write_to_user};
+use crate::TrapFrame;
+
+pub use crate::signal::{SignalDelivery, SignalInfo, SignalStack};
+
+/// Size of the red zone below the user stack pointer, which must not be
+/// clobbered by the signal frame.
+const RED_ZONE_SIZE: usize = 128;
+
+/// `uc_flags`: the FP state in the signal frame is an XSAVE area.
+#[cfg(feature = "fp-simd")]
+const UC_FP_XSTATE: u64 = 0x1;
+/// `uc_flags`: `ss` is saved in the signal context and restored strictly.
+const UC_SIGCONTEXT_SS: u64 = 0x2;
+const UC_STRICT_RESTORE_SS: u64 = 0x4;
+
+/// Magic numbers of the XSAVE area in the signal frame: in
+/// [`FpxSwBytes::magic1`], and right after the XSAVE area.
+#[cfg(feature = "fp-simd")]
+const FP_XSTATE_MAGIC1: u32 = 0x4650_5853;
+#[cfg(feature = "fp-simd")]
+const FP_XSTATE_MAGIC2: u32 = 0x4650_5845;
+
+/// Offset of [`FpxSwBytes`] in the FXSAVE area, i.e., its software-available
+/// bytes, which are ignored by the processor.
+#[cfg(feature = "fp-simd")]
+const FPX_SW_BYTES_OFFSET: usize = 464;
+
+/// `struct _fpx_sw_bytes` in Linux, which describes the XSAVE area in the
+/// signal frame.
+#[cfg(feature = "fp-simd")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FpxSwBytes {
+    /// [`FP_XSTATE_MAGIC1`] if the FP state is an XSAVE area.
+    magic1: u32,
+    /// Size of the XSAVE area plus [`FP_XSTATE_MAGIC2`].
+    extended_size: u32,
+    /// State components saved in the XSAVE area.
+    xfeatures: u64,
+    /// Size of the XSAVE area.
+    xstate_size: u32,
+    padding: [u32; 7],
+}
+
+/// `RFLAGS` bits that can be changed by the signal handler.
+const FIX_RFLAGS: RFlags = RFlags::ALIGNMENT_CHECK
+    .union(RFlags::OVERFLOW_FLAG)
+    .union(RFlags::DIRECTION_FLAG)
+    .union(RFlags::TRAP_FLAG)
+    .union(RFlags::SIGN_FLAG)
+    .union(RFlags::ZERO_FLAG)
+    .union(RFlags::AUXILIARY_CARRY_FLAG)
+    .union(RFlags::PARITY_FLAG)
+    .union(RFlags::CARRY_FLAG)
+    .union(RFlags::RESUME_FLAG);
+
+/// `struct sigcontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SigContext {
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rdi: u64,
+    rsi: u64,
+    rbp: u64,
+    rbx: u64,
+    rdx: u64,
+    rax: u64,
+    rcx: u64,
+    rsp: u64,
+    rip: u64,
+    eflags: u64,
+    cs: u16,
+    gs: u16,
+    fs: u16,
+    ss: u16,
+    err: u64,
+    trapno: u64,
+    oldmask: u64,
+    cr2: u64,
+    /// User address of the FXSAVE or XSAVE area, or zero if not saved.
+    fpstate: u64,
+    reserved1: [u64; 8],
+}
+
+/// `struct ucontext` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UContext {
+    flags: u64,
+    link: u64,
+    stack: SignalStack,
+    mcontext: SigContext,
+    sigmask: u64,
+}
+
+/// `struct rt_sigframe` in Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SignalFrame {
+    /// Return address of the handler, i.e., the trampoline.
+    pretcode: u64,
+    uc: UContext,
+    info: SignalInfo,
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<SigContext>(), 256);
+static_assertions::const_assert_eq!(core::mem::size_of::<SignalFrame>(), 440);
+#[cfg(feature = "fp-simd")]
+static_assertions::const_assert_eq!(
+    FPX_SW_BYTES_OFFSET + core::mem::size_of::<FpxSwBytes>(),
+    core::mem::size_of::<crate::FxsaveArea>()
+);
+
+/// Writes the live extended states of the current task below `sp`.
+///
+/// Returns the address of the area and the flags for `uc_flags`, or [`None`]
+/// if the user stack is not writable.
+#[cfg(feature = "fp-simd")]
+unsafe fn save_fpstate(sp: usize) -> Option<(usize, u64)> {
+    use crate::uaccess::copy_to_user;
+
+    let mut state = crate::ExtendedState::default();
+    state.save_standard();
+    let Some((xfeatures, xstate_size)) = crate::ExtendedState::standard_layout() else {
+        let fpstate = (sp - core::mem::size_of::<crate::FxsaveArea>()) & !63;
+        return unsafe { write_to_user(fpstate, &state.fxsave_area) }.then_some((fpstate, 0));
+    };
+
+    let fpstate = (sp - xstate_size - 4) & !63;
+    let area = unsafe { core::slice::from_raw_parts(&state as *const _ as *const u8, xstate_size) };
+    let sw_bytes = FpxSwBytes {
+        magic1: FP_XSTATE_MAGIC1,
+        extended_size: (xstate_size + 4) as u32,
+        xfeatures,
+        xstate_size: xstate_size as u32,
+        padding: [0; 7],
+    };
+    let written = unsafe {
+        copy_to_user(fpstate as *mut u8, area) == 0
+            && write_to_user(fpstate + FPX_SW_BYTES_OFFSET, &sw_bytes)
+            && write_to_user(fpstate + xstate_size, &FP_XSTATE_MAGIC2)
+    };
+    written.then_some((fpstate, UC_FP_XSTATE))
+}
+
+/// Restores the live extended states of the current task from the area at
+/// `fpstate` written by [`save_fpstate`].
+///
+/// If the magic numbers or sizes are not valid, only the FXSAVE area is
+/// restored, and the other state components are reset, as in Linux.
+///
+/// Returns [`None`] if the area is not readable.
+#[cfg(feature = "fp-simd")]
+unsafe fn restore_fpstate(fpstate: usize) -> Option<()> {
+    use crate::uaccess::copy_from_user;
+
+    let mut state = crate::ExtendedState::default();
+    state.save_standard();
+    let mxcsr_mask = match state.fxsave_area.mxcsr_mask {
+        0 => 0xffbf,
+        mask => mask,
+    };
+
+    let sw_bytes: FpxSwBytes = unsafe { read_from_user(fpstate + FPX_SW_BYTES_OFFSET)? };
+    let xstate_size = sw_bytes.xstate_size as usize;
+    let xstate = match crate::ExtendedState::standard_layout() {
+        Some((_, size))
+            if sw_bytes.magic1 == FP_XSTATE_MAGIC1
+                && (core::mem::size_of::<crate::FxsaveArea>() + 64..=size)
+                    .contains(&xstate_size)
+                && sw_bytes.extended_size as usize == xstate_size + 4 =>
+        unsafe { read_from_user::<u32>(fpstate + xstate_size)? == FP_XSTATE_MAGIC2 },
+        _ => false,
+    };
+
+    if xstate {
+        let area = unsafe {
+            core::slice::from_raw_parts_mut(&mut state as *mut _ as *mut u8, xstate_size)
+        };
+        if unsafe { copy_from_user(area, fpstate as *const u8) } != 0 {
+            return None;
+        }
+        state.xsave_header.xstate_bv &= sw_bytes.xfeatures;
+    } else {
+        state.fxsave_area = unsafe { read_from_user(fpstate)? };
+        // Only the x87 and SSE states are restored, even if they were initial
+        // when saved.
+        state.xsave_header.xstate_bv = 0b11;
+    }
+    state.restore_standard(mxcsr_mask);
+    Some(())
+}
+
+/// Pushes a signal frame onto the user stack, and redirects the trap frame to
+/// the signal handler.
+///
+/// If the `fp-simd` feature is enabled, the extended states are saved above
+/// the frame, as an XSAVE area in the standard format with the software bytes
+/// and magic numbers of Linux (`_fpx_sw_bytes`), or only as an FXSAVE area if
+/// XSAVE is not enabled. They are the live states on the CPU, so it must be
+/// called on the task whose trap frame is given. The address of the
+/// trampoline is pushed as the return address of the handler.
+///
+/// Returns `false` if the user stack is not writable, in which case the trap
+/// frame is not modified.
+///
+/// # Safety
+///
+/// The caller must ensure that the user stack to push the frame onto is in
+/// user space.
+pub unsafe fn setup_sigframe(tf: &mut TrapFrame, delivery: &SignalDelivery) -> bool {
+    let sp = if delivery.use_altstack {
+        delivery.stack_top(tf.rsp as _)
+    } else {
+        tf.rsp as usize - RED_ZONE_SIZE
+    };
+
+    #[cfg(feature = "fp-simd")]
+    let (sp, fpstate, fp_flags) = {
+        let Some((fpstate, flags)) = (unsafe { save_fpstate(sp) }) else {
+            return false;
+        };
+        (fpstate, fpstate as u64, flags)
+    };
+    #[cfg(not(feature = "fp-simd"))]
+    let (fpstate, fp_flags) = (0, 0);
+
+    // Make the stack aligned as if the trampoline address was pushed by a
+    // `call` instruction.
+    let frame_addr = ((sp - core::mem::size_of::<SignalFrame>() + 8) & !15) - 8;
+    let frame = SignalFrame {
+        pretcode: delivery.restorer as _,
+        uc: UContext {
+            flags: UC_SIGCONTEXT_SS | UC_STRICT_RESTORE_SS | fp_flags,
+            link: 0,
+            stack: delivery.altstack,
+            mcontext: SigContext {
+                r8: tf.r8,
+                r9: tf.r9,
+                r10: tf.r10,
+                r11: tf.r11,
+                r12: tf.r12,
+                r13: tf.r13,
+                r14: tf.r14,
+                r15: tf.r15,
+                rdi: tf.rdi,
+                rsi: tf.rsi,
+                rbp: tf.rbp,
+                rbx: tf.rbx,
+                rdx: tf.rdx,
+                rax: tf.rax,
+                rcx: tf.rcx,
+                rsp: tf.rsp,
+                rip: tf.rip,
+                eflags: tf.rflags,
+                cs: tf.cs as _,
+                gs: 0,
+                fs: 0,
+                ss: tf.ss as _,
+                err: tf.error_code,
+                trapno: tf.vector,
+                oldmask: delivery.sigmask,
+                cr2: 0,
+                fpstate,
+                reserved1: [0; 8],
+            },
+            sigmask: delivery.sigmask,
+        },
+        info: delivery.info,
+    };
+    if !unsafe { write_to_user(frame_addr, &frame) } {
+        return false;
+    }
+
+    tf.rdi = delivery.info.signo as _;
+    tf.rsi = (frame_addr + core::mem::offset_of!(SignalFrame, info)) as _;
+    tf.rdx = (frame_addr + core::mem::offset_of!(SignalFrame, uc)) as _;
+    tf.rax = 0;
+    tf.rsp = frame_addr as _;
+    tf.rip = delivery.handler as _;
+    tf.rflags &= !(RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG | RFlags::RESUME_FLAG).bits();
+    true
+}
+
+/// Restores the trap frame from the signal frame on the user stack, which is
+/// pushed by [`setup_sigframe`]. It should be called on `rt_sigreturn`.
+///
+/// The extended states saved in the frame are restored to the CPU, i.e., as
+/// the live states of the current task, so it must be called on the task whose
+/// trap frame is given.
+///
+/// Returns the signal mask saved in the frame, or [`None`] if the frame is not
+/// readable, in which case the trap frame is not modified.
+///
+/// # Safety
+///
+/// The caller must ensure that the user stack pointer in the trap frame points
+/// to user space.
+pub unsafe fn restore_from_sigframe(tf: &mut TrapFrame) -> Option<u64> {
+    // The trampoline address has been popped by the `ret` of the handler.
+    let frame_addr = tf.rsp as usize - 8;
+    let frame: SignalFrame = unsafe { read_from_user(frame_addr)? };
+    let sc = &frame.uc.mcontext;
+
+    #[cfg(feature = "fp-simd")]
+    if sc.fpstate != 0 {
+        unsafe { restore_fpstate(sc.fpstate as _)? };
+    }
+
+    tf.r8 = sc.r8;
+    tf.r9 = sc.r9;
+    tf.r10 = sc.r10;
+    tf.r11 = sc.r11;
+    tf.r12 = sc.r12;
+    tf.r13 = sc.r13;
+    tf.r14 = sc.r14;
+    tf.r15 = sc.r15;
+    tf.rdi = sc.rdi;
+    tf.rsi = sc.rsi;
+    tf.rbp = sc.rbp;
+    tf.rbx = sc.rbx;
+    tf.rdx = sc.rdx;
+    tf.rax = sc.rax;
+    tf.rcx = sc.rcx;
+    tf.rsp = sc.rsp;
+    tf.rip = sc.rip;
+    tf.rflags = (tf.rflags & !FIX_RFLAGS.bits()) | (sc.eflags & FIX_RFLAGS.bits());
+    Some(frame.uc.sigmask)
+}
+
+impl UspaceContext {
+    /// Pushes a signal frame onto the user stack, and redirects the context to
+    /// the signal handler.
+    ///
+    /// See [`setup_sigframe`] for details. The extended states are still the live
+    /// states on the CPU, so this context must be the one of the current task.
+    ///
+    /// # Safety
+    ///
+    /// See [`setup_sigframe`].
+    pub unsafe fn setup_sigframe(&mut self, delivery: &SignalDelivery) -> bool {
+        unsafe { setup_sigframe(&mut self.0, delivery) }
+    }
+
+    /// Restores the context from the signal frame on the user stack.
+    ///
+    /// See [`restore_from_sigframe`] for details. The extended states are still the live
+    /// states on the CPU, so this context must be the one of the current task.
+    ///
+    /// # Safety
+    ///
+    /// See [`restore_from_sigframe`].
+    pub unsafe fn restore_from_sigframe(&mut self) -> Option<u64> {
+        unsafe { restore_from_sigframe(&mut self.0) }
+    }
+}
@@ -0,0 +1,196 @@
+//! Detection and use of the XSAVE feature set for [`ExtendedState`].
+//!
+//! The extended states are saved and restored by the best instructions
+//! available, in the order of preference: `XSAVES`/`XRSTORS`, `XSAVEOPT`/
+//! `XRSTOR`, `XSAVE`/`XRSTOR`, and `FXSAVE`/`FXRSTOR` as the fallback if XSAVE
+//! is not supported or not enabled by [`init`].
+
+use core::arch::x86_64::{
+    _fxrstor64, _fxsave64, _xrstor64, _xrstors64, _xsave64, _xsaveopt64, _xsaves64,
+};
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use x86::cpuid::native_cpuid::cpuid_count;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+use super::context::{ExtendedState, XSAVE_AREA_SIZE};
+
+/// `XCOMP_BV[63]`: the XSAVE area is in the compacted format.
+const XCOMP_BV_COMPACTED: u64 = 1 << 63;
+
+/// Instructions to save and restore the extended states.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SaveMode {
+    Fxsave,
+    Xsave,
+    Xsaveopt,
+    Xsaves,
+}
+
+static SAVE_MODE: AtomicU8 = AtomicU8::new(SaveMode::Fxsave as u8);
+
+/// The state components enabled in `XCR0`, which are saved and restored.
+static FEATURES: AtomicU64 = AtomicU64::new(0);
+
+/// Size of the XSAVE area in the standard format for [`FEATURES`], or `0` if
+/// XSAVE is not enabled.
+static STANDARD_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+fn save_mode() -> SaveMode {
+    match SAVE_MODE.load(Ordering::Relaxed) {
+        1 => SaveMode::Xsave,
+        2 => SaveMode::Xsaveopt,
+        3 => SaveMode::Xsaves,
+        _ => SaveMode::Fxsave,
+    }
+}
+
+/// Enables the XSAVE feature set on the current CPU if supported.
+///
+/// The x87, SSE, AVX and AVX-512 states supported by the CPU are enabled in
+/// `XCR0`, except that the AVX-512 states are not enabled if the size of the
+/// XSAVE area reported by CPUID leaf `0xD` exceeds the capacity of
+/// [`ExtendedState`].
+pub(super) fn init() {
+    const CPUID_1_ECX_XSAVE: u32 = 1 << 26;
+    const CPUID_D_1_EAX_XSAVEOPT: u32 = 1 << 0;
+    const CPUID_D_1_EAX_XSAVES: u32 = 1 << 3;
+    const AVX512: XCr0Flags = XCr0Flags::OPMASK
+        .union(XCr0Flags::ZMM_HI256)
+        .union(XCr0Flags::HI16_ZMM);
+
+    if cpuid_count(1, 0).ecx & CPUID_1_ECX_XSAVE == 0 {
+        return;
+    }
+    unsafe { Cr4::update(|cr4| cr4.insert(Cr4Flags::OSXSAVE)) };
+
+    let leaf = cpuid_count(0xd, 0);
+    let supported = XCr0Flags::from_bits_truncate(leaf.eax as u64 | (leaf.edx as u64) << 32);
+    let mut features = supported & (XCr0Flags::X87 | XCr0Flags::SSE | XCr0Flags::AVX | AVX512);
+    // AVX-512 states must be enabled together, and with AVX.
+    if !features.contains(AVX512 | XCr0Flags::AVX) {
+        features.remove(AVX512);
+    }
+    unsafe { XCr0::write(features) };
+
+    let xsave_1 = cpuid_count(0xd, 1).eax;
+    let mode = if xsave_1 & CPUID_D_1_EAX_XSAVES != 0 {
+        SaveMode::Xsaves
+    } else if xsave_1 & CPUID_D_1_EAX_XSAVEOPT != 0 {
+        SaveMode::Xsaveopt
+    } else {
+        SaveMode::Xsave
+    };
+    // Size of the XSAVE area for the states enabled in XCR0 (and IA32_XSS for
+    // the compacted format).
+    let size = |mode| {
+        let subleaf = if mode == SaveMode::Xsaves { 1 } else { 0 };
+        cpuid_count(0xd, subleaf).ebx as usize
+    };
+    if size(mode) > XSAVE_AREA_SIZE {
+        features.remove(AVX512);
+        unsafe { XCr0::write(features) };
+    }
+    if size(mode) > XSAVE_AREA_SIZE {
+        warn!(
+            "XSAVE area too large: {} bytes, fall back to FXSAVE",
+            size(mode)
+        );
+        return;
+    }
+
+    FEATURES.store(features.bits(), Ordering::Relaxed);
+    STANDARD_SIZE.store(size(SaveMode::Xsave), Ordering::Relaxed);
+    SAVE_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Returns the enabled state components and the size of the XSAVE area in the
+/// standard format, or [`None`] if XSAVE is not enabled.
+pub(super) fn standard_layout() -> Option<(u64, usize)> {
+    match save_mode() {
+        SaveMode::Fxsave => None,
+        _ => Some((
+            FEATURES.load(Ordering::Relaxed),
+            STANDARD_SIZE.load(Ordering::Relaxed),
+        )),
+    }
+}
+
+/// Saves the current extended states to `state`.
+///
+/// `XSAVEOPT` skips the states not modified since the last `XRSTOR` from the
+/// same area, so this is only used for areas that are paired with their own
+/// restore, e.g., the extended states of a task.
+pub(super) fn save(state: &mut ExtendedState) {
+    let area = state as *mut ExtendedState as *mut u8;
+    let features = FEATURES.load(Ordering::Relaxed);
+    unsafe {
+        match save_mode() {
+            SaveMode::Fxsave => _fxsave64(area),
+            SaveMode::Xsave => _xsave64(area, features),
+            SaveMode::Xsaveopt => _xsaveopt64(area, features),
+            SaveMode::Xsaves => _xsaves64(area, features),
+        }
+    }
+}
+
+/// Saves all the current extended states to `state`, without the optimization
+/// of `XSAVEOPT`.
+///
+/// It is used for temporary areas, which may be at the same address as an area
+/// restored before but no longer hold the states it had.
+pub(super) fn save_full(state: &mut ExtendedState) {
+    let area = state as *mut ExtendedState as *mut u8;
+    let features = FEATURES.load(Ordering::Relaxed);
+    unsafe {
+        match save_mode() {
+            SaveMode::Fxsave => _fxsave64(area),
+            SaveMode::Xsave | SaveMode::Xsaveopt => _xsave64(area, features),
+            SaveMode::Xsaves => _xsaves64(area, features),
+        }
+    }
+}
+
+/// Saves all the current extended states to `state` in the standard format,
+/// which is the layout exposed to user space, e.g., in signal frames.
+pub(super) fn save_standard(state: &mut ExtendedState) {
+    let area = state as *mut ExtendedState as *mut u8;
+    let features = FEATURES.load(Ordering::Relaxed);
+    unsafe {
+        match save_mode() {
+            SaveMode::Fxsave => _fxsave64(area),
+            _ => _xsave64(area, features),
+        }
+    }
+}
+
+/// Restores the extended states from `state` in the standard format.
+pub(super) fn restore_standard(state: &ExtendedState) {
+    let area = state as *const ExtendedState as *const u8;
+    let features = FEATURES.load(Ordering::Relaxed);
+    unsafe {
+        match save_mode() {
+            SaveMode::Fxsave => _fxrstor64(area),
+            _ => _xrstor64(area, features),
+        }
+    }
+}
+
+/// Restores the extended states from `state`.
+pub(super) fn restore(state: &ExtendedState) {
+    let area = state as *const ExtendedState as *const u8;
+    let features = FEATURES.load(Ordering::Relaxed);
+    unsafe {
+        match save_mode() {
+            SaveMode::Fxsave => _fxrstor64(area),
+            // Areas not saved by `XSAVES`, e.g., the initial one, are in the
+            // standard format.
+            SaveMode::Xsaves if state.xsave_header.xcomp_bv & XCOMP_BV_COMPACTED != 0 => {
+                _xrstors64(area, features)
+            }
+            _ => _xrstor64(area, features),
+        }
+    }
+}